@@ -1,15 +1,41 @@
+#[cfg(feature = "vlc")]
 extern crate vlc;
-use crate::defs::{AudioBackendState, AudioRequest, AudioState, DBData, DBRequest, RepeatMode};
+mod engine;
+mod queue;
+#[cfg(feature = "symphonia-backend")]
+mod symphonia_engine;
+#[cfg(feature = "vlc")]
+mod vlc_engine;
+
+#[cfg(not(any(feature = "vlc", feature = "symphonia-backend")))]
+compile_error!(
+    "At least one playback backend is required, enable the vlc or symphonia-backend feature"
+);
+
+use crate::defs::{
+    AudioBackendState, AudioRequest, AudioState, ConfigState, DBData, DBRequest, Gapless, Output,
+    PlaySource, PlaybackBackend, PlayedTrack, ReplayGain, ReplayGainMode,
+};
+use engine::{EngineEvent, EngineState, PlaybackEngine};
 use log::{debug, error};
+use queue::{PlayQueue, QueueTrack};
 use rand::rngs::ThreadRng;
-use rand::Rng;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::PathBuf;
+#[cfg(feature = "vlc")]
+use std::rc::Rc;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "symphonia-backend")]
+use symphonia_engine::SymphoniaEngine;
 use tauri::{AppHandle, Emitter, Manager};
-use vlc::{Event, EventType, Instance, Media, MediaPlayer, MediaPlayerAudioEx, Meta};
+#[cfg(feature = "vlc")]
+use vlc::{Instance, Media, Meta};
+#[cfg(feature = "vlc")]
+use vlc_engine::VlcEngine;
 
 fn format_time(milis: i64) -> String {
     let secs = milis / 1000; // convert miliseconds to seconds
@@ -19,6 +45,7 @@ fn format_time(milis: i64) -> String {
     return format!("{}:{:02}", min, sec);
 }
 
+#[cfg(feature = "vlc")]
 fn get_metadata(media: Media) -> String {
     let metadata = format!(
         "Artist: {}\nTitle: {}\nAlbum: {}\nGenre: {}\nTrackNumber: {}\nTrackTotal: {}\nTrackID: {}\nArtworkURL: {}\nURL: {}\nDescription: {}\nPublisher: {}\nDate: {}",
@@ -59,6 +86,160 @@ fn get_metadata(media: Media) -> String {
     return metadata;
 }
 
+// Interval of position updates to the frontend
+static POSITION_UPDATE_MS: u64 = 250;
+// Time a seek is reported until the player catches up, get_time lags behind set_time
+static SEEK_SETTLE_MS: u64 = 1000;
+// Interval of volume changes while crossfading
+static CROSSFADE_STEP_MS: u64 = 50;
+// Interval in which the playback position is stored while playing
+static CHECKPOINT_INTERVAL_MS: u64 = 5000;
+// Next before this share of the track in percent was listened to counts as a skip
+static SKIP_THRESHOLD_PERCENT: i64 = 50;
+
+/*
+Playback position in ms of the current track.
+Until the media is started, the player has no time and the position it will start at is used.
+ */
+fn get_position<E: PlaybackEngine>(
+    player: &E,
+    pending_position: &Option<(i64, i64)>,
+    current_id: i64,
+) -> i64 {
+    match player.state() {
+        EngineState::Playing | EngineState::Paused | EngineState::Buffering => {
+            return player.get_time().unwrap_or_default();
+        }
+        _ => match pending_position {
+            Some((id, position)) if *id == current_id => return *position,
+            _ => return player.get_time().unwrap_or_default().max(0),
+        },
+    }
+}
+
+/*
+Player volume for a track with ReplayGain applied.
+libvlc volume is linear with 100 being 0 dB, the gain is limited by the peak to prevent clipping.
+ */
+fn get_gain_volume(volume: i32, opt_replaygain: Option<&ReplayGain>, mode: &ReplayGainMode) -> i32 {
+    let replaygain = match opt_replaygain {
+        Some(replaygain) => replaygain,
+        None => return volume,
+    };
+
+    let (opt_gain, opt_peak) = match mode {
+        ReplayGainMode::Off => return volume,
+        ReplayGainMode::Track => (replaygain.track_gain, replaygain.track_peak),
+        ReplayGainMode::Album => {
+            if replaygain.album_gain.is_some() {
+                (replaygain.album_gain, replaygain.album_peak)
+            } else {
+                (replaygain.track_gain, replaygain.track_peak)
+            }
+        }
+    };
+
+    let mut factor = match opt_gain {
+        Some(gain) => 10f64.powf(gain / 20.0),
+        None => return volume,
+    };
+
+    if let Some(peak) = opt_peak {
+        if peak > 0.0 {
+            factor = factor.min(1.0 / peak);
+        }
+    }
+
+    return ((volume as f64 * factor).round() as i32).clamp(0, 200);
+}
+
+// Why the current track was replaced
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ListenEnd {
+    Completed, // played until the end
+    Next,      // skipped by the user
+    Replaced,  // any other track change, e.g. a jump in the queue
+}
+
+/*
+Listening session of the current track, reported to the play history once the track changes.
+Only time spent playing counts as listened, a track that never played is not reported.
+ */
+struct Listen {
+    track_id: i64,
+    source: Option<PlaySource>,
+    started_at: Option<i64>, // unix time in seconds of the first play
+    listened: Duration,
+    playing_since: Option<Instant>,
+    length: Option<i64>, // in ms, known once the media is parsed
+}
+
+impl Listen {
+    fn new(track: &QueueTrack) -> Listen {
+        return Listen {
+            track_id: track.id,
+            source: track.source.clone(),
+            started_at: None,
+            listened: Duration::ZERO,
+            playing_since: None,
+            length: None,
+        };
+    }
+
+    // Called with the state and length of the player after every message
+    fn update(&mut self, is_playing: bool, length: Option<i64>) {
+        if let Some(length) = length {
+            if length > 0 {
+                self.length = Some(length);
+            }
+        }
+
+        match (self.playing_since, is_playing) {
+            (None, true) => {
+                if self.started_at.is_none() {
+                    self.started_at = Some(
+                        SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs() as i64,
+                    );
+                }
+                self.playing_since = Some(Instant::now());
+            }
+            (Some(playing_since), false) => {
+                self.listened += playing_since.elapsed();
+                self.playing_since = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(mut self, end: ListenEnd) -> Option<PlayedTrack> {
+        self.update(false, None);
+
+        let duration_listened = self.listened.as_millis() as i64;
+        let skipped = end == ListenEnd::Next
+            && match self.length {
+                Some(length) => duration_listened * 100 < length * SKIP_THRESHOLD_PERCENT,
+                None => true,
+            };
+
+        match self.started_at {
+            Some(started_at) => {
+                return Some(PlayedTrack {
+                    track_id: self.track_id,
+                    started_at: started_at,
+                    duration_listened: duration_listened,
+                    completed: end == ListenEnd::Completed,
+                    skipped: skipped,
+                    source: self.source,
+                });
+            }
+            None => return None,
+        }
+    }
+}
+
 pub fn get_audio_backend_state(
     audio_backend_state_path: &PathBuf,
 ) -> Result<AudioBackendState, String> {
@@ -145,704 +326,1122 @@ pub fn set_audio_backend_state(
     }
 }
 
+// The configured backend if it is part of the build, otherwise the one that is
+fn get_playback_backend(configured: &PlaybackBackend) -> PlaybackBackend {
+    match configured {
+        PlaybackBackend::Vlc if cfg!(feature = "vlc") => return PlaybackBackend::Vlc,
+        PlaybackBackend::Symphonia if cfg!(feature = "symphonia-backend") => {
+            return PlaybackBackend::Symphonia;
+        }
+        _ => {}
+    }
+
+    error!("Playback backend {:?} is not available", configured);
+    if cfg!(feature = "vlc") {
+        return PlaybackBackend::Vlc;
+    }
+    return PlaybackBackend::Symphonia;
+}
+
+// Everything the player thread reacts to, received through a single channel
+enum PlayerMessage {
+    Request(AudioRequest),
+    Event(usize, EngineEvent), // slot of the player and its event
+    Data(DBData),
+    Tick, // nothing was received until the next scheduled update
+}
+
 pub fn create_player_instance(
     app: AppHandle,
     audio_receiver: Receiver<AudioRequest>,
     db_data_receiver: Receiver<DBData>,
     db_sender_audio: Sender<DBRequest>,
+    config_state: ConfigState,
 ) {
-    /*
-    TODO:
-    Detect when anmutunes gets terminated.
-    In a graceful shutdown, store play position of track.
-     */
-    thread::spawn(move || loop {
-        // VLC thread
-        /*
-         * Init vlc and loop to receive input and event
-         */
+    let (message_sender, message_receiver) = mpsc::channel::<PlayerMessage>();
+
+    // Forward requests and data into the player channel, so no receiver delays another one
+    let request_sender = message_sender.clone();
+    thread::spawn(move || {
+        for request in audio_receiver {
+            if request_sender
+                .send(PlayerMessage::Request(request))
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
 
-        #[cfg(target_os = "macos")]
-        {
-            match std::env::current_exe() {
-                Ok(mut dir) => {
-                    // Remove executable and MacOS dir
-                    dir.pop();
-                    dir.pop();
-
-                    // On macOS set_var seems to be unsafe
-                    unsafe {
-                        std::env::set_var(
-                            "VLC_PLUGIN_PATH",
-                            dir.join("Resources")
-                                .join("vlc")
-                                .join("macos")
-                                .join("plugins")
-                                .display()
-                                .to_string(),
-                        )
-                    };
-                    /*set_var(
-                        "VLC_PLUGIN_PATH",
-                        "/Applications/anmutunes.app/Contents/Resources/vlc/plugins",
-                    );*/
-                }
-                Err(_error) => {
-                    // TODO: send to frontend
-                }
+    let data_sender = message_sender.clone();
+    thread::spawn(move || {
+        for data in db_data_receiver {
+            if data_sender.send(PlayerMessage::Data(data)).is_err() {
+                break;
             }
         }
+    });
+
+    thread::spawn(
+        move || match get_playback_backend(&config_state.playback_backend) {
+            #[cfg(feature = "vlc")]
+            PlaybackBackend::Vlc => {
+                // VLC thread
+                #[cfg(target_os = "macos")]
+                {
+                    match std::env::current_exe() {
+                        Ok(mut dir) => {
+                            // Remove executable and MacOS dir
+                            dir.pop();
+                            dir.pop();
+
+                            // On macOS set_var seems to be unsafe
+                            unsafe {
+                                std::env::set_var(
+                                    "VLC_PLUGIN_PATH",
+                                    dir.join("Resources")
+                                        .join("vlc")
+                                        .join("macos")
+                                        .join("plugins")
+                                        .display()
+                                        .to_string(),
+                                )
+                            };
+                            /*set_var(
+                                "VLC_PLUGIN_PATH",
+                                "/Applications/anmutunes.app/Contents/Resources/vlc/plugins",
+                            );*/
+                        }
+                        Err(_error) => {
+                            // TODO: send to frontend
+                        }
+                    }
+                }
 
-        // Create an instance of VLC
-        let instance = Instance::new().unwrap();
+                // Create an instance of VLC
+                let instance = Rc::new(Instance::new().unwrap());
+
+                run_player(
+                    app,
+                    message_sender,
+                    message_receiver,
+                    db_sender_audio,
+                    config_state,
+                    VlcEngine::new(&instance),
+                    VlcEngine::new(&instance),
+                );
+            }
+            #[cfg(feature = "symphonia-backend")]
+            PlaybackBackend::Symphonia => {
+                run_player(
+                    app,
+                    message_sender,
+                    message_receiver,
+                    db_sender_audio,
+                    config_state,
+                    SymphoniaEngine::new(),
+                    SymphoniaEngine::new(),
+                );
+            }
+            #[allow(unreachable_patterns)]
+            _ => {}
+        },
+    );
+}
 
-        // Vector of track IDs
-        let mut queue_ids: Vec<i64> = vec![];
-        let mut history_ids: Vec<i64> = vec![];
+/*
+Init the player and loop to receive input and events.
+mediaplayer plays the current track, next_player is the second player used for gapless playback and crossfades.
+ */
+fn run_player<E: PlaybackEngine>(
+    app: AppHandle,
+    message_sender: Sender<PlayerMessage>,
+    message_receiver: Receiver<PlayerMessage>,
+    db_sender_audio: Sender<DBRequest>,
+    config_state: ConfigState,
+    mut mediaplayer: E,
+    mut next_player: E,
+) {
+    // Queue, history and current track with shuffle and repeat
+    let mut play_queue = PlayQueue::new();
+    let mut rng: ThreadRng = rand::rng();
+    // Listen of the current track for the play history
+    let mut listen: Option<Listen> = None;
 
-        // Vector containing the paths or URLs to your media files
-        let mut queue: Vec<String> = vec![];
-        let mut history: Vec<String> = vec![];
+    let mut volume: i32 = 100;
 
-        let mut current_id: i64 = 0;
-        let mut current_location: String = "".to_string();
+    let mut crossfade_ms: i64 = config_state.crossfade_ms;
+    // Set while the previous track fades out on next_player
+    let mut crossfade_start: Option<Instant> = None;
 
-        let mut shuffle: bool = false;
-        let mut rng: ThreadRng = rand::rng();
+    // Album of each track in queue and history, crossfade is skipped within an album
+    let mut album_ids: HashMap<i64, i64> = HashMap::new();
 
-        let mut repeat: RepeatMode = RepeatMode::RepeatNone;
+    let mut replaygain_mode: ReplayGainMode = config_state.replaygain_mode;
+    let mut replaygains: HashMap<i64, ReplayGain> = HashMap::new();
+    // Encoder delay and padding of each track, libvlc plays them as silence
+    let mut gapless_infos: HashMap<i64, Gapless> = HashMap::new();
 
-        let mut volume: i32 = 100;
+    let _ = mediaplayer.set_volume(volume);
 
-        // Create a media player
-        let mediaplayer = MediaPlayer::new(&instance).unwrap();
+    // next_player holds the preloaded next track and swaps with mediaplayer on track change
+    let mut next_id: i64 = 0;
+    let mut next_index: Option<usize> = None;
 
-        let _ = mediaplayer.set_volume(volume);
+    // Events are tagged with the slot of the player, mediaplayer is always in player_slot
+    let mut player_slot: usize = 0;
 
+    for (slot, player) in [&mediaplayer, &next_player].iter().enumerate() {
         // Set up event listener for end of media
-        let events = mediaplayer.event_manager();
-
-        let (event_sender, event_receiver) = mpsc::channel::<Event>();
-        let sender_copy = event_sender.clone();
-        let _ = events.attach(EventType::MediaPlayerEndReached, move |e, _| {
-            let _ = sender_copy.send(e);
+        let sender_copy = message_sender.clone();
+        player.on_event(move |e| {
+            let _ = sender_copy.send(PlayerMessage::Event(slot, e));
         });
+    }
 
-        let sender_copy2 = event_sender.clone();
-        let _ = events.attach(EventType::MediaPlayerMediaChanged, move |e, _| {
-            let _ = sender_copy2.send(e);
-        });
+    // Position updates are sent on a steady cadence, independent of other messages
+    let mut next_position_update = Instant::now();
+    // Target of the last seek and when it was requested
+    let mut seek_position: Option<(i64, Instant)> = None;
+
+    let audio_backend_state_name = if tauri::is_dev() {
+        "debug.audio_backend_state.json"
+    } else {
+        "audio_backend_state.json"
+    };
+
+    let audio_backend_state_path = match app.path().app_local_data_dir() {
+        Ok(mut data_path) => {
+            data_path.push(audio_backend_state_name);
+            data_path
+        }
+        Err(error) => {
+            error!("{}", error);
+            PathBuf::new()
+        }
+    };
 
-        let audio_backend_state_name = if tauri::is_dev() {
-            "debug.audio_backend_state.json"
-        } else {
-            "audio_backend_state.json"
-        };
+    // Track id and position in ms that media was created at, reported until it starts playing
+    let mut pending_position: Option<(i64, i64)> = None;
+    let mut last_checkpoint = Instant::now();
+
+    // Outputs as sent to the frontend, the id of an output is its index
+    let mut outputs: Vec<Output> = mediaplayer.get_outputs();
+    let mut output: Option<Output> = None;
 
-        let audio_backend_state_path = match app.path().app_local_data_dir() {
-            Ok(mut data_path) => {
-                data_path.push(audio_backend_state_name);
-                data_path
+    if audio_backend_state_path.ends_with(audio_backend_state_name) {
+        match get_audio_backend_state(&audio_backend_state_path) {
+            Ok(audio_backend_state) => {
+                play_queue.shuffle = audio_backend_state.shuffle_mode;
+                play_queue.unshuffled = audio_backend_state.unshuffled_queue;
+                play_queue.repeat = audio_backend_state.repeat_mode;
+                // TODO: this data is not trustworthy and should not be used. Instead get location from index.
+                let current_location = audio_backend_state.current_location;
+                if audio_backend_state.current_id != 0 && current_location != "" {
+                    mediaplayer.set_media(&current_location, None, audio_backend_state.position);
+                    pending_position =
+                        Some((audio_backend_state.current_id, audio_backend_state.position));
+                    play_queue.current = Some(QueueTrack {
+                        id: audio_backend_state.current_id,
+                        location: current_location,
+                        album_id: 0,
+                        plays: 0,
+                        rating: 0,
+                        source: None,
+                    });
+                }
+                volume = audio_backend_state.volume;
+                let _ = mediaplayer.set_volume(volume);
+                // The saved output is only used if the device is still available
+                if let Some(saved_output) = audio_backend_state.output {
+                    match outputs.iter().find(|available| {
+                        available.module == saved_output.module
+                            && available.device == saved_output.device
+                    }) {
+                        Some(available) => {
+                            mediaplayer.set_output(available);
+                            next_player.set_output(available);
+                            output = Some(available.clone());
+                        }
+                        None => {
+                            error!("Output {} is not available", saved_output.name);
+                        }
+                    }
+                }
+                let _ = db_sender_audio
+                    .send(DBRequest::AudioBackendRecover(
+                        audio_backend_state.history,
+                        audio_backend_state.queue,
+                    ))
+                    .unwrap();
             }
             Err(error) => {
                 error!("{}", error);
-                PathBuf::new()
             }
+        }
+    }
+
+    // Event and input handling loop
+    loop {
+        let remaining_time = if mediaplayer.is_playing() {
+            match (mediaplayer.get_length(), mediaplayer.get_time()) {
+                (Some(length), Some(time)) => Some(length - time),
+                _ => None,
+            }
+        } else {
+            None
         };
 
-        let mut old_position = 0;
-
-        if audio_backend_state_path.ends_with(audio_backend_state_name) {
-            match get_audio_backend_state(&audio_backend_state_path) {
-                Ok(audio_backend_state) => {
-                    shuffle = audio_backend_state.shuffle_mode;
-                    repeat = audio_backend_state.repeat_mode;
-                    current_id = audio_backend_state.current_id;
-                    // TODO: this data is not trustworthy and should not be used. Instead get location from index.
-                    current_location = audio_backend_state.current_location;
-                    if current_location != "" {
-                        mediaplayer.set_media(
-                            &Media::new_path(&instance, current_location.clone()).unwrap(),
-                        );
-                    }
-                    volume = audio_backend_state.volume;
-                    let _ = mediaplayer.set_volume(volume);
-                    old_position = audio_backend_state.position;
-                    let _ = db_sender_audio
-                        .send(DBRequest::AudioBackendRecover(
-                            audio_backend_state.history,
-                            audio_backend_state.queue,
-                        ))
-                        .unwrap();
-                }
-                Err(error) => {
-                    error!("{}", error);
-                }
+        let same_album = match (
+            album_ids.get(&play_queue.current_id()),
+            album_ids.get(&next_id),
+        ) {
+            (Some(current_album_id), Some(next_album_id)) => {
+                *current_album_id != 0 && current_album_id == next_album_id
             }
-        }
+            _ => false,
+        };
+
+        // Crossfade into the preloaded track, unless both are part of the same album
+        let crossfade_next =
+            crossfade_ms > 0 && next_id != 0 && crossfade_start.is_none() && !same_album;
 
-        // Event and input handling loop
-        loop {
-            // TODO: package all requests into single receiver to get rid of delays?
-            // Wait for mediaplayer event or user input
-            let audio_request = audio_receiver.recv_timeout(std::time::Duration::from_millis(100));
-            let vlc_event = event_receiver.recv_timeout(std::time::Duration::from_millis(100));
-            let db_data = db_data_receiver.recv_timeout(std::time::Duration::from_millis(100));
-
-            let mut audio_state = AudioState {
-                is_playing: None,
-                is_muted: None,
-                volume: None,
-                output: None,
-                position: None,
-                shuffle_mode: None,
-                repeat_mode: None,
-                current_track: None,
-                queue: None,
-                history: None,
+        // Close to the start of a crossfade and while fading, the volume is changed in small steps
+        let near_crossfade = crossfade_next
+            && match remaining_time {
+                Some(remaining_time) => remaining_time < crossfade_ms + POSITION_UPDATE_MS as i64,
+                None => false,
             };
 
-            let mut write_audio_backend_state = false;
+        let mut timeout = next_position_update.saturating_duration_since(Instant::now());
+        if crossfade_start.is_some() || near_crossfade {
+            timeout = timeout.min(Duration::from_millis(CROSSFADE_STEP_MS));
+        }
 
-            /*
-            TODO: create function for Play and Next that covers all playback options, including shuffle.
-             */
+        // Wait for mediaplayer event, user input or db data
+        let message = match message_receiver.recv_timeout(timeout) {
+            Ok(message) => message,
+            Err(_) => PlayerMessage::Tick,
+        };
 
-            match audio_request {
-                Ok(result) => match result {
-                    AudioRequest::PlayPause(is_playing) => {
-                        if current_id != 0 {
-                            println!("Playing: {}", current_id);
-                            if is_playing {
-                                println!("start playing");
-                                match mediaplayer.play() {
-                                    Ok(()) => {
-                                        // Workaround to recover old playback position
-                                        if old_position != 0 {
-                                            mediaplayer.set_time(old_position);
-                                            old_position = 0;
-                                        }
-
-                                        audio_state.is_playing = Some(true);
-                                        audio_state.current_track = Some(current_id);
-                                    }
-                                    Err(_error) => {}
-                                }
-                            } else {
-                                println!("stop playing");
-                                mediaplayer.pause();
-                                audio_state.is_playing = Some(false);
-                            }
+        let mut audio_request: Option<AudioRequest> = None;
+        let mut vlc_event: Option<(usize, EngineEvent)> = None;
+        let mut db_data: Option<DBData> = None;
+        let is_tick = match message {
+            PlayerMessage::Request(request) => {
+                audio_request = Some(request);
+                false
+            }
+            PlayerMessage::Event(slot, event) => {
+                vlc_event = Some((slot, event));
+                false
+            }
+            PlayerMessage::Data(data) => {
+                db_data = Some(data);
+                false
+            }
+            PlayerMessage::Tick => true,
+        };
 
-                            write_audio_backend_state = true;
-                        } else {
-                            println!("Nothing to play/pause");
-                            audio_state.is_playing = Some(false);
-                        }
-                    }
-                    AudioRequest::Next => {
-                        let is_playing = mediaplayer.is_playing();
-                        if queue.len() > 0 || repeat != RepeatMode::RepeatNone {
-                            /*
-                            TODO:
-                            unload prev_media
-                            move current_media into prev_media
-                            move next_media into current_media
-                            load new next_media
-                             */
-
-                            // TODO: needs proper testing, this has code smell...
-                            if current_id != 0 {
-                                history_ids.push(current_id);
-                                history.push(current_location.clone());
-                            }
+        // Anything but a tick may change the track, the seek target is set again below
+        if !is_tick {
+            seek_position = None;
+        }
 
-                            match repeat {
-                                RepeatMode::RepeatNone => {}
-                                RepeatMode::RepeatQueue => {
-                                    queue_ids.push(current_id);
-                                    queue.push(current_location);
-                                }
-                                RepeatMode::RepeatTrack => {
-                                    queue_ids.insert(0, current_id);
-                                    queue.insert(0, current_location);
-                                }
-                            }
+        // Changing the track ends a running crossfade, the previous track is not relevant anymore
+        let changes_track = match (&audio_request, &db_data) {
+            (
+                Some(
+                    AudioRequest::Next
+                    | AudioRequest::Prev
+                    | AudioRequest::QueueJump(_)
+                    | AudioRequest::HistoryJump(_),
+                ),
+                _,
+            ) => true,
+            // The fading track would keep playing while paused
+            (Some(AudioRequest::PlayPause(false)), _) => true,
+            (_, Some(DBData::Play(_, _))) => true,
+            _ => false,
+        };
+        if crossfade_start.is_some() && changes_track {
+            next_player.stop();
+            let _ = mediaplayer.set_volume(get_gain_volume(
+                volume,
+                replaygains.get(&play_queue.current_id()),
+                &replaygain_mode,
+            ));
+            crossfade_start = None;
+        }
 
-                            let mut shuffled_index: usize = 0;
+        // A crossfade starts like the end of the track, but the previous track keeps playing.
+        // Only into the preloaded track, loading the next track would cut the end of the current one.
+        let start_crossfade = crossfade_next
+            && is_tick
+            && play_queue.is_next(match next_index {
+                Some(index) => Some((index, next_id)),
+                None => None,
+            })
+            && match remaining_time {
+                Some(remaining_time) => remaining_time - (CROSSFADE_STEP_MS as i64) <= crossfade_ms,
+                None => false,
+            };
 
-                            if shuffle && repeat != RepeatMode::RepeatTrack {
-                                shuffled_index = rng.random_range(..queue.len());
-                            }
+        if start_crossfade {
+            vlc_event = Some((player_slot, EngineEvent::EndReached));
+        }
 
-                            current_id = queue_ids.remove(shuffled_index);
-                            current_location = queue.remove(shuffled_index);
+        let mut audio_state = AudioState {
+            is_playing: None,
+            is_muted: None,
+            volume: None,
+            output: None,
+            outputs: None,
+            position: None,
+            shuffle_mode: None,
+            repeat_mode: None,
+            current_track: None,
+            queue: None,
+            history: None,
+        };
 
-                            mediaplayer.set_media(
-                                &Media::new_path(&instance, current_location.clone()).unwrap(),
-                            );
+        let mut write_audio_backend_state = false;
+        // Set when the current track is replaced
+        let mut listen_end: Option<ListenEnd> = None;
+        // Notified once the state is written
+        let mut checkpoint_sender: Option<Sender<()>> = None;
 
-                            if is_playing {
-                                mediaplayer.play().unwrap();
-                            }
+        /*
+        TODO: create function for Play and Next that covers all playback options, including shuffle.
+         */
 
-                            audio_state.queue = Some(queue_ids.clone());
-                        } else {
-                            if current_id != 0 {
-                                history_ids.push(current_id);
-                                history.push(current_location);
+        match audio_request {
+            Some(result) => match result {
+                AudioRequest::PlayPause(is_playing) => {
+                    let current_id = play_queue.current_id();
+                    if current_id != 0 {
+                        println!("Playing: {}", current_id);
+                        if is_playing {
+                            println!("start playing");
+                            match mediaplayer.play() {
+                                Ok(()) => {
+                                    audio_state.is_playing = Some(true);
+                                    audio_state.current_track = Some(current_id);
+                                }
+                                Err(_error) => {}
                             }
-
-                            current_id = 0;
-                            current_location = "".to_string();
-
-                            mediaplayer.stop();
+                        } else {
+                            println!("stop playing");
+                            mediaplayer.pause();
+                            audio_state.is_playing = Some(false);
                         }
 
                         write_audio_backend_state = true;
-                        audio_state.current_track = Some(current_id);
-                        audio_state.history = Some(history_ids.clone());
+                    } else {
+                        println!("Nothing to play/pause");
+                        audio_state.is_playing = Some(false);
                     }
-                    AudioRequest::Prev => {
-                        if history.len() > 0 {
-                            /*
-                            TODO:
-                            unload next_media
-                            move current_media into next_media
-                            move prev_media into current_media
-                            load new prev_media
-                             */
-                            let is_playing = mediaplayer.is_playing();
-
-                            // TODO: needs proper testing, this has code smell...
-                            queue_ids.insert(0, current_id);
-                            queue.insert(0, current_location);
-
-                            current_id = history_ids.pop().unwrap();
-                            current_location = history.pop().unwrap();
-
-                            mediaplayer.set_media(
-                                &Media::new_path(&instance, current_location.clone()).unwrap(),
-                            );
-
-                            audio_state.current_track = Some(current_id);
-                            audio_state.queue = Some(queue_ids.clone());
-                            audio_state.history = Some(history_ids.clone());
+                }
+                AudioRequest::Next => {
+                    let is_playing = mediaplayer.is_playing();
+                    let preloaded = match next_index {
+                        Some(index) if next_id != 0 => Some((index, next_id)),
+                        _ => None,
+                    };
+
+                    let use_preloaded = play_queue.next(preloaded);
+                    listen_end = Some(ListenEnd::Next);
+
+                    match play_queue.current.clone() {
+                        Some(current) => {
+                            if use_preloaded {
+                                // Switch to the preloaded player, the old one becomes the next player
+                                let is_muted = mediaplayer.get_mute();
+                                std::mem::swap(&mut mediaplayer, &mut next_player);
+                                player_slot = 1 - player_slot;
+                                next_player.stop();
+                                let _ = mediaplayer.set_volume(get_gain_volume(
+                                    volume,
+                                    replaygains.get(&current.id),
+                                    &replaygain_mode,
+                                ));
+                                mediaplayer.set_mute(is_muted);
+                            } else {
+                                mediaplayer.set_media(
+                                    &current.location,
+                                    gapless_infos.get(&current.id),
+                                    0,
+                                );
+                            }
+                            next_id = 0;
+                            next_index = None;
 
                             if is_playing {
                                 mediaplayer.play().unwrap();
                             }
-                        } else {
+
+                            audio_state.queue = Some(play_queue.queue_ids());
+                        }
+                        None => {
                             mediaplayer.stop();
                         }
-
-                        write_audio_backend_state = true;
-                        // TODO: emit state for new active track? Requires ID
-                        //app.emit("audio_state", AudioState current track and maybe position(aka time?)).unwrap();
                     }
-                    AudioRequest::QueueJump(index) => {
-                        // TODO: What is proper behaviour? move all previous tracks into history?
-                        // Move current into history, discard all others before selected queue_id
-                        // Move selected queue_id into current.
-                        let id: usize = index.try_into().unwrap();
-                        if queue.len() > id {
-                            // TODO: needs proper testing, this has code smell...
-                            if current_id != 0 {
-                                history_ids.push(current_id);
-                                history.push(current_location);
-                            }
 
-                            current_id = queue_ids[id];
-                            current_location = queue[id].clone();
+                    write_audio_backend_state = true;
+                    audio_state.current_track = Some(play_queue.current_id());
+                    audio_state.history = Some(play_queue.history_ids());
+                }
+                AudioRequest::Prev => {
+                    /*
+                    TODO:
+                    unload next_media
+                    move current_media into next_media
+                    move prev_media into current_media
+                    load new prev_media
+                     */
+                    let is_playing = mediaplayer.is_playing();
+
+                    if play_queue.prev() {
+                        mediaplayer.set_media(
+                            &play_queue.current_location(),
+                            gapless_infos.get(&play_queue.current_id()),
+                            0,
+                        );
+                        listen_end = Some(ListenEnd::Replaced);
 
-                            //  remove entries from queue up to and including id
-                            queue_ids.drain(0..=id);
-                            queue.drain(0..=id);
+                        audio_state.current_track = Some(play_queue.current_id());
+                        audio_state.queue = Some(play_queue.queue_ids());
+                        audio_state.history = Some(play_queue.history_ids());
 
-                            mediaplayer.set_media(
-                                &Media::new_path(&instance, current_location.clone()).unwrap(),
-                            );
+                        if is_playing {
                             mediaplayer.play().unwrap();
-
-                            audio_state.current_track = Some(current_id);
-                            audio_state.queue = Some(queue_ids.clone());
-                            audio_state.history = Some(history_ids.clone());
-                            audio_state.position = Some(mediaplayer.get_time().unwrap_or_default());
-                        } else {
-                            mediaplayer.stop();
                         }
-
-                        write_audio_backend_state = true;
-                        audio_state.is_playing = Some(mediaplayer.is_playing());
+                    } else {
+                        mediaplayer.stop();
                     }
-                    AudioRequest::QueueMove(vec_ids) => {
-                        // vec_ids contains the track_ids in the new order
-                        let mut new_queue = vec![];
-                        let mut new_queue_ids = vec![];
-                        for id in vec_ids {
-                            let index = queue_ids.iter().position(|&i| i == id).unwrap();
-                            new_queue.push(queue[index].clone());
-                            new_queue_ids.push(queue_ids[index]);
-                        }
 
-                        queue = new_queue;
-                        queue_ids = new_queue_ids;
+                    write_audio_backend_state = true;
+                }
+                AudioRequest::QueueJump(index) => {
+                    // TODO: What is proper behaviour? move all previous tracks into history?
+                    // Move current into history, discard all others before selected queue_id
+                    // Move selected queue_id into current.
+                    if index >= 0 && play_queue.queue_jump(index as usize) {
+                        mediaplayer.set_media(
+                            &play_queue.current_location(),
+                            gapless_infos.get(&play_queue.current_id()),
+                            0,
+                        );
+                        mediaplayer.play().unwrap();
+                        listen_end = Some(ListenEnd::Replaced);
 
-                        write_audio_backend_state = true;
-                        audio_state.queue = Some(queue_ids.clone());
+                        audio_state.current_track = Some(play_queue.current_id());
+                        audio_state.queue = Some(play_queue.queue_ids());
+                        audio_state.history = Some(play_queue.history_ids());
+                        audio_state.position = Some(mediaplayer.get_time().unwrap_or_default());
+                    } else {
+                        mediaplayer.stop();
                     }
-                    AudioRequest::QueueRemove(indices) => {
-                        // sort indices then remove in descending order
-                        let mut mut_indices = indices.clone();
-                        mut_indices.sort();
-                        for index in mut_indices.iter().rev() {
-                            let index: usize = index.to_owned().try_into().unwrap();
-                            if index < queue_ids.len() {
-                                queue_ids.remove(index);
-                                queue.remove(index);
-                            }
 
-                            audio_state.queue = Some(queue_ids.clone());
-                        }
+                    write_audio_backend_state = true;
+                    audio_state.is_playing = Some(mediaplayer.is_playing());
+                }
+                AudioRequest::QueueMove(vec_ids) => {
+                    // vec_ids contains the track_ids in the new order
+                    play_queue.queue_move(&vec_ids);
 
-                        write_audio_backend_state = true;
-                    }
-                    AudioRequest::HistoryJump(history_id) => {
-                        // TODO: What is proper behaviour?
-                        // Move current into history.
-                        // Move selected history_id into current.
-                        let id: usize = history_id.try_into().unwrap();
-                        if history.len() > id {
-                            // TODO: needs proper testing, this has code smell...
-                            if current_id != 0 {
-                                history_ids.push(current_id);
-                                history.push(current_location);
-                            }
+                    write_audio_backend_state = true;
+                    audio_state.queue = Some(play_queue.queue_ids());
+                }
+                AudioRequest::QueueRemove(indices) => {
+                    let indices: Vec<usize> = indices
+                        .iter()
+                        .filter(|index| **index >= 0)
+                        .map(|index| *index as usize)
+                        .collect();
+                    play_queue.queue_remove(&indices);
+
+                    write_audio_backend_state = true;
+                    audio_state.queue = Some(play_queue.queue_ids());
+                }
+                AudioRequest::HistoryJump(history_id) => {
+                    // TODO: What is proper behaviour?
+                    // Move current into history.
+                    // Move selected history_id into current.
+                    if history_id >= 0 && play_queue.history_jump(history_id as usize) {
+                        mediaplayer.set_media(
+                            &play_queue.current_location(),
+                            gapless_infos.get(&play_queue.current_id()),
+                            0,
+                        );
+                        mediaplayer.play().unwrap();
+                        listen_end = Some(ListenEnd::Replaced);
 
-                            current_id = history_ids[id];
-                            current_location = history[id].clone();
+                        audio_state.current_track = Some(play_queue.current_id());
+                        audio_state.queue = Some(play_queue.queue_ids());
+                        audio_state.history = Some(play_queue.history_ids());
+                        audio_state.position = Some(mediaplayer.get_time().unwrap_or_default());
+                    } else {
+                        mediaplayer.stop();
+                    }
 
-                            mediaplayer.set_media(
-                                &Media::new_path(&instance, current_location.clone()).unwrap(),
-                            );
-                            mediaplayer.play().unwrap();
+                    write_audio_backend_state = true;
+                    audio_state.is_playing = Some(mediaplayer.is_playing());
+                }
+                AudioRequest::HistoryRemove => {
+                    play_queue.history_clear();
 
-                            audio_state.current_track = Some(current_id);
-                            audio_state.queue = Some(queue_ids.clone());
-                            audio_state.history = Some(history_ids.clone());
-                            audio_state.position = Some(mediaplayer.get_time().unwrap_or_default());
-                        } else {
-                            mediaplayer.stop();
+                    write_audio_backend_state = true;
+                    audio_state.history = Some(play_queue.history_ids());
+                }
+                AudioRequest::Mute(new_mute) => {
+                    mediaplayer.set_mute(new_mute);
+                    // The previous track may still be fading out
+                    next_player.set_mute(new_mute);
+                    audio_state.is_muted = Some(new_mute);
+                }
+                AudioRequest::Volume(new_volume) => {
+                    match mediaplayer.set_volume(get_gain_volume(
+                        new_volume,
+                        replaygains.get(&play_queue.current_id()),
+                        &replaygain_mode,
+                    )) {
+                        Ok(()) => {
+                            volume = new_volume;
+                            audio_state.volume = Some(volume);
                         }
-
-                        write_audio_backend_state = true;
-                        audio_state.is_playing = Some(mediaplayer.is_playing());
+                        Err(_error) => {}
                     }
-                    AudioRequest::HistoryRemove => {
-                        history_ids = vec![];
+                    println!("{}", volume);
+                    write_audio_backend_state = true;
+                }
+                AudioRequest::Output(id) => {
+                    match outputs
+                        .iter()
+                        .find(|available| available.id == id as i64)
+                        .cloned()
+                    {
+                        Some(new_output) => {
+                            let is_same_module = match &output {
+                                Some(current_output) => current_output.module == new_output.module,
+                                None => false,
+                            };
+
+                            if is_same_module && new_output.device != "" {
+                                mediaplayer.set_output_device(&new_output);
+                                next_player.set_output(&new_output);
+                            } else {
+                                mediaplayer.set_output(&new_output);
+                                next_player.set_output(&new_output);
+
+                                /*
+                                A new module requires to restart playback.
+                                The media is loaded again at the current position, setting the time fails until it plays.
+                                 */
+                                if let Some(current) = &play_queue.current {
+                                    let position =
+                                        get_position(&mediaplayer, &pending_position, current.id);
+                                    let is_playing = mediaplayer.is_playing();
+                                    mediaplayer.set_media(
+                                        &current.location,
+                                        gapless_infos.get(&current.id),
+                                        position,
+                                    );
+                                    pending_position = Some((current.id, position));
+                                    if is_playing {
+                                        let _ = mediaplayer.play();
+                                    }
+                                }
+                            }
 
-                        write_audio_backend_state = true;
-                        audio_state.history = Some(history_ids.clone());
-                    }
-                    AudioRequest::Mute(new_mute) => {
-                        mediaplayer.set_mute(new_mute);
-                        audio_state.is_muted = Some(new_mute);
+                            output = Some(new_output);
+                            audio_state.output = output.clone();
+                            write_audio_backend_state = true;
+                        }
+                        None => {
+                            error!("Output {} does not exist", id);
+                        }
                     }
-                    AudioRequest::Volume(new_volume) => {
-                        match mediaplayer.set_volume(new_volume) {
-                            Ok(()) => {
-                                volume = new_volume;
-                                audio_state.volume = Some(volume);
+                }
+                AudioRequest::Seek(time) => {
+                    match mediaplayer.state() {
+                        EngineState::Playing | EngineState::Paused | EngineState::Buffering => {
+                            mediaplayer.set_time(time);
+                            seek_position = Some((time, Instant::now()));
+                        }
+                        _ => {
+                            // Not started yet, start the media at the new position instead
+                            if let Some(current) = &play_queue.current {
+                                mediaplayer.set_media(
+                                    &current.location,
+                                    gapless_infos.get(&current.id),
+                                    time,
+                                );
+                                pending_position = Some((current.id, time));
                             }
-                            Err(_error) => {}
                         }
-                        println!("{}", volume);
-                        write_audio_backend_state = true;
-                    }
-                    AudioRequest::Output(_index) => {
-                        // TODO: set output
                     }
-                    AudioRequest::Seek(time) => {
-                        mediaplayer.set_time(time);
 
-                        // Workaround since set time on not yet played media fails
-                        if old_position != 0 {
-                            old_position = time;
-                        }
-
-                        if let Some(time) = mediaplayer.get_time() {
-                            audio_state.position = Some(time);
+                    write_audio_backend_state = true;
+                    audio_state.position = Some(time);
+                }
+                AudioRequest::Shuffle(new_shuffle) => {
+                    write_audio_backend_state = true;
+                    play_queue.set_shuffle(new_shuffle, &mut rng);
+                    audio_state.queue = Some(play_queue.queue_ids());
+                }
+                AudioRequest::Repeat(new_repeat) => {
+                    write_audio_backend_state = true;
+                    play_queue.repeat = new_repeat;
+                }
+                AudioRequest::Crossfade(new_crossfade_ms) => {
+                    crossfade_ms = new_crossfade_ms.max(0);
+                }
+                AudioRequest::Checkpoint(sender) => {
+                    write_audio_backend_state = true;
+                    checkpoint_sender = Some(sender);
+                }
+                AudioRequest::ReplayGain(new_replaygain_mode) => {
+                    // The volume of the current track is updated below
+                    replaygain_mode = new_replaygain_mode;
+                }
+                AudioRequest::Init => {
+                    // TODO: emit current audio state to frontend.
+                    // Also we should send queue_ids so the frontend can retrieve track data
+                    audio_state.is_playing = Some(mediaplayer.is_playing());
+                    audio_state.is_muted = Some(mediaplayer.get_mute());
+                    audio_state.volume = Some(volume);
+                    audio_state.position = Some(mediaplayer.get_time().unwrap_or_default());
+                    audio_state.current_track = Some(play_queue.current_id());
+
+                    // Devices could have been added or removed since
+                    outputs = mediaplayer.get_outputs();
+                    if let Some(current_output) = output.as_mut() {
+                        if let Some(available) = outputs.iter().find(|available| {
+                            available.module == current_output.module
+                                && available.device == current_output.device
+                        }) {
+                            current_output.id = available.id;
+                        } else {
+                            current_output.id = -1;
                         }
                     }
-                    AudioRequest::Shuffle(new_shuffle) => {
-                        write_audio_backend_state = true;
-                        shuffle = new_shuffle;
-                    }
-                    AudioRequest::Repeat(new_repeat) => {
-                        write_audio_backend_state = true;
-                        repeat = new_repeat;
-                    }
-                    AudioRequest::Init => {
-                        // TODO: emit current audio state to frontend.
-                        // Also we should send queue_ids so the frontend can retrieve track data
-                        audio_state.is_playing = Some(mediaplayer.is_playing());
-                        audio_state.is_muted = Some(mediaplayer.get_mute().unwrap_or_default());
-                        audio_state.volume = Some(volume);
-                        audio_state.position = Some(mediaplayer.get_time().unwrap_or_default());
-                        audio_state.current_track = Some(current_id);
-                        audio_state.queue = Some(queue_ids.clone());
-                        audio_state.history = Some(history_ids.clone());
-                    }
-                },
-                Err(_) => {
-                    //eprintln!("Error: {}", e);
+                    audio_state.output = output.clone();
+                    audio_state.outputs = Some(outputs.clone());
+                    audio_state.queue = Some(play_queue.queue_ids());
+                    audio_state.history = Some(play_queue.history_ids());
                 }
-            }
-
-            /*
-            AudioRequest::Play(datatype, vec_id, opt_index) => {
-                        /* TODO:
-                        This frontendevent should instead go to the db, which should then send an event to audio.
-
-                            based on datatype query the db for all entries in vec_id.
-                            For tracks, get the paths for all tracks in vec_id.
-                            For albums, get all tracks per album and insert them into the queue
-                            For playlists, get all tracks per playlist and insert them into the queue
-                        */
-
-                        /*current_index = 0;
-
-                        queue = vec![];
-                        // For each entry in the paths vector we need to do this:
-                        for path in paths.iter() {
-                            queue.append(&mut vec![Media::new_path(&instance, path).unwrap()]);
-                        }
+            },
+            None => {}
+        }
 
-                        mediaplayer.set_media(&queue[current_index]);
-                        mediaplayer.play();*/
+        /*
+        AudioRequest::Play(datatype, vec_id, opt_index) => {
+                    /* TODO:
+                    This frontendevent should instead go to the db, which should then send an event to audio.
+
+                        based on datatype query the db for all entries in vec_id.
+                        For tracks, get the paths for all tracks in vec_id.
+                        For albums, get all tracks per album and insert them into the queue
+                        For playlists, get all tracks per playlist and insert them into the queue
+                    */
+
+                    /*current_index = 0;
+
+                    queue = vec![];
+                    // For each entry in the paths vector we need to do this:
+                    for path in paths.iter() {
+                        queue.append(&mut vec![Media::new_path(&instance, path).unwrap()]);
                     }
-             */
 
-            /*AudioRequest::INFO => {
-                if current_index == queue.len() {
-                    println!(
-                        "Currently playing:\nNone\nPlaylist index: {}\n{:?}",
-                        current_index, queue_paths
-                    );
-                } else {
-                    let current_time =
-                        format_time(mediaplayer.get_time().unwrap_or_else(|| 0));
-                    let mut duration = format_time(0);
+                    mediaplayer.set_media(&queue[current_index]);
+                    mediaplayer.play();*/
+                }
+         */
 
-                    if let Some(media) = mediaplayer.get_media() {
-                        duration = format_time(media.duration().unwrap_or_else(|| 0));
-                    }
+        /*AudioRequest::INFO => {
+            if current_index == queue.len() {
+                println!(
+                    "Currently playing:\nNone\nPlaylist index: {}\n{:?}",
+                    current_index, queue_paths
+                );
+            } else {
+                let current_time =
+                    format_time(mediaplayer.get_time().unwrap_or_else(|| 0));
+                let mut duration = format_time(0);
 
-                    let mut metadata = "".to_string();
-                    if let Some(media) = mediaplayer.get_media() {
-                        metadata = get_metadata(media);
-                    }
+                if let Some(media) = mediaplayer.get_media() {
+                    duration = format_time(media.duration().unwrap_or_else(|| 0));
+                }
 
-                    println!(
-                        "Currently playing:\n{}\n{} - {}\n{}\nPlaylist index: {}\n{:?}",
-                        queue_paths[current_index],
-                        current_time,
-                        duration,
-                        metadata,
-                        current_index,
-                        queue_paths
-                    );
+                let mut metadata = "".to_string();
+                if let Some(media) = mediaplayer.get_media() {
+                    metadata = get_metadata(media);
                 }
-            }*/
-
-            match vlc_event {
-                Ok(result) => match result {
-                    Event::MediaPlayerEndReached => {
-                        println!("NEXT");
-                        // TOOD: Should we only increment if the user did not seek in the track?
-                        let _ = db_sender_audio
-                            .send(DBRequest::ListenedToTrack(current_id))
-                            .unwrap();
-
-                        if queue.len() > 0 || repeat != RepeatMode::RepeatNone {
-                            // TODO: needs proper testing, this has code smell...
-                            if current_id != 0 {
-                                history_ids.push(current_id);
-                                history.push(current_location.clone());
-                            }
 
-                            match repeat {
-                                RepeatMode::RepeatNone => {}
-                                RepeatMode::RepeatQueue => {
-                                    queue_ids.push(current_id);
-                                    queue.push(current_location);
+                println!(
+                    "Currently playing:\n{}\n{} - {}\n{}\nPlaylist index: {}\n{:?}",
+                    queue_paths[current_index],
+                    current_time,
+                    duration,
+                    metadata,
+                    current_index,
+                    queue_paths
+                );
+            }
+        }*/
+
+        match vlc_event {
+            // Events of the idle next player are not relevant
+            Some((slot, result)) if slot == player_slot => match result {
+                EngineEvent::EndReached => {
+                    println!("NEXT");
+                    // TOOD: Should we only count it as completed if the user did not seek in the track?
+                    listen_end = Some(ListenEnd::Completed);
+
+                    let preloaded = match next_index {
+                        Some(index) if next_id != 0 => Some((index, next_id)),
+                        _ => None,
+                    };
+
+                    let use_preloaded = play_queue.next(preloaded);
+
+                    match play_queue.current.clone() {
+                        Some(current) => {
+                            if use_preloaded {
+                                // Start the preloaded player first, the old one already ended
+                                if start_crossfade {
+                                    let _ = next_player.set_volume(0);
+                                    crossfade_start = Some(Instant::now());
+                                } else {
+                                    let _ = next_player.set_volume(get_gain_volume(
+                                        volume,
+                                        replaygains.get(&current.id),
+                                        &replaygain_mode,
+                                    ));
                                 }
-                                RepeatMode::RepeatTrack => {
-                                    queue_ids.insert(0, current_id);
-                                    queue.insert(0, current_location);
+                                next_player.set_mute(mediaplayer.get_mute());
+                                next_player.play().unwrap();
+                                std::mem::swap(&mut mediaplayer, &mut next_player);
+                                player_slot = 1 - player_slot;
+                                if !start_crossfade {
+                                    next_player.stop();
                                 }
+                            } else {
+                                mediaplayer.set_media(
+                                    &current.location,
+                                    gapless_infos.get(&current.id),
+                                    0,
+                                );
+                                mediaplayer.play().unwrap();
                             }
+                            next_id = 0;
+                            next_index = None;
 
-                            let mut shuffled_index: usize = 0;
-
-                            if shuffle && repeat != RepeatMode::RepeatTrack {
-                                shuffled_index = rng.random_range(..queue.len());
-                            }
+                            audio_state.queue = Some(play_queue.queue_ids());
+                        }
+                        None => {
+                            mediaplayer.stop();
+                        }
+                    }
 
-                            current_id = queue_ids.remove(shuffled_index);
-                            current_location = queue.remove(shuffled_index);
+                    write_audio_backend_state = true;
+                    audio_state.is_playing = Some(mediaplayer.is_playing());
+                    audio_state.current_track = Some(play_queue.current_id());
+                    audio_state.history = Some(play_queue.history_ids());
+                }
+                _ => {}
+            },
+            Some(_) => {}
+            None => {}
+        }
 
-                            mediaplayer.set_media(
-                                &Media::new_path(&instance, current_location.clone()).unwrap(),
-                            );
+        match db_data {
+            Some(result) => match result {
+                DBData::Play(audiotracks, source) => {
+                    let mut tracks: Vec<QueueTrack> = vec![];
+                    for audiotrack in audiotracks.iter() {
+                        tracks.push(QueueTrack {
+                            id: audiotrack.id,
+                            location: audiotrack.location.clone(),
+                            album_id: audiotrack.album_id,
+                            plays: audiotrack.plays,
+                            rating: audiotrack.rating,
+                            source: source.clone(),
+                        });
+                        album_ids.insert(audiotrack.id, audiotrack.album_id);
+                        replaygains.insert(audiotrack.id, audiotrack.replaygain.clone());
+                        gapless_infos.insert(audiotrack.id, audiotrack.gapless.clone());
+                    }
 
-                            mediaplayer.play().unwrap();
+                    // TODO: correct? repeat track would not play from new tracks...
+                    if play_queue.play(tracks, &mut rng) {
+                        mediaplayer.set_media(
+                            &play_queue.current_location(),
+                            gapless_infos.get(&play_queue.current_id()),
+                            0,
+                        );
+                        let _ = mediaplayer.play();
+                        listen_end = Some(ListenEnd::Replaced);
+                    }
 
-                            audio_state.queue = Some(queue_ids.clone());
-                        } else {
-                            if current_id != 0 {
-                                history_ids.push(current_id);
-                                history.push(current_location);
-                            }
+                    write_audio_backend_state = true;
+                    audio_state.is_playing = Some(mediaplayer.is_playing());
+                    audio_state.current_track = Some(play_queue.current_id());
+                    audio_state.queue = Some(play_queue.queue_ids());
+                    audio_state.history = Some(play_queue.history_ids());
+                }
+                DBData::QueueInsert(audiotracks, opt_index, source) => {
+                    let mut tracks: Vec<QueueTrack> = vec![];
+                    for audiotrack in audiotracks.iter() {
+                        tracks.push(QueueTrack {
+                            id: audiotrack.id,
+                            location: audiotrack.location.clone(),
+                            album_id: audiotrack.album_id,
+                            plays: audiotrack.plays,
+                            rating: audiotrack.rating,
+                            source: source.clone(),
+                        });
+                        album_ids.insert(audiotrack.id, audiotrack.album_id);
+                        replaygains.insert(audiotrack.id, audiotrack.replaygain.clone());
+                        gapless_infos.insert(audiotrack.id, audiotrack.gapless.clone());
+                    }
 
-                            current_id = 0;
-                            current_location = "".to_string();
+                    // Without an index, just append the tracks
+                    play_queue.insert(tracks, opt_index);
 
-                            mediaplayer.stop();
+                    write_audio_backend_state = true;
+                    audio_state.queue = Some(play_queue.queue_ids());
+                }
+                DBData::AudioBackendRecover(opt_history_audiotracks, opt_queue_audiotracks) => {
+                    if let Some(history_audiotracks) = opt_history_audiotracks {
+                        for audiotrack in history_audiotracks.iter() {
+                            play_queue.history.push(QueueTrack {
+                                id: audiotrack.id,
+                                location: audiotrack.location.clone(),
+                                album_id: audiotrack.album_id,
+                                plays: audiotrack.plays,
+                                rating: audiotrack.rating,
+                                source: None,
+                            });
+                            album_ids.insert(audiotrack.id, audiotrack.album_id);
+                            replaygains.insert(audiotrack.id, audiotrack.replaygain.clone());
+                            gapless_infos.insert(audiotrack.id, audiotrack.gapless.clone());
                         }
-
-                        write_audio_backend_state = true;
-                        audio_state.is_playing = Some(mediaplayer.is_playing());
-                        audio_state.current_track = Some(current_id);
-                        audio_state.history = Some(history_ids.clone());
+                        audio_state.history = Some(play_queue.history_ids());
                     }
-                    _ => {}
-                },
-                Err(_) => {
-                    //eprintln!("Error: {}", e);
+                    if let Some(queue_audiotracks) = opt_queue_audiotracks {
+                        for audiotrack in queue_audiotracks.iter() {
+                            play_queue.queue.push(QueueTrack {
+                                id: audiotrack.id,
+                                location: audiotrack.location.clone(),
+                                album_id: audiotrack.album_id,
+                                plays: audiotrack.plays,
+                                rating: audiotrack.rating,
+                                source: None,
+                            });
+                            album_ids.insert(audiotrack.id, audiotrack.album_id);
+                            replaygains.insert(audiotrack.id, audiotrack.replaygain.clone());
+                            gapless_infos.insert(audiotrack.id, audiotrack.gapless.clone());
+                        }
+                        audio_state.queue = Some(play_queue.queue_ids());
+                    }
+                }
+            },
+            None => {}
+        }
+
+        // Report the listen of the replaced track and start one for the current track
+        if let Some(end) = listen_end {
+            if let Some(played_track) = listen.take().and_then(|ended| ended.finish(end)) {
+                let _ = db_sender_audio.send(DBRequest::ListenedToTrack(played_track));
+            }
+        }
+        match &play_queue.current {
+            Some(current) => match &listen {
+                Some(current_listen) if current_listen.track_id == current.id => {}
+                _ => {
+                    listen = Some(Listen::new(current));
                 }
+            },
+            None => {
+                listen = None;
             }
+        }
+        if let Some(current_listen) = listen.as_mut() {
+            current_listen.update(mediaplayer.is_playing(), mediaplayer.get_length());
+        }
 
-            match db_data {
-                Ok(result) => match result {
-                    DBData::Play(audiotracks) => {
-                        queue = vec![];
-                        queue_ids = vec![];
+        let track_volume = get_gain_volume(
+            volume,
+            replaygains.get(&play_queue.current_id()),
+            &replaygain_mode,
+        );
+
+        if let Some(start) = crossfade_start {
+            let progress = if crossfade_ms > 0 {
+                (start.elapsed().as_millis() as f64 / crossfade_ms as f64).min(1.0)
+            } else {
+                1.0
+            };
 
-                        for audiotrack in audiotracks.iter() {
-                            queue.push(audiotrack.location.clone());
-                            queue_ids.push(audiotrack.id);
-                        }
+            // The fading track was moved into the history when the crossfade started
+            let fading_volume = match play_queue.history.last() {
+                Some(track) => {
+                    get_gain_volume(volume, replaygains.get(&track.id), &replaygain_mode)
+                }
+                None => volume,
+            };
 
-                        if current_id != 0 {
-                            history_ids.push(current_id);
-                            history.push(current_location);
-                        }
+            let _ = mediaplayer.set_volume((track_volume as f64 * progress).round() as i32);
+            let _ =
+                next_player.set_volume((fading_volume as f64 * (1.0 - progress)).round() as i32);
 
-                        let mut shuffled_index: usize = 0;
+            if progress >= 1.0 || !next_player.is_playing() {
+                next_player.stop();
+                let _ = mediaplayer.set_volume(track_volume);
+                crossfade_start = None;
+            }
+        } else if mediaplayer.get_volume() != track_volume {
+            // The volume of a player is kept when its media changes, apply the gain of the new track
+            let _ = mediaplayer.set_volume(track_volume);
+        }
 
-                        // TODO: correct? repeat track would not play from new tracks...
-                        if shuffle && repeat != RepeatMode::RepeatTrack {
-                            shuffled_index = rng.random_range(..queue.len());
-                        }
+        // Preload the track after the current one into the idle player for gapless playback
+        let preloaded = match next_index {
+            Some(index) if next_id != 0 => match play_queue.upcoming(index) {
+                Some(track) => track.id == next_id,
+                None => false,
+            },
+            _ => false,
+        };
 
-                        current_id = queue_ids.remove(shuffled_index);
-                        current_location = queue.remove(shuffled_index);
+        if play_queue.current.is_none() {
+            next_id = 0;
+            next_index = None;
+        } else if !preloaded && crossfade_start.is_none() {
+            next_id = 0;
+            next_index = play_queue.next_index();
+
+            if let Some(index) = next_index {
+                if let Some(track) = play_queue.upcoming(index) {
+                    if track.id != 0 && track.location != "" {
+                        next_player.set_media(&track.location, gapless_infos.get(&track.id), 0);
+                        next_id = track.id;
+                    }
+                }
+            }
+        }
 
-                        mediaplayer.set_media(
-                            &Media::new_path(&instance, current_location.clone()).unwrap(),
-                        );
-                        let _ = mediaplayer.play();
+        if mediaplayer.state() == EngineState::Playing {
+            pending_position = None;
 
-                        write_audio_backend_state = true;
-                        audio_state.is_playing = Some(mediaplayer.is_playing());
-                        audio_state.current_track = Some(current_id);
-                        audio_state.queue = Some(queue_ids.clone());
-                        audio_state.history = Some(history_ids.clone());
-                    }
-                    DBData::QueueInsert(audiotracks, opt_index) => {
-                        /*
-                        TODO:
-                        Requires update of current_index to point to the same track as before.
-                        The index only changes in the case where something is inserted before the current track,
-                        which only is true for opt_index <= current_index.
-                         */
-
-                        if let Some(index) = opt_index {
-                            for (audioindex, audiotrack) in audiotracks.iter().enumerate() {
-                                queue.insert(index + audioindex, audiotrack.location.clone());
-                                queue_ids.insert(index + audioindex, audiotrack.id);
-                            }
-                        } else {
-                            // Without an index, just append the tracks
-                            for audiotrack in audiotracks.iter() {
-                                queue.push(audiotrack.location.clone());
-                                queue_ids.push(audiotrack.id);
-                            }
-                        }
+            if last_checkpoint.elapsed() >= Duration::from_millis(CHECKPOINT_INTERVAL_MS) {
+                write_audio_backend_state = true;
+            }
+        }
 
-                        write_audio_backend_state = true;
-                        audio_state.queue = Some(queue_ids.clone());
-                    }
-                    DBData::AudioBackendRecover(opt_history_audiotracks, opt_queue_audiotracks) => {
-                        if let Some(history_audiotracks) = opt_history_audiotracks {
-                            for audiotrack in history_audiotracks.iter() {
-                                history.push(audiotrack.location.clone());
-                                history_ids.push(audiotrack.id);
-                            }
-                            audio_state.history = Some(history_ids.clone());
-                        }
-                        if let Some(queue_audiotracks) = opt_queue_audiotracks {
-                            for audiotrack in queue_audiotracks.iter() {
-                                queue.push(audiotrack.location.clone());
-                                queue_ids.push(audiotrack.id);
-                            }
-                            audio_state.queue = Some(queue_ids.clone());
-                        }
-                    }
-                },
-                Err(_) => {
-                    //eprintln!("Error: {}", e);
-                }
+        let mut position = get_position(&mediaplayer, &pending_position, play_queue.current_id());
+
+        // libvlc keeps reporting the old time for a moment after set_time
+        if let Some((target, requested)) = seek_position {
+            if (position - target).abs() < SEEK_SETTLE_MS as i64
+                || requested.elapsed() >= Duration::from_millis(SEEK_SETTLE_MS)
+            {
+                seek_position = None;
+            } else {
+                position = target;
             }
+        }
 
-            // Not required for every action, but for many
-            if write_audio_backend_state {
-                let audio_backend_state = AudioBackendState {
-                    volume: volume,
-                    position: mediaplayer.get_time().unwrap_or_default(),
-                    shuffle_mode: shuffle,
-                    repeat_mode: repeat.clone(),
-                    current_id: current_id,
-                    current_location: current_location.clone(),
-                    queue: queue_ids.clone(),
-                    history: history_ids.clone(),
-                };
-                set_audio_backend_state(
-                    &audio_backend_state_name,
-                    &audio_backend_state_path,
-                    audio_backend_state,
-                );
+        // Not required for every action, but for many
+        if write_audio_backend_state {
+            let audio_backend_state = AudioBackendState {
+                volume: volume,
+                position: position,
+                shuffle_mode: play_queue.shuffle.clone(),
+                repeat_mode: play_queue.repeat.clone(),
+                current_id: play_queue.current_id(),
+                current_location: play_queue.current_location(),
+                queue: play_queue.queue_ids(),
+                history: play_queue.history_ids(),
+                unshuffled_queue: play_queue.unshuffled.clone(),
+                output: output.clone(),
+            };
+            set_audio_backend_state(
+                &audio_backend_state_name,
+                &audio_backend_state_path,
+                audio_backend_state,
+            );
+            last_checkpoint = Instant::now();
+        }
+
+        if let Some(sender) = checkpoint_sender {
+            let _ = sender.send(());
+        }
+
+        let position_due = Instant::now() >= next_position_update;
+        if position_due {
+            next_position_update += Duration::from_millis(POSITION_UPDATE_MS);
+            if next_position_update < Instant::now() {
+                next_position_update = Instant::now() + Duration::from_millis(POSITION_UPDATE_MS);
             }
+        }
 
-            audio_state.shuffle_mode = Some(shuffle);
-            audio_state.repeat_mode = Some(repeat.clone());
+        // Ticks for crossfade steps only emit when a position update is due
+        if !is_tick || position_due {
+            audio_state.shuffle_mode = Some(play_queue.shuffle.clone());
+            audio_state.repeat_mode = Some(play_queue.repeat.clone());
             audio_state.is_playing = Some(mediaplayer.is_playing());
-            /*
-            TODO:
-            once events are unified this will not regularly update position in frontend.
-            Frontend needs to run own timer and resync whenever new message comes in.
-            On the other hand, unified events should be able to make audio backend more responsive.
-             */
-            audio_state.position = Some(mediaplayer.get_time().unwrap_or_default());
-
-            // Workaround to make sure we recover old playback position
-            if old_position != 0 {
-                mediaplayer.set_time(old_position);
-                audio_state.position = Some(old_position);
-            }
+            audio_state.position = Some(position);
 
             let _ = app.emit("audio_state", audio_state).unwrap();
         }
-    });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use engine::FakeEngine;
+
+    #[test]
+    fn position_before_media_started() {
+        let mut player = FakeEngine::new(10000);
+        player.set_media("/music/1.flac", None, 3000);
+        let pending_position = Some((1, 3000));
+
+        assert_eq!(get_position(&player, &pending_position, 1), 3000);
+        // Pending position of a different track
+        assert_eq!(get_position(&player, &pending_position, 2), 0);
+
+        player.play().unwrap();
+        player.advance(500);
+        assert_eq!(get_position(&player, &pending_position, 1), 3500);
+    }
+
+    #[test]
+    fn gain_volume_limited_by_peak() {
+        let replaygain = ReplayGain {
+            track_gain: Some(6.0),
+            track_peak: Some(0.8),
+            album_gain: Some(-6.0),
+            album_peak: None,
+        };
+
+        assert_eq!(
+            get_gain_volume(100, Some(&replaygain), &ReplayGainMode::Off),
+            100
+        );
+        assert_eq!(get_gain_volume(100, None, &ReplayGainMode::Track), 100);
+        // +6 dB would clip, the peak allows 1.25
+        assert_eq!(
+            get_gain_volume(100, Some(&replaygain), &ReplayGainMode::Track),
+            125
+        );
+        assert_eq!(
+            get_gain_volume(100, Some(&replaygain), &ReplayGainMode::Album),
+            50
+        );
+    }
 }
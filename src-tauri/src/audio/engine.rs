@@ -0,0 +1,240 @@
+// Playback backends of the player implement PlaybackEngine, an in-memory fake is used for tests
+use crate::defs::{Gapless, Output};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EngineState {
+    Stopped, // no media, or the media has not been started yet
+    #[allow(dead_code)] // only reported by libvlc
+    Buffering,
+    Playing,
+    Paused,
+    Ended,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EngineEvent {
+    EndReached,
+    MediaChanged,
+}
+
+pub trait PlaybackEngine {
+    // Load media that starts at position in ms once played, gapless is the stored encoder delay and padding
+    fn set_media(&mut self, location: &str, gapless: Option<&Gapless>, position: i64);
+    fn play(&mut self) -> Result<(), ()>;
+    fn pause(&mut self);
+    fn stop(&mut self);
+    fn state(&self) -> EngineState;
+    fn is_playing(&self) -> bool;
+    fn get_time(&self) -> Option<i64>;
+    // Only has an effect once the media is started
+    fn set_time(&mut self, time: i64);
+    fn get_length(&self) -> Option<i64>;
+    fn get_volume(&self) -> i32;
+    fn set_volume(&mut self, volume: i32) -> Result<(), ()>;
+    fn get_mute(&self) -> bool;
+    fn set_mute(&mut self, muted: bool);
+    // Select the output module and device, a different module is only used once playback starts again
+    fn set_output(&mut self, output: &Output);
+    // Switch the device of the current output module while playing
+    fn set_output_device(&mut self, output: &Output);
+    // Outputs as sent to the frontend, the id of an output is its index
+    fn get_outputs(&self) -> Vec<Output>;
+    // The callback is called from the thread of the backend
+    fn on_event<F: Fn(EngineEvent) + Send + Sync + 'static>(&self, callback: F);
+}
+
+/*
+Engine without audio output, playback only advances when told to.
+Behaves like libvlc where it matters for the player: set_time is ignored until the media is started.
+ */
+#[cfg(test)]
+pub struct FakeEngine {
+    pub location: Option<String>,
+    pub state: EngineState,
+    pub time: i64,
+    pub length: i64,
+    pub volume: i32,
+    pub muted: bool,
+    pub output: Option<Output>,
+    start_time: i64,
+    callbacks: std::sync::Mutex<Vec<Box<dyn Fn(EngineEvent) + Send + Sync>>>,
+}
+
+#[cfg(test)]
+impl FakeEngine {
+    pub fn new(length: i64) -> FakeEngine {
+        return FakeEngine {
+            location: None,
+            state: EngineState::Stopped,
+            time: 0,
+            length: length,
+            volume: 100,
+            muted: false,
+            output: None,
+            start_time: 0,
+            callbacks: std::sync::Mutex::new(vec![]),
+        };
+    }
+
+    fn emit(&self, event: EngineEvent) {
+        for callback in self.callbacks.lock().unwrap().iter() {
+            callback(event);
+        }
+    }
+
+    // Play for ms, EndReached is emitted at the end of the media
+    pub fn advance(&mut self, ms: i64) {
+        if self.state != EngineState::Playing {
+            return;
+        }
+
+        self.time += ms;
+        if self.time >= self.length {
+            self.time = self.length;
+            self.state = EngineState::Ended;
+            self.emit(EngineEvent::EndReached);
+        }
+    }
+}
+
+#[cfg(test)]
+impl PlaybackEngine for FakeEngine {
+    fn set_media(&mut self, location: &str, _gapless: Option<&Gapless>, position: i64) {
+        self.location = Some(location.to_string());
+        self.state = EngineState::Stopped;
+        self.time = 0;
+        self.start_time = position.max(0);
+        self.emit(EngineEvent::MediaChanged);
+    }
+
+    fn play(&mut self) -> Result<(), ()> {
+        if self.location.is_none() {
+            return Err(());
+        }
+        if self.state == EngineState::Stopped || self.state == EngineState::Ended {
+            self.time = self.start_time;
+            self.start_time = 0;
+        }
+        self.state = EngineState::Playing;
+        return Ok(());
+    }
+
+    fn pause(&mut self) {
+        if self.state == EngineState::Playing {
+            self.state = EngineState::Paused;
+        }
+    }
+
+    fn stop(&mut self) {
+        self.state = EngineState::Stopped;
+        self.time = 0;
+    }
+
+    fn state(&self) -> EngineState {
+        return self.state;
+    }
+
+    fn is_playing(&self) -> bool {
+        return self.state == EngineState::Playing;
+    }
+
+    fn get_time(&self) -> Option<i64> {
+        match self.state {
+            EngineState::Stopped => return None,
+            _ => return Some(self.time),
+        }
+    }
+
+    fn set_time(&mut self, time: i64) {
+        match self.state {
+            EngineState::Playing | EngineState::Paused | EngineState::Buffering => {
+                self.time = time.clamp(0, self.length);
+            }
+            _ => {}
+        }
+    }
+
+    fn get_length(&self) -> Option<i64> {
+        match self.location {
+            Some(_) => return Some(self.length),
+            None => return None,
+        }
+    }
+
+    fn get_volume(&self) -> i32 {
+        return self.volume;
+    }
+
+    fn set_volume(&mut self, volume: i32) -> Result<(), ()> {
+        if volume < 0 || volume > 200 {
+            return Err(());
+        }
+        self.volume = volume;
+        return Ok(());
+    }
+
+    fn get_mute(&self) -> bool {
+        return self.muted;
+    }
+
+    fn set_mute(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    fn set_output(&mut self, output: &Output) {
+        self.output = Some(output.clone());
+    }
+
+    fn set_output_device(&mut self, output: &Output) {
+        self.output = Some(output.clone());
+    }
+
+    fn get_outputs(&self) -> Vec<Output> {
+        return vec![];
+    }
+
+    fn on_event<F: Fn(EngineEvent) + Send + Sync + 'static>(&self, callback: F) {
+        self.callbacks.lock().unwrap().push(Box::new(callback));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn fake_engine_starts_at_position() {
+        let mut engine = FakeEngine::new(10000);
+        engine.set_media("/music/1.flac", None, 4000);
+        assert_eq!(engine.get_time(), None);
+
+        // Like libvlc, seeking before the media started has no effect
+        engine.set_time(2000);
+        engine.play().unwrap();
+        assert_eq!(engine.get_time(), Some(4000));
+
+        engine.set_time(2000);
+        assert_eq!(engine.get_time(), Some(2000));
+    }
+
+    #[test]
+    fn fake_engine_emits_events() {
+        let mut engine = FakeEngine::new(1000);
+        let (sender, receiver) = mpsc::channel::<EngineEvent>();
+        engine.on_event(move |event| {
+            let _ = sender.send(event);
+        });
+
+        engine.set_media("/music/1.flac", None, 0);
+        assert_eq!(receiver.try_recv(), Ok(EngineEvent::MediaChanged));
+
+        engine.play().unwrap();
+        engine.advance(600);
+        assert!(receiver.try_recv().is_err());
+        engine.advance(600);
+        assert_eq!(receiver.try_recv(), Ok(EngineEvent::EndReached));
+        assert_eq!(engine.state(), EngineState::Ended);
+        assert!(!engine.is_playing());
+    }
+}
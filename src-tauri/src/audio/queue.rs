@@ -0,0 +1,945 @@
+// Queue, history and current track of the player, independent of the playback engine
+use crate::defs::{PlaySource, RepeatMode, ShuffleMode};
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueTrack {
+    pub id: i64,
+    pub location: String,
+    pub album_id: i64,
+    pub plays: i64,
+    pub rating: i64,                // 0-100, 0 if unrated
+    pub source: Option<PlaySource>, // for the play history
+}
+
+/*
+While shuffled, queue is in the order the tracks will play, so the frontend shows what comes next.
+unshuffled holds the track ids in the order before shuffling, turning shuffle off restores it.
+ */
+pub struct PlayQueue {
+    pub queue: Vec<QueueTrack>,
+    pub history: Vec<QueueTrack>,
+    pub current: Option<QueueTrack>,
+    pub shuffle: ShuffleMode,
+    pub repeat: RepeatMode,
+    pub unshuffled: Vec<i64>,
+}
+
+fn fisher_yates<T, R: Rng>(items: &mut Vec<T>, rng: &mut R) {
+    for index in (1..items.len()).rev() {
+        let other = rng.random_range(..=index);
+        items.swap(index, other);
+    }
+}
+
+// Albums keep the order of their tracks, tracks without an album are shuffled on their own
+fn shuffle_albums<R: Rng>(tracks: Vec<QueueTrack>, rng: &mut R) -> Vec<QueueTrack> {
+    let mut albums: Vec<Vec<QueueTrack>> = vec![];
+    let mut album_indices: HashMap<i64, usize> = HashMap::new();
+
+    for track in tracks {
+        match album_indices.get(&track.album_id) {
+            Some(index) if track.album_id != 0 => {
+                albums[*index].push(track);
+            }
+            _ => {
+                album_indices.insert(track.album_id, albums.len());
+                albums.push(vec![track]);
+            }
+        }
+    }
+
+    fisher_yates(&mut albums, rng);
+    return albums.into_iter().flatten().collect();
+}
+
+/*
+Weighted random permutation (Efraimidis-Spirakis), each track gets the key u^(1/weight).
+Sorting by key puts tracks with a higher weight at the front more often.
+The weight grows with the play count and the rating, unrated tracks count as three stars.
+ */
+fn shuffle_weighted<R: Rng>(tracks: Vec<QueueTrack>, rng: &mut R) -> Vec<QueueTrack> {
+    let mut keyed: Vec<(f64, QueueTrack)> = tracks
+        .into_iter()
+        .map(|track| {
+            let rating = if track.rating > 0 { track.rating } else { 60 };
+            let weight = (1.0 + track.plays.max(0) as f64) * rating.min(100) as f64 / 60.0;
+            (rng.random::<f64>().powf(1.0 / weight), track)
+        })
+        .collect();
+
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    return keyed.into_iter().map(|(_key, track)| track).collect();
+}
+
+// Marks the longest increasing subsequence of values, O(n log n)
+fn longest_increasing(values: &[usize]) -> Vec<bool> {
+    // Index of the last value of the best subsequence of each length, and the previous index of each value
+    let mut tails: Vec<usize> = vec![];
+    let mut previous: Vec<Option<usize>> = vec![None; values.len()];
+
+    for (index, value) in values.iter().enumerate() {
+        let length = tails.partition_point(|tail| values[*tail] < *value);
+        if length > 0 {
+            previous[index] = Some(tails[length - 1]);
+        }
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+
+    let mut kept = vec![false; values.len()];
+    let mut current = tails.last().copied();
+    while let Some(index) = current {
+        kept[index] = true;
+        current = previous[index];
+    }
+    return kept;
+}
+
+impl PlayQueue {
+    pub fn new() -> PlayQueue {
+        return PlayQueue {
+            queue: vec![],
+            history: vec![],
+            current: None,
+            shuffle: ShuffleMode::Off,
+            repeat: RepeatMode::RepeatNone,
+            unshuffled: vec![],
+        };
+    }
+
+    // 0 if there is no current track, as used by the frontend and the audio backend state
+    pub fn current_id(&self) -> i64 {
+        match &self.current {
+            Some(current) => return current.id,
+            None => return 0,
+        }
+    }
+
+    pub fn current_location(&self) -> String {
+        match &self.current {
+            Some(current) => return current.location.clone(),
+            None => return "".to_string(),
+        }
+    }
+
+    pub fn queue_ids(&self) -> Vec<i64> {
+        return self.queue.iter().map(|track| track.id).collect();
+    }
+
+    pub fn history_ids(&self) -> Vec<i64> {
+        return self.history.iter().map(|track| track.id).collect();
+    }
+
+    fn current_to_history(&mut self) {
+        if let Some(current) = self.current.take() {
+            self.history.push(current);
+        }
+    }
+
+    fn shuffle_queue<R: Rng>(&mut self, rng: &mut R) {
+        self.unshuffled = self.queue_ids();
+
+        let tracks = std::mem::take(&mut self.queue);
+        match self.shuffle {
+            ShuffleMode::Off => {
+                self.queue = tracks;
+            }
+            ShuffleMode::Tracks => {
+                self.queue = tracks;
+                fisher_yates(&mut self.queue, rng);
+            }
+            ShuffleMode::Albums => {
+                self.queue = shuffle_albums(tracks, rng);
+            }
+            ShuffleMode::Weighted => {
+                self.queue = shuffle_weighted(tracks, rng);
+            }
+        }
+    }
+
+    /*
+    Restore the order before shuffling.
+    Tracks that were added while shuffled are not part of it and keep their order at the end.
+     */
+    fn unshuffle(&mut self) {
+        let mut remaining: Vec<Option<QueueTrack>> = std::mem::take(&mut self.queue)
+            .into_iter()
+            .map(Some)
+            .collect();
+
+        // Queue indices of each id, a track can be queued more than once
+        let mut indices: HashMap<i64, VecDeque<usize>> = HashMap::new();
+        for (index, track) in remaining.iter().enumerate() {
+            if let Some(track) = track {
+                indices.entry(track.id).or_default().push_back(index);
+            }
+        }
+
+        for id in self.unshuffled.iter() {
+            if let Some(index) = indices.get_mut(id).and_then(|queue| queue.pop_front()) {
+                if let Some(track) = remaining[index].take() {
+                    self.queue.push(track);
+                }
+            }
+        }
+
+        self.queue.extend(remaining.into_iter().flatten());
+        self.unshuffled = vec![];
+    }
+
+    // A different shuffle mode shuffles the original order again
+    pub fn set_shuffle<R: Rng>(&mut self, mode: ShuffleMode, rng: &mut R) {
+        if self.shuffle != ShuffleMode::Off {
+            self.unshuffle();
+        }
+
+        self.shuffle = mode;
+        if self.shuffle != ShuffleMode::Off {
+            self.shuffle_queue(rng);
+        }
+    }
+
+    /*
+    Move to the next track, used for Next and EndReached.
+    The current track goes into the history and, depending on repeat, back into the queue.
+    preloaded is the index and id of the track in the next player, it is used if the queue did not change since.
+    Returns true if the preloaded track is the new current track.
+     */
+    pub fn next(&mut self, preloaded: Option<(usize, i64)>) -> bool {
+        if let Some(current) = self.current.take() {
+            match self.repeat {
+                RepeatMode::RepeatNone => {}
+                RepeatMode::RepeatQueue => {
+                    self.queue.push(current.clone());
+                }
+                RepeatMode::RepeatTrack => {
+                    self.queue.insert(0, current.clone());
+                }
+            }
+            self.history.push(current);
+        }
+
+        if self.queue.is_empty() {
+            return false;
+        }
+
+        match preloaded {
+            Some((index, id)) if index < self.queue.len() && self.queue[index].id == id => {
+                self.current = Some(self.queue.remove(index));
+                return true;
+            }
+            _ => {
+                self.current = Some(self.queue.remove(0));
+                return false;
+            }
+        }
+    }
+
+    // Whether next() would use the preloaded track, without moving to it
+    pub fn is_next(&self, preloaded: Option<(usize, i64)>) -> bool {
+        match preloaded {
+            Some((index, id)) => match self.upcoming(index) {
+                Some(track) => return track.id == id,
+                None => return false,
+            },
+            None => return false,
+        }
+    }
+
+    // The current track goes back to the front of the queue, false if there is no history
+    pub fn prev(&mut self) -> bool {
+        match self.history.pop() {
+            Some(previous) => {
+                if let Some(current) = self.current.take() {
+                    if self.shuffle != ShuffleMode::Off {
+                        self.unshuffled.insert(0, current.id);
+                    }
+                    self.queue.insert(0, current);
+                }
+                self.current = Some(previous);
+                return true;
+            }
+            None => return false,
+        }
+    }
+
+    // Play the track at index, all tracks before it are discarded
+    pub fn queue_jump(&mut self, index: usize) -> bool {
+        if index >= self.queue.len() {
+            return false;
+        }
+
+        self.current_to_history();
+        self.current = Some(self.queue[index].clone());
+        self.queue.drain(0..=index);
+        return true;
+    }
+
+    // Play the track at index again, it stays in the history
+    pub fn history_jump(&mut self, index: usize) -> bool {
+        if index >= self.history.len() {
+            return false;
+        }
+
+        let track = self.history[index].clone();
+        self.current_to_history();
+        self.current = Some(track);
+        return true;
+    }
+
+    /*
+    Reorder the queue to ids, tracks that are not part of ids are removed.
+    While shuffled, moved tracks are also moved in the original order, before the same track as in the queue.
+     */
+    pub fn queue_move(&mut self, ids: &Vec<i64>) {
+        let mut old_queue: Vec<Option<QueueTrack>> = std::mem::take(&mut self.queue)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut old_indices: Vec<usize> = vec![];
+        for id in ids {
+            let position = old_queue.iter().position(|track| match track {
+                Some(track) => track.id == *id,
+                None => false,
+            });
+            if let Some(index) = position {
+                if let Some(track) = old_queue[index].take() {
+                    self.queue.push(track);
+                    old_indices.push(index);
+                }
+            }
+        }
+
+        if self.shuffle == ShuffleMode::Off {
+            return;
+        }
+
+        for removed in old_queue.into_iter().flatten() {
+            if let Some(index) = self.unshuffled.iter().position(|id| *id == removed.id) {
+                self.unshuffled.remove(index);
+            }
+        }
+
+        // Tracks that kept their order relative to each other did not move, from the back so the following track is in place
+        let kept = longest_increasing(&old_indices);
+        for index in (0..self.queue.len()).rev() {
+            if kept[index] {
+                continue;
+            }
+            let id = self.queue[index].id;
+            if let Some(unshuffled_index) = self.unshuffled.iter().position(|other| *other == id) {
+                self.unshuffled.remove(unshuffled_index);
+            }
+            let unshuffled_index = match self.queue.get(index + 1) {
+                Some(following) => self
+                    .unshuffled
+                    .iter()
+                    .position(|other| *other == following.id)
+                    .unwrap_or(self.unshuffled.len()),
+                None => self.unshuffled.len(),
+            };
+            self.unshuffled.insert(unshuffled_index, id);
+        }
+    }
+
+    pub fn queue_remove(&mut self, indices: &Vec<usize>) {
+        // sort indices then remove in descending order
+        let mut sorted_indices = indices.clone();
+        sorted_indices.sort();
+        sorted_indices.dedup();
+        for index in sorted_indices.iter().rev() {
+            if *index < self.queue.len() {
+                self.queue.remove(*index);
+            }
+        }
+    }
+
+    pub fn history_clear(&mut self) {
+        self.history = vec![];
+    }
+
+    // Replace the queue with tracks and start with the first one, shuffled if enabled
+    pub fn play<R: Rng>(&mut self, tracks: Vec<QueueTrack>, rng: &mut R) -> bool {
+        if tracks.is_empty() {
+            return false;
+        }
+
+        self.queue = tracks;
+        self.current_to_history();
+
+        if self.shuffle != ShuffleMode::Off {
+            self.shuffle_queue(rng);
+        }
+
+        self.current = Some(self.queue.remove(0));
+        return true;
+    }
+
+    /*
+    Insert tracks at index, or append them without one.
+    While shuffled, they are inserted before the same track in the original order.
+     */
+    pub fn insert(&mut self, tracks: Vec<QueueTrack>, opt_index: Option<usize>) {
+        let index = match opt_index {
+            Some(index) => index.min(self.queue.len()),
+            None => self.queue.len(),
+        };
+
+        if self.shuffle != ShuffleMode::Off {
+            let unshuffled_index = match self.queue.get(index) {
+                Some(following) => self
+                    .unshuffled
+                    .iter()
+                    .position(|id| *id == following.id)
+                    .unwrap_or(self.unshuffled.len()),
+                None => self.unshuffled.len(),
+            };
+            let ids: Vec<i64> = tracks.iter().map(|track| track.id).collect();
+            self.unshuffled
+                .splice(unshuffled_index..unshuffled_index, ids);
+        }
+
+        self.queue.splice(index..index, tracks);
+    }
+
+    /*
+    Index of the track that plays after the current one.
+    The index is valid for the queue after next moved the current track according to repeat:
+    RepeatTrack inserts the current track at the front, RepeatQueue appends it.
+     */
+    pub fn next_index(&self) -> Option<usize> {
+        match self.repeat {
+            RepeatMode::RepeatTrack | RepeatMode::RepeatQueue => {
+                return Some(0);
+            }
+            RepeatMode::RepeatNone => {
+                if self.queue.is_empty() {
+                    return None;
+                }
+                return Some(0);
+            }
+        }
+    }
+
+    // Track at next_index without moving the current track into the queue, see next_index
+    pub fn upcoming(&self, index: usize) -> Option<&QueueTrack> {
+        match self.repeat {
+            RepeatMode::RepeatTrack => {
+                if index == 0 {
+                    return self.current.as_ref();
+                }
+                return self.queue.get(index - 1);
+            }
+            RepeatMode::RepeatQueue => {
+                if index == self.queue.len() {
+                    return self.current.as_ref();
+                }
+                return self.queue.get(index);
+            }
+            RepeatMode::RepeatNone => {
+                return self.queue.get(index);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn track(id: i64) -> QueueTrack {
+        return QueueTrack {
+            id: id,
+            location: format!("/music/{}.flac", id),
+            album_id: 0,
+            plays: 0,
+            rating: 0,
+            source: None,
+        };
+    }
+
+    fn album_track(id: i64, album_id: i64) -> QueueTrack {
+        let mut track = track(id);
+        track.album_id = album_id;
+        return track;
+    }
+
+    fn tracks(ids: &[i64]) -> Vec<QueueTrack> {
+        return ids.iter().map(|id| track(*id)).collect();
+    }
+
+    // Current track 1, queue 2..=4
+    fn play_queue(repeat: RepeatMode) -> PlayQueue {
+        let mut play_queue = PlayQueue::new();
+        play_queue.repeat = repeat;
+        play_queue.play(tracks(&[1, 2, 3, 4]), &mut StdRng::seed_from_u64(0));
+        return play_queue;
+    }
+
+    #[test]
+    fn play_starts_with_first_track() {
+        let play_queue = play_queue(RepeatMode::RepeatNone);
+        assert_eq!(play_queue.current_id(), 1);
+        assert_eq!(play_queue.current_location(), "/music/1.flac");
+        assert_eq!(play_queue.queue_ids(), vec![2, 3, 4]);
+        assert!(play_queue.history.is_empty());
+    }
+
+    #[test]
+    fn play_moves_current_into_history() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+        play_queue.play(tracks(&[5, 6]), &mut StdRng::seed_from_u64(0));
+        assert_eq!(play_queue.current_id(), 5);
+        assert_eq!(play_queue.queue_ids(), vec![6]);
+        assert_eq!(play_queue.history_ids(), vec![1]);
+    }
+
+    #[test]
+    fn play_without_tracks_keeps_state() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+        assert!(!play_queue.play(vec![], &mut StdRng::seed_from_u64(0)));
+        assert_eq!(play_queue.current_id(), 1);
+        assert_eq!(play_queue.queue_ids(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn play_shuffled_starts_with_any_track() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut first_ids = vec![];
+        for _ in 0..20 {
+            let mut play_queue = PlayQueue::new();
+            play_queue.set_shuffle(ShuffleMode::Tracks, &mut rng);
+            play_queue.play(tracks(&[1, 2, 3, 4]), &mut rng);
+            first_ids.push(play_queue.current_id());
+
+            let mut ids = play_queue.queue_ids();
+            ids.push(play_queue.current_id());
+            ids.sort();
+            assert_eq!(ids, vec![1, 2, 3, 4]);
+        }
+
+        first_ids.sort();
+        first_ids.dedup();
+        assert!(first_ids.len() > 1);
+    }
+
+    #[test]
+    fn shuffle_off_restores_order() {
+        let mut play_queue = PlayQueue::new();
+        let mut rng = StdRng::seed_from_u64(5);
+        play_queue.play(tracks(&[1, 2, 3, 4, 5, 6, 7, 8]), &mut rng);
+
+        play_queue.set_shuffle(ShuffleMode::Tracks, &mut rng);
+        let shuffled = play_queue.queue_ids();
+        assert_ne!(shuffled, vec![2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(play_queue.unshuffled, vec![2, 3, 4, 5, 6, 7, 8]);
+
+        // The shuffled order is stable until shuffle is toggled
+        play_queue.next(None);
+        assert_eq!(play_queue.current_id(), shuffled[0]);
+        assert_eq!(play_queue.queue_ids(), shuffled[1..].to_vec());
+
+        play_queue.set_shuffle(ShuffleMode::Off, &mut rng);
+        let mut expected: Vec<i64> = vec![2, 3, 4, 5, 6, 7, 8];
+        expected.retain(|id| *id != shuffled[0]);
+        assert_eq!(play_queue.queue_ids(), expected);
+        assert!(play_queue.unshuffled.is_empty());
+    }
+
+    #[test]
+    fn shuffle_off_keeps_inserted_tracks() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+        let mut rng = StdRng::seed_from_u64(9);
+        play_queue.insert(tracks(&[2]), None);
+        play_queue.set_shuffle(ShuffleMode::Tracks, &mut rng);
+
+        // Inserted before 3 in the shuffled queue, so also before 3 in the original order
+        let index = play_queue
+            .queue_ids()
+            .iter()
+            .position(|id| *id == 3)
+            .unwrap();
+        play_queue.insert(tracks(&[5]), Some(index));
+        play_queue.insert(tracks(&[6]), None);
+        play_queue.queue_remove(&vec![play_queue
+            .queue_ids()
+            .iter()
+            .position(|id| *id == 4)
+            .unwrap()]);
+
+        play_queue.set_shuffle(ShuffleMode::Off, &mut rng);
+        assert_eq!(play_queue.queue_ids(), vec![2, 5, 3, 2, 6]);
+    }
+
+    #[test]
+    fn shuffle_albums_keeps_album_order() {
+        let mut play_queue = PlayQueue::new();
+        let mut rng = StdRng::seed_from_u64(1);
+        play_queue.play(tracks(&[99]), &mut rng);
+        play_queue.insert(
+            vec![
+                album_track(1, 10),
+                album_track(2, 10),
+                album_track(3, 20),
+                album_track(4, 10),
+                album_track(5, 20),
+                album_track(6, 0),
+                album_track(7, 0),
+            ],
+            None,
+        );
+
+        for _ in 0..10 {
+            play_queue.set_shuffle(ShuffleMode::Albums, &mut rng);
+            let ids = play_queue.queue_ids();
+            assert_eq!(ids.len(), 7);
+
+            let album_10: Vec<i64> = ids
+                .iter()
+                .cloned()
+                .filter(|id| [1, 2, 4].contains(id))
+                .collect();
+            let album_20: Vec<i64> = ids
+                .iter()
+                .cloned()
+                .filter(|id| [3, 5].contains(id))
+                .collect();
+            assert_eq!(album_10, vec![1, 2, 4]);
+            assert_eq!(album_20, vec![3, 5]);
+
+            // Tracks of an album play one after another
+            let start = ids.iter().position(|id| *id == 1).unwrap();
+            assert_eq!(ids[start..start + 3].to_vec(), vec![1, 2, 4]);
+
+            play_queue.set_shuffle(ShuffleMode::Off, &mut rng);
+            assert_eq!(play_queue.queue_ids(), vec![1, 2, 3, 4, 5, 6, 7]);
+        }
+    }
+
+    #[test]
+    fn shuffle_weighted_prefers_played_tracks() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut first_played = 0;
+        for _ in 0..200 {
+            let mut play_queue = PlayQueue::new();
+            play_queue.set_shuffle(ShuffleMode::Weighted, &mut rng);
+
+            let mut queue_tracks = tracks(&[1, 2, 3, 4]);
+            queue_tracks[3].plays = 30;
+            play_queue.play(queue_tracks, &mut rng);
+            if play_queue.current_id() == 4 {
+                first_played += 1;
+            }
+        }
+
+        assert!(first_played > 100);
+    }
+
+    #[test]
+    fn shuffle_weighted_prefers_rated_tracks() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut first_played = 0;
+        for _ in 0..200 {
+            let mut play_queue = PlayQueue::new();
+            play_queue.set_shuffle(ShuffleMode::Weighted, &mut rng);
+
+            let mut queue_tracks = tracks(&[1, 2, 3, 4]);
+            queue_tracks[0].rating = 20;
+            queue_tracks[1].rating = 20;
+            queue_tracks[2].rating = 20;
+            queue_tracks[3].rating = 100;
+            play_queue.play(queue_tracks, &mut rng);
+            if play_queue.current_id() == 4 {
+                first_played += 1;
+            }
+        }
+
+        assert!(first_played > 100);
+    }
+
+    #[test]
+    fn next_without_repeat() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+
+        assert!(!play_queue.next(None));
+        assert_eq!(play_queue.current_id(), 2);
+        assert_eq!(play_queue.queue_ids(), vec![3, 4]);
+        assert_eq!(play_queue.history_ids(), vec![1]);
+
+        play_queue.next(None);
+        play_queue.next(None);
+        assert_eq!(play_queue.current_id(), 4);
+
+        // End of the queue
+        play_queue.next(None);
+        assert_eq!(play_queue.current, None);
+        assert_eq!(play_queue.current_id(), 0);
+        assert_eq!(play_queue.current_location(), "");
+        assert_eq!(play_queue.history_ids(), vec![1, 2, 3, 4]);
+
+        // Nothing left to play
+        play_queue.next(None);
+        assert_eq!(play_queue.current, None);
+        assert_eq!(play_queue.history_ids(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn next_repeat_queue_appends_current() {
+        let mut play_queue = play_queue(RepeatMode::RepeatQueue);
+
+        let mut played = vec![play_queue.current_id()];
+        for _ in 0..7 {
+            play_queue.next(None);
+            played.push(play_queue.current_id());
+        }
+
+        assert_eq!(played, vec![1, 2, 3, 4, 1, 2, 3, 4]);
+        assert_eq!(play_queue.queue_ids(), vec![1, 2, 3]);
+        assert_eq!(play_queue.history_ids(), vec![1, 2, 3, 4, 1, 2, 3]);
+    }
+
+    #[test]
+    fn next_repeat_track_keeps_current() {
+        let mut play_queue = play_queue(RepeatMode::RepeatTrack);
+        play_queue.set_shuffle(ShuffleMode::Tracks, &mut StdRng::seed_from_u64(0));
+        let queue_ids = play_queue.queue_ids();
+
+        for _ in 0..3 {
+            play_queue.next(None);
+            assert_eq!(play_queue.current_id(), 1);
+            assert_eq!(play_queue.queue_ids(), queue_ids);
+        }
+        assert_eq!(play_queue.history_ids(), vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn next_repeat_without_current_track() {
+        let mut play_queue = PlayQueue::new();
+        play_queue.repeat = RepeatMode::RepeatQueue;
+
+        assert!(!play_queue.next(None));
+        assert_eq!(play_queue.current, None);
+        assert!(play_queue.queue.is_empty());
+        assert!(play_queue.history.is_empty());
+    }
+
+    #[test]
+    fn next_shuffled_plays_every_track_once() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+        play_queue.set_shuffle(ShuffleMode::Tracks, &mut StdRng::seed_from_u64(3));
+
+        let mut played = vec![play_queue.current_id()];
+        while play_queue.current.is_some() {
+            play_queue.next(None);
+            if play_queue.current.is_some() {
+                played.push(play_queue.current_id());
+            }
+        }
+
+        played.sort();
+        assert_eq!(played, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn next_uses_preloaded_track() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+
+        assert!(play_queue.next(Some((1, 3))));
+        assert_eq!(play_queue.current_id(), 3);
+        assert_eq!(play_queue.queue_ids(), vec![2, 4]);
+    }
+
+    #[test]
+    fn next_ignores_outdated_preloaded_track() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+
+        // The queue changed since the track was preloaded
+        play_queue.queue_remove(&vec![1]);
+        assert!(!play_queue.is_next(Some((1, 3))));
+        assert!(!play_queue.next(Some((1, 3))));
+        assert_eq!(play_queue.current_id(), 2);
+
+        assert!(!play_queue.next(Some((5, 4))));
+        assert_eq!(play_queue.current_id(), 4);
+    }
+
+    #[test]
+    fn next_index_matches_next() {
+        let mut rng = StdRng::seed_from_u64(11);
+
+        for repeat in [
+            RepeatMode::RepeatNone,
+            RepeatMode::RepeatQueue,
+            RepeatMode::RepeatTrack,
+        ] {
+            for shuffle in [ShuffleMode::Off, ShuffleMode::Tracks, ShuffleMode::Albums] {
+                let mut play_queue = play_queue(repeat.clone());
+                play_queue.set_shuffle(shuffle, &mut rng);
+
+                for _ in 0..10 {
+                    let index = match play_queue.next_index() {
+                        Some(index) => index,
+                        None => break,
+                    };
+                    let upcoming = play_queue.upcoming(index).unwrap().clone();
+
+                    assert!(play_queue.is_next(Some((index, upcoming.id))));
+                    assert!(play_queue.next(Some((index, upcoming.id))));
+                    assert_eq!(play_queue.current, Some(upcoming));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn next_index_at_end_of_queue() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+        play_queue.queue_jump(2);
+        assert_eq!(play_queue.next_index(), None);
+
+        play_queue.repeat = RepeatMode::RepeatQueue;
+        assert_eq!(play_queue.next_index(), Some(0));
+        assert_eq!(play_queue.upcoming(0), Some(&track(4)));
+    }
+
+    #[test]
+    fn prev_moves_current_to_front_of_queue() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+        play_queue.next(None);
+        play_queue.next(None);
+
+        assert!(play_queue.prev());
+        assert_eq!(play_queue.current_id(), 2);
+        assert_eq!(play_queue.queue_ids(), vec![3, 4]);
+        assert_eq!(play_queue.history_ids(), vec![1]);
+
+        assert!(play_queue.prev());
+        assert_eq!(play_queue.current_id(), 1);
+        assert_eq!(play_queue.queue_ids(), vec![2, 3, 4]);
+
+        assert!(!play_queue.prev());
+        assert_eq!(play_queue.current_id(), 1);
+        assert_eq!(play_queue.queue_ids(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn prev_after_end_of_queue() {
+        let mut play_queue = PlayQueue::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        play_queue.play(tracks(&[1]), &mut rng);
+        play_queue.next(None);
+
+        assert!(play_queue.prev());
+        assert_eq!(play_queue.current_id(), 1);
+        assert!(play_queue.queue.is_empty());
+    }
+
+    #[test]
+    fn queue_jump_discards_skipped_tracks() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+
+        assert!(play_queue.queue_jump(1));
+        assert_eq!(play_queue.current_id(), 3);
+        assert_eq!(play_queue.queue_ids(), vec![4]);
+        assert_eq!(play_queue.history_ids(), vec![1]);
+
+        assert!(!play_queue.queue_jump(1));
+        assert_eq!(play_queue.current_id(), 3);
+        assert_eq!(play_queue.queue_ids(), vec![4]);
+    }
+
+    #[test]
+    fn history_jump_keeps_history() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+        play_queue.next(None);
+        play_queue.next(None);
+
+        assert!(play_queue.history_jump(0));
+        assert_eq!(play_queue.current_id(), 1);
+        assert_eq!(play_queue.history_ids(), vec![1, 2, 3]);
+        assert_eq!(play_queue.queue_ids(), vec![4]);
+
+        assert!(!play_queue.history_jump(3));
+        assert_eq!(play_queue.current_id(), 1);
+    }
+
+    #[test]
+    fn queue_move_reorders() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+        play_queue.insert(tracks(&[3]), None);
+
+        play_queue.queue_move(&vec![3, 4, 2, 3, 9]);
+        assert_eq!(play_queue.queue_ids(), vec![3, 4, 2, 3]);
+        assert_eq!(play_queue.queue[0], track(3));
+    }
+
+    #[test]
+    fn queue_move_shuffled_moves_original_order() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+        play_queue.shuffle = ShuffleMode::Tracks;
+        play_queue.queue = tracks(&[5, 2, 3, 4]);
+        play_queue.unshuffled = vec![2, 3, 4, 5];
+
+        // 4 moved before 2, 3 removed
+        play_queue.queue_move(&vec![5, 4, 2]);
+        assert_eq!(play_queue.queue_ids(), vec![5, 4, 2]);
+        assert_eq!(play_queue.unshuffled, vec![4, 2, 5]);
+
+        play_queue.set_shuffle(ShuffleMode::Off, &mut StdRng::seed_from_u64(0));
+        assert_eq!(play_queue.queue_ids(), vec![4, 2, 5]);
+    }
+
+    #[test]
+    fn longest_increasing_marks_unmoved() {
+        let cases: [(&[usize], &[bool]); 4] = [
+            (&[], &[]),
+            (&[0, 1, 2], &[true, true, true]),
+            (&[0, 3, 1, 2], &[true, false, true, true]),
+            (&[2, 0, 1, 3], &[false, true, true, true]),
+        ];
+        for (values, expected) in cases {
+            assert_eq!(longest_increasing(values), expected, "{:?}", values);
+        }
+    }
+
+    #[test]
+    fn queue_remove_indices() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+
+        play_queue.queue_remove(&vec![2, 0, 2, 7]);
+        assert_eq!(play_queue.queue_ids(), vec![3]);
+        assert_eq!(play_queue.current_id(), 1);
+    }
+
+    #[test]
+    fn insert_tracks() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+
+        play_queue.insert(tracks(&[5, 6]), Some(1));
+        assert_eq!(play_queue.queue_ids(), vec![2, 5, 6, 3, 4]);
+
+        play_queue.insert(tracks(&[7]), None);
+        assert_eq!(play_queue.queue_ids(), vec![2, 5, 6, 3, 4, 7]);
+
+        play_queue.insert(tracks(&[8]), Some(100));
+        assert_eq!(play_queue.queue_ids(), vec![2, 5, 6, 3, 4, 7, 8]);
+    }
+
+    #[test]
+    fn history_clear() {
+        let mut play_queue = play_queue(RepeatMode::RepeatNone);
+        play_queue.next(None);
+
+        play_queue.history_clear();
+        assert!(play_queue.history.is_empty());
+        assert!(!play_queue.prev());
+        assert_eq!(play_queue.current_id(), 2);
+    }
+}
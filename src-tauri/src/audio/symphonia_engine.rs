@@ -0,0 +1,712 @@
+// Playback with symphonia for decoding and cpal for output, does not require libvlc
+use super::engine::{EngineEvent, EngineState, PlaybackEngine};
+use crate::defs::{Gapless, Output};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, FromSample, Host, SampleFormat, SizedSample, Stream, StreamConfig};
+use log::error;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::Time;
+
+// Decoded audio that is buffered ahead of the output
+static BUFFER_AHEAD_MS: u64 = 500;
+// Interval in which the decoder checks for free buffer space, seeks and stop
+static DECODER_WAIT_MS: u64 = 10;
+
+// State shared between the engine, its decoder thread and the output callback
+struct Shared {
+    // Interleaved samples with the channel count and sample rate of the output
+    buffer: Mutex<VecDeque<f32>>,
+    playing: AtomicBool,
+    ended: AtomicBool,
+    decoder_done: AtomicBool,
+    stop_decoder: AtomicBool,
+    seek_to: Mutex<Option<i64>>,
+    // Time in ms of the first frame after the start or the last seek
+    base_time: AtomicI64,
+    frames_played: AtomicU64,
+    length: AtomicI64, // ms, -1 if unknown
+    gain: AtomicU32,   // f32 bits
+    muted: AtomicBool,
+    output_rate: AtomicU32,
+    output_channels: AtomicU32,
+    callbacks: Mutex<Vec<Box<dyn Fn(EngineEvent) + Send + Sync>>>,
+}
+
+impl Shared {
+    fn emit(&self, event: EngineEvent) {
+        for callback in self.callbacks.lock().unwrap().iter() {
+            callback(event);
+        }
+    }
+
+    fn restart_at(&self, time: i64) {
+        self.buffer.lock().unwrap().clear();
+        self.base_time.store(time, Ordering::Relaxed);
+        self.frames_played.store(0, Ordering::Relaxed);
+    }
+}
+
+/*
+Converts decoded frames to the channels and sample rate of the output.
+Missing channels repeat the existing ones, additional ones are dropped. Resampling is linear.
+ */
+struct Converter {
+    // Position of the next output frame in input frames, relative to the current chunk
+    position: f64,
+    // Last frame of the previous chunk, used as frame -1
+    previous: Vec<f32>,
+}
+
+impl Converter {
+    fn new() -> Converter {
+        return Converter {
+            position: 0.0,
+            previous: vec![],
+        };
+    }
+
+    fn process(
+        &mut self,
+        samples: &[f32],
+        in_channels: usize,
+        in_rate: u32,
+        out_channels: usize,
+        out_rate: u32,
+        skip_frames: &mut u64,
+        output: &mut Vec<f32>,
+    ) {
+        let mut frames: Vec<f32> = vec![];
+        for frame in samples.chunks_exact(in_channels) {
+            // Skip frames before the exact seek position
+            if *skip_frames > 0 {
+                *skip_frames -= 1;
+                continue;
+            }
+            for channel in 0..out_channels {
+                frames.push(frame[channel % in_channels]);
+            }
+        }
+
+        if in_rate == out_rate {
+            output.extend_from_slice(&frames);
+            return;
+        }
+
+        let count = frames.len() / out_channels;
+        if count == 0 {
+            return;
+        }
+
+        if self.previous.len() != out_channels {
+            self.previous = frames[0..out_channels].to_vec();
+        }
+
+        let step = in_rate as f64 / out_rate as f64;
+        while self.position < (count - 1) as f64 {
+            let index = self.position.floor();
+            let fraction = (self.position - index) as f32;
+            for channel in 0..out_channels {
+                let a = if index < 0.0 {
+                    self.previous[channel]
+                } else {
+                    frames[index as usize * out_channels + channel]
+                };
+                let b = frames[(index + 1.0) as usize * out_channels + channel];
+                output.push(a + (b - a) * fraction);
+            }
+            self.position += step;
+        }
+
+        self.position -= count as f64;
+        self.previous = frames[(count - 1) * out_channels..].to_vec();
+    }
+}
+
+fn seek(format: &mut Box<dyn FormatReader>, track_id: u32, time: i64) -> u64 {
+    match format.seek(
+        SeekMode::Accurate,
+        SeekTo::Time {
+            time: Time::from(time.max(0) as f64 / 1000.0),
+            track_id: Some(track_id),
+        },
+    ) {
+        Ok(seeked_to) => return seeked_to.required_ts.saturating_sub(seeked_to.actual_ts),
+        Err(error) => {
+            error!("{}", error);
+            return 0;
+        }
+    }
+}
+
+// Decodes into the shared buffer until stopped, after the end of the track it waits for a seek
+fn decode(location: String, start_time: i64, shared: Arc<Shared>) {
+    let path = Path::new(&location);
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) => {
+            error!("{}", error);
+            shared.decoder_done.store(true, Ordering::Relaxed);
+            return;
+        }
+    };
+
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    // Encoder delay and padding are removed, no silence between tracks
+    let format_opts = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+
+    let metadata_opts: MetadataOptions = Default::default();
+
+    let mut hint = Hint::new();
+
+    if let Some(extension) = path.extension() {
+        if let Some(extension_str) = extension.to_str() {
+            hint.with_extension(extension_str);
+        }
+    }
+
+    let mut format =
+        match symphonia::default::get_probe().format(&hint, mss, &format_opts, &metadata_opts) {
+            Ok(probed) => probed.format,
+            Err(error) => {
+                error!("{}", error);
+                shared.decoder_done.store(true, Ordering::Relaxed);
+                return;
+            }
+        };
+
+    let (track_id, codec_params) = match format.default_track() {
+        Some(track) => (track.id, track.codec_params.clone()),
+        None => {
+            shared.decoder_done.store(true, Ordering::Relaxed);
+            return;
+        }
+    };
+
+    if let (Some(n_frames), Some(sample_rate)) = (codec_params.n_frames, codec_params.sample_rate) {
+        shared.length.store(
+            (n_frames * 1000 / sample_rate as u64) as i64,
+            Ordering::Relaxed,
+        );
+    }
+
+    let mut decoder =
+        match symphonia::default::get_codecs().make(&codec_params, &DecoderOptions::default()) {
+            Ok(decoder) => decoder,
+            Err(error) => {
+                error!("{}", error);
+                shared.decoder_done.store(true, Ordering::Relaxed);
+                return;
+            }
+        };
+
+    let mut skip_frames: u64 = 0;
+    if start_time > 0 {
+        skip_frames = seek(&mut format, track_id, start_time);
+    }
+
+    let mut converter = Converter::new();
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+    let mut converted: Vec<f32> = vec![];
+    let mut output_format = (0, 0);
+
+    loop {
+        if shared.stop_decoder.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let opt_seek_to = shared.seek_to.lock().unwrap().take();
+        if let Some(time) = opt_seek_to {
+            skip_frames = seek(&mut format, track_id, time);
+            decoder.reset();
+            converter = Converter::new();
+            shared.restart_at(time);
+            shared.decoder_done.store(false, Ordering::Relaxed);
+        }
+
+        let out_rate = shared.output_rate.load(Ordering::Relaxed);
+        let out_channels = shared.output_channels.load(Ordering::Relaxed) as usize;
+
+        // The output changed, the position of the converter is not valid anymore
+        if output_format != (out_rate, out_channels) {
+            converter = Converter::new();
+            output_format = (out_rate, out_channels);
+        }
+
+        let buffer_limit = out_rate as usize * out_channels * BUFFER_AHEAD_MS as usize / 1000;
+        if shared.decoder_done.load(Ordering::Relaxed)
+            || out_channels == 0
+            || shared.buffer.lock().unwrap().len() >= buffer_limit
+        {
+            thread::sleep(Duration::from_millis(DECODER_WAIT_MS));
+            continue;
+        }
+
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            // End of stream
+            Err(_error) => {
+                shared.decoder_done.store(true, Ordering::Relaxed);
+                continue;
+            }
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(audio_buf) => {
+                let spec = *audio_buf.spec();
+
+                let required = audio_buf.capacity() * spec.channels.count();
+                let too_small = match &sample_buf {
+                    Some(buf) => buf.capacity() < required,
+                    None => true,
+                };
+                if too_small {
+                    sample_buf = Some(SampleBuffer::<f32>::new(audio_buf.capacity() as u64, spec));
+                }
+
+                if let Some(buf) = sample_buf.as_mut() {
+                    buf.copy_interleaved_ref(audio_buf);
+
+                    converted.clear();
+                    converter.process(
+                        buf.samples(),
+                        spec.channels.count().max(1),
+                        spec.rate,
+                        out_channels,
+                        out_rate,
+                        &mut skip_frames,
+                        &mut converted,
+                    );
+
+                    // A seek during decoding makes the samples obsolete
+                    let mut buffer = shared.buffer.lock().unwrap();
+                    if shared.seek_to.lock().unwrap().is_none() {
+                        buffer.extend(converted.iter());
+                    }
+                }
+            }
+            // Skip corrupted packets
+            Err(Error::DecodeError(_)) => continue,
+            Err(error) => {
+                error!("{}", error);
+                shared.decoder_done.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn write_output<T: SizedSample + FromSample<f32>>(data: &mut [T], shared: &Shared) {
+    if !shared.playing.load(Ordering::Relaxed) {
+        for sample in data.iter_mut() {
+            *sample = T::EQUILIBRIUM;
+        }
+        return;
+    }
+
+    let gain = if shared.muted.load(Ordering::Relaxed) {
+        0.0
+    } else {
+        f32::from_bits(shared.gain.load(Ordering::Relaxed))
+    };
+
+    let mut buffer = shared.buffer.lock().unwrap();
+    let mut written: u64 = 0;
+    for sample in data.iter_mut() {
+        match buffer.pop_front() {
+            Some(value) => {
+                *sample = T::from_sample(value * gain);
+                written += 1;
+            }
+            None => {
+                *sample = T::EQUILIBRIUM;
+            }
+        }
+    }
+    let is_empty = buffer.is_empty();
+    drop(buffer);
+
+    let channels = shared.output_channels.load(Ordering::Relaxed).max(1) as u64;
+    shared
+        .frames_played
+        .fetch_add(written / channels, Ordering::Relaxed);
+
+    // Everything is decoded and played
+    if is_empty
+        && shared.decoder_done.load(Ordering::Relaxed)
+        && !shared.ended.swap(true, Ordering::Relaxed)
+    {
+        shared.playing.store(false, Ordering::Relaxed);
+        shared.emit(EngineEvent::EndReached);
+    }
+}
+
+fn build_stream<T: SizedSample + FromSample<f32>>(
+    device: &Device,
+    config: &StreamConfig,
+    shared: Arc<Shared>,
+) -> Result<Stream, String> {
+    match device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| write_output(data, &shared),
+        |error| {
+            error!("{}", error);
+        },
+        None,
+    ) {
+        Ok(stream) => return Ok(stream),
+        Err(error) => return Err(error.to_string()),
+    }
+}
+
+pub struct SymphoniaEngine {
+    host: Host,
+    device_name: Option<String>, // None for the default device
+    stream: Option<Stream>,
+    shared: Arc<Shared>,
+    decoder: Option<JoinHandle<()>>,
+    location: Option<String>,
+    start_time: i64,
+    started: bool,
+    volume: i32,
+}
+
+impl SymphoniaEngine {
+    pub fn new() -> SymphoniaEngine {
+        return SymphoniaEngine {
+            host: cpal::default_host(),
+            device_name: None,
+            stream: None,
+            shared: Arc::new(Shared {
+                buffer: Mutex::new(VecDeque::new()),
+                playing: AtomicBool::new(false),
+                ended: AtomicBool::new(false),
+                decoder_done: AtomicBool::new(false),
+                stop_decoder: AtomicBool::new(false),
+                seek_to: Mutex::new(None),
+                base_time: AtomicI64::new(0),
+                frames_played: AtomicU64::new(0),
+                length: AtomicI64::new(-1),
+                gain: AtomicU32::new(1f32.to_bits()),
+                muted: AtomicBool::new(false),
+                output_rate: AtomicU32::new(0),
+                output_channels: AtomicU32::new(0),
+                callbacks: Mutex::new(vec![]),
+            }),
+            decoder: None,
+            location: None,
+            start_time: 0,
+            started: false,
+            volume: 100,
+        };
+    }
+
+    fn get_device(&self) -> Option<Device> {
+        if let Some(device_name) = &self.device_name {
+            match self.host.output_devices() {
+                Ok(mut devices) => {
+                    if let Some(device) = devices.find(|device| match device.name() {
+                        Ok(name) => name == *device_name,
+                        Err(_error) => false,
+                    }) {
+                        return Some(device);
+                    }
+                }
+                Err(error) => {
+                    error!("{}", error);
+                }
+            }
+            error!("Output {} is not available, using default", device_name);
+        }
+        return self.host.default_output_device();
+    }
+
+    fn open_stream(&mut self) -> Result<(), String> {
+        let device = match self.get_device() {
+            Some(device) => device,
+            None => return Err("No output device available".to_string()),
+        };
+
+        let supported_config = match device.default_output_config() {
+            Ok(supported_config) => supported_config,
+            Err(error) => return Err(error.to_string()),
+        };
+        let config: StreamConfig = supported_config.config();
+
+        self.shared
+            .output_rate
+            .store(config.sample_rate.0, Ordering::Relaxed);
+        self.shared
+            .output_channels
+            .store(config.channels as u32, Ordering::Relaxed);
+
+        let stream = match supported_config.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, self.shared.clone())?,
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, self.shared.clone())?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, self.shared.clone())?,
+            SampleFormat::I32 => build_stream::<i32>(&device, &config, self.shared.clone())?,
+            sample_format => {
+                return Err(format!(
+                    "Sample format {:?} is not supported",
+                    sample_format
+                ));
+            }
+        };
+
+        self.stream = Some(stream);
+        return Ok(());
+    }
+
+    fn stop_decoder(&mut self) {
+        if let Some(decoder) = self.decoder.take() {
+            self.shared.stop_decoder.store(true, Ordering::Relaxed);
+            let _ = decoder.join();
+            self.shared.stop_decoder.store(false, Ordering::Relaxed);
+        }
+        self.shared.decoder_done.store(false, Ordering::Relaxed);
+        *self.shared.seek_to.lock().unwrap() = None;
+    }
+
+    // Continue on a new device, buffered samples are in the format of the old one
+    fn reopen_stream(&mut self) {
+        if self.stream.is_none() {
+            return;
+        }
+
+        let time = self.get_time();
+        self.stream = None;
+        match self.open_stream() {
+            Ok(()) => {
+                if let Some(time) = time {
+                    self.set_time(time);
+                }
+                if self.shared.playing.load(Ordering::Relaxed) {
+                    if let Some(stream) = &self.stream {
+                        if let Err(error) = stream.play() {
+                            error!("{}", error);
+                        }
+                    }
+                }
+            }
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+}
+
+impl Drop for SymphoniaEngine {
+    fn drop(&mut self) {
+        self.stop_decoder();
+    }
+}
+
+impl PlaybackEngine for SymphoniaEngine {
+    // Symphonia removes encoder delay and padding while decoding
+    fn set_media(&mut self, location: &str, _gapless: Option<&Gapless>, position: i64) {
+        self.shared.playing.store(false, Ordering::Relaxed);
+        self.stop_decoder();
+        self.shared.ended.store(false, Ordering::Relaxed);
+        self.shared.length.store(-1, Ordering::Relaxed);
+        self.shared.restart_at(0);
+
+        self.location = Some(location.to_string());
+        self.start_time = position.max(0);
+        self.started = false;
+
+        self.shared.emit(EngineEvent::MediaChanged);
+    }
+
+    fn play(&mut self) -> Result<(), ()> {
+        let location = match &self.location {
+            Some(location) => location.clone(),
+            None => return Err(()),
+        };
+
+        if self.stream.is_none() {
+            if let Err(error) = self.open_stream() {
+                error!("{}", error);
+                return Err(());
+            }
+        }
+
+        // Decoding starts with the first play and again after the end of the track
+        if !self.started || self.shared.ended.load(Ordering::Relaxed) {
+            self.stop_decoder();
+            self.shared.ended.store(false, Ordering::Relaxed);
+            self.shared.restart_at(self.start_time);
+
+            let start_time = self.start_time;
+            let shared = self.shared.clone();
+            self.decoder = Some(thread::spawn(move || {
+                decode(location, start_time, shared);
+            }));
+
+            self.start_time = 0;
+            self.started = true;
+        }
+
+        self.shared.playing.store(true, Ordering::Relaxed);
+        if let Some(stream) = &self.stream {
+            if let Err(error) = stream.play() {
+                error!("{}", error);
+                return Err(());
+            }
+        }
+        return Ok(());
+    }
+
+    fn pause(&mut self) {
+        self.shared.playing.store(false, Ordering::Relaxed);
+        if let Some(stream) = &self.stream {
+            let _ = stream.pause();
+        }
+    }
+
+    fn stop(&mut self) {
+        self.pause();
+        self.stop_decoder();
+        self.shared.restart_at(0);
+        self.start_time = 0;
+        self.started = false;
+    }
+
+    fn state(&self) -> EngineState {
+        if !self.started {
+            return EngineState::Stopped;
+        } else if self.shared.ended.load(Ordering::Relaxed) {
+            return EngineState::Ended;
+        } else if self.shared.playing.load(Ordering::Relaxed) {
+            return EngineState::Playing;
+        }
+        return EngineState::Paused;
+    }
+
+    fn is_playing(&self) -> bool {
+        return self.state() == EngineState::Playing;
+    }
+
+    fn get_time(&self) -> Option<i64> {
+        if !self.started {
+            return None;
+        }
+
+        let rate = self.shared.output_rate.load(Ordering::Relaxed).max(1) as u64;
+        let played = self.shared.frames_played.load(Ordering::Relaxed) * 1000 / rate;
+        return Some(self.shared.base_time.load(Ordering::Relaxed) + played as i64);
+    }
+
+    fn set_time(&mut self, time: i64) {
+        if self.started && !self.shared.ended.load(Ordering::Relaxed) {
+            *self.shared.seek_to.lock().unwrap() = Some(time);
+        }
+    }
+
+    fn get_length(&self) -> Option<i64> {
+        let length = self.shared.length.load(Ordering::Relaxed);
+        if length < 0 {
+            return None;
+        }
+        return Some(length);
+    }
+
+    fn get_volume(&self) -> i32 {
+        return self.volume;
+    }
+
+    // Same scale as libvlc, 100 is 0 dB
+    fn set_volume(&mut self, volume: i32) -> Result<(), ()> {
+        if volume < 0 || volume > 200 {
+            return Err(());
+        }
+        self.volume = volume;
+        self.shared
+            .gain
+            .store((volume as f32 / 100.0).to_bits(), Ordering::Relaxed);
+        return Ok(());
+    }
+
+    fn get_mute(&self) -> bool {
+        return self.shared.muted.load(Ordering::Relaxed);
+    }
+
+    fn set_mute(&mut self, muted: bool) {
+        self.shared.muted.store(muted, Ordering::Relaxed);
+    }
+
+    fn set_output(&mut self, output: &Output) {
+        self.device_name = if output.device == "" {
+            None
+        } else {
+            Some(output.device.clone())
+        };
+        self.reopen_stream();
+    }
+
+    fn set_output_device(&mut self, output: &Output) {
+        self.set_output(output);
+    }
+
+    // The default device of the host followed by all devices
+    fn get_outputs(&self) -> Vec<Output> {
+        let module = self.host.id().name().to_string();
+        let mut outputs: Vec<Output> = vec![Output {
+            id: 0,
+            name: format!("Default ({})", module),
+            module: module.clone(),
+            device: "".to_string(),
+        }];
+
+        match self.host.output_devices() {
+            Ok(devices) => {
+                for device in devices {
+                    match device.name() {
+                        Ok(name) => {
+                            outputs.push(Output {
+                                id: outputs.len() as i64,
+                                name: name.clone(),
+                                module: module.clone(),
+                                device: name,
+                            });
+                        }
+                        Err(error) => {
+                            error!("{}", error);
+                        }
+                    }
+                }
+            }
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+
+        return outputs;
+    }
+
+    fn on_event<F: Fn(EngineEvent) + Send + Sync + 'static>(&self, callback: F) {
+        self.shared
+            .callbacks
+            .lock()
+            .unwrap()
+            .push(Box::new(callback));
+    }
+}
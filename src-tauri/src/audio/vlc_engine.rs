@@ -0,0 +1,279 @@
+// Playback with libvlc
+use super::engine::{EngineEvent, EngineState, PlaybackEngine};
+use crate::defs::{Gapless, Output};
+use log::error;
+use std::ffi::{c_char, CStr, CString};
+use std::rc::Rc;
+use std::sync::Arc;
+use vlc::{Event, EventType, Instance, Media, MediaPlayer, MediaPlayerAudioEx, State};
+
+fn add_media_option(media: &Media, option: &str) {
+    match CString::new(option) {
+        Ok(c_option) => unsafe {
+            vlc::sys::libvlc_media_add_option(media.raw(), c_option.as_ptr());
+        },
+        Err(error) => {
+            error!("{}", error);
+        }
+    }
+}
+
+// Start and stop time in seconds without encoder delay and padding, None if the track has neither
+fn get_gapless_times(gapless: &Gapless) -> Option<(f64, Option<f64>)> {
+    if gapless.sample_rate <= 0 || (gapless.encoder_delay <= 0 && gapless.encoder_padding <= 0) {
+        return None;
+    }
+    let sample_rate = gapless.sample_rate as f64;
+
+    let start_time = gapless.encoder_delay.max(0) as f64 / sample_rate;
+    let stop_time = if gapless.encoder_padding > 0 && gapless.frame_count > gapless.encoder_padding
+    {
+        Some((gapless.frame_count - gapless.encoder_padding) as f64 / sample_rate)
+    } else {
+        None
+    };
+
+    return Some((start_time, stop_time));
+}
+
+// Media that starts at position in ms, set_time fails for media that has not been played yet
+fn new_media_at(
+    instance: &Instance,
+    location: &str,
+    gapless: Option<&Gapless>,
+    position: i64,
+) -> Media {
+    let media = Media::new_path(instance, location).unwrap();
+
+    let mut start_time = position.max(0) as f64 / 1000.0;
+
+    // Skip encoder delay and padding, libvlc would play them as silence between tracks
+    if let Some((gapless_start_time, opt_stop_time)) = gapless.and_then(get_gapless_times) {
+        start_time = start_time.max(gapless_start_time);
+        if let Some(stop_time) = opt_stop_time {
+            add_media_option(&media, &format!(":stop-time={:.3}", stop_time));
+        }
+    }
+
+    if start_time > 0.0 {
+        add_media_option(&media, &format!(":start-time={:.3}", start_time));
+    }
+
+    return media;
+}
+
+fn get_c_string(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return "".to_string();
+    }
+    return unsafe { CStr::from_ptr(ptr) }
+        .to_string_lossy()
+        .into_owned();
+}
+
+// Output modules that do not play to an audio device
+static IGNORED_OUTPUT_MODULES: [&str; 3] = ["adummy", "afile", "amem"];
+
+/*
+All audio output modules of libvlc with their devices.
+A module without a device list is added once and plays to its default device.
+ */
+fn get_outputs(instance: &Instance) -> Vec<Output> {
+    let mut outputs: Vec<Output> = vec![];
+
+    unsafe {
+        let module_list = vlc::sys::libvlc_audio_output_list_get(instance.raw());
+        let mut module = module_list;
+
+        while !module.is_null() {
+            let module_name = get_c_string((*module).psz_name);
+            let module_description = get_c_string((*module).psz_description);
+            module = (*module).p_next;
+
+            if IGNORED_OUTPUT_MODULES.contains(&module_name.as_str()) {
+                continue;
+            }
+
+            let mut has_devices = false;
+
+            match CString::new(module_name.clone()) {
+                Ok(c_module_name) => {
+                    let device_list = vlc::sys::libvlc_audio_output_device_list_get(
+                        instance.raw(),
+                        c_module_name.as_ptr(),
+                    );
+                    let mut device = device_list;
+
+                    while !device.is_null() {
+                        outputs.push(Output {
+                            id: outputs.len() as i64,
+                            name: format!(
+                                "{} ({})",
+                                get_c_string((*device).psz_description),
+                                module_description
+                            ),
+                            module: module_name.clone(),
+                            device: get_c_string((*device).psz_device),
+                        });
+                        has_devices = true;
+                        device = (*device).p_next;
+                    }
+
+                    if !device_list.is_null() {
+                        vlc::sys::libvlc_audio_output_device_list_release(device_list);
+                    }
+                }
+                Err(error) => {
+                    error!("{}", error);
+                }
+            }
+
+            if !has_devices {
+                outputs.push(Output {
+                    id: outputs.len() as i64,
+                    name: module_description,
+                    module: module_name,
+                    device: "".to_string(),
+                });
+            }
+        }
+
+        if !module_list.is_null() {
+            vlc::sys::libvlc_audio_output_list_release(module_list);
+        }
+    }
+
+    return outputs;
+}
+
+pub struct VlcEngine {
+    instance: Rc<Instance>,
+    player: MediaPlayer,
+}
+
+impl VlcEngine {
+    pub fn new(instance: &Rc<Instance>) -> VlcEngine {
+        return VlcEngine {
+            instance: instance.clone(),
+            player: MediaPlayer::new(instance).unwrap(),
+        };
+    }
+}
+
+impl PlaybackEngine for VlcEngine {
+    fn set_media(&mut self, location: &str, gapless: Option<&Gapless>, position: i64) {
+        self.player
+            .set_media(&new_media_at(&self.instance, location, gapless, position));
+    }
+
+    fn play(&mut self) -> Result<(), ()> {
+        return self.player.play();
+    }
+
+    fn pause(&mut self) {
+        self.player.pause();
+    }
+
+    fn stop(&mut self) {
+        self.player.stop();
+    }
+
+    fn state(&self) -> EngineState {
+        match self.player.state() {
+            State::Playing => return EngineState::Playing,
+            State::Paused => return EngineState::Paused,
+            State::Buffering => return EngineState::Buffering,
+            State::Ended => return EngineState::Ended,
+            _ => return EngineState::Stopped,
+        }
+    }
+
+    fn is_playing(&self) -> bool {
+        return self.player.is_playing();
+    }
+
+    fn get_time(&self) -> Option<i64> {
+        return self.player.get_time();
+    }
+
+    fn set_time(&mut self, time: i64) {
+        self.player.set_time(time);
+    }
+
+    fn get_length(&self) -> Option<i64> {
+        return self.player.get_length();
+    }
+
+    fn get_volume(&self) -> i32 {
+        return self.player.get_volume();
+    }
+
+    fn set_volume(&mut self, volume: i32) -> Result<(), ()> {
+        return self.player.set_volume(volume);
+    }
+
+    fn get_mute(&self) -> bool {
+        return self.player.get_mute().unwrap_or_default();
+    }
+
+    fn set_mute(&mut self, muted: bool) {
+        self.player.set_mute(muted);
+    }
+
+    fn set_output(&mut self, output: &Output) {
+        match (
+            CString::new(output.module.clone()),
+            CString::new(output.device.clone()),
+        ) {
+            (Ok(c_module), Ok(c_device)) => unsafe {
+                vlc::sys::libvlc_audio_output_set(self.player.raw(), c_module.as_ptr());
+                if output.device != "" {
+                    vlc::sys::libvlc_audio_output_device_set(
+                        self.player.raw(),
+                        c_module.as_ptr(),
+                        c_device.as_ptr(),
+                    );
+                }
+            },
+            (Err(error), _) | (_, Err(error)) => {
+                error!("{}", error);
+            }
+        }
+    }
+
+    fn set_output_device(&mut self, output: &Output) {
+        match CString::new(output.device.clone()) {
+            Ok(c_device) => unsafe {
+                vlc::sys::libvlc_audio_output_device_set(
+                    self.player.raw(),
+                    std::ptr::null(),
+                    c_device.as_ptr(),
+                );
+            },
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+
+    fn get_outputs(&self) -> Vec<Output> {
+        return get_outputs(&self.instance);
+    }
+
+    fn on_event<F: Fn(EngineEvent) + Send + Sync + 'static>(&self, callback: F) {
+        let events = self.player.event_manager();
+        let callback = Arc::new(callback);
+
+        let end_callback = callback.clone();
+        let _ = events.attach(EventType::MediaPlayerEndReached, move |e, _| match e {
+            Event::MediaPlayerEndReached => end_callback(EngineEvent::EndReached),
+            _ => {}
+        });
+
+        let changed_callback = callback.clone();
+        let _ = events.attach(EventType::MediaPlayerMediaChanged, move |e, _| match e {
+            Event::MediaPlayerMediaChanged => changed_callback(EngineEvent::MediaChanged),
+            _ => {}
+        });
+    }
+}
@@ -1,4 +1,7 @@
-use crate::defs::{ConfigState, Language, Theme, ThemeColors, Version, View};
+use crate::defs::{
+    ConfigState, Language, PlaybackBackend, PlaylistPaths, ReplayGainMode, Theme, ThemeColors,
+    Version, View,
+};
 use directories::UserDirs;
 use log::{debug, error};
 use std::fs::{self, File};
@@ -119,6 +122,12 @@ pub fn create_config(mut config_path: PathBuf) -> Result<ConfigState, String> {
         allow_delete_from_db: false,
         allow_delete_files: false,
         is_new: true,
+        crossfade_ms: 0,
+        replaygain_mode: ReplayGainMode::Off,
+        playback_backend: PlaybackBackend::Vlc,
+        playlist_export_paths: PlaylistPaths::Absolute,
+        write_tags: false,
+        write_ratings: false,
     };
 
     match serde_json::to_string(&default_config) {
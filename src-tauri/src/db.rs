@@ -1,34 +1,60 @@
-mod metadata;
+pub mod cover_store;
+mod library_xml;
+mod loudness;
+pub mod metadata;
+mod playlist_files;
+mod smart;
+mod tag_writer;
 
 use crate::{
     config::{load_or_setup_config_path, set_config},
     defs::{
         Album, Artist, ArtistAlbums, ArtistTracks, AudioTrack, BackendMessage, Composer,
-        ComposerTracks, ConfigState, Cover, DBData, DBPlaylist, DBRequest, DBState, DBTrack, Data,
-        DataType, Genre, GenreTracks, Image, Notification, Order, Playlist, Progress, ProgressInfo,
-        Search, SpaceTime, Track,
+        ComposerTracks, ConfigState, DBData, DBPlaylist, DBRequest, DBState, DBTrack, Data,
+        DataType, Gapless, Genre, GenreTracks, Image, ImportReport, Notification, Order,
+        PlaySource, PlayStats, PlayedTrack, Playlist, Progress, ProgressInfo, ReplayGain, Search,
+        SmartCriteria, SpaceTime, Track, Warning,
     },
 };
 use base64::{engine::general_purpose, Engine as _};
+use cover_store::{covers_dir, load_cover, store_cover};
+use library_xml::{
+    location_to_url, persistent_id, write_library_xml, LibraryPlaylist, LibraryTrack, PlistValue,
+};
 use log::{debug, error};
-use metadata::extract_metadata;
-use quick_xml::{escape::unescape, events::Event, reader::Reader};
+use loudness::{get_gated_loudness, get_replaygain, measure_loudness};
+use metadata::{
+    choose_cover_file, extract_gapless_info, extract_metadata, find_cover_file, is_image_file,
+    read_cover_file,
+};
+use playlist_files::{is_playlist_file, read_playlist_file, write_playlist_file, PlaylistEntry};
+use quick_xml::{
+    escape::unescape,
+    events::{BytesRef, Event},
+    reader::Reader,
+};
 use regex::Regex;
-use sqlite::{self, Connection, State};
+use smart::{apply_smart_limit, decode_smart_criteria, smart_criteria_to_sql};
+use sqlite::{self, Connection, State, Statement};
 use std::{
     collections::{HashMap, HashSet},
     fs,
     io::BufReader,
     path::{Path, PathBuf},
-    sync::mpsc::{Receiver, Sender},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
     thread,
     time::{SystemTime, UNIX_EPOCH},
 };
+use tag_writer::{write_rating, write_tags, TagUpdate};
 use tauri::{AppHandle, Emitter, Manager};
 use urlencoding;
 
 static DB_MAJOR: i64 = 1;
-static DB_MINOR: i64 = 0;
+static DB_MINOR: i64 = 10;
 static DB_PATCH: i64 = 0;
 
 fn get_db_state(conn: &Connection) -> DBState {
@@ -222,7 +248,12 @@ fn get_or_create_album_id(
     return 0;
 }
 
-fn get_or_create_cover_id(conn: &Connection, album_id: i64, cover: &Image) -> i64 {
+fn get_or_create_cover_id(
+    conn: &Connection,
+    covers_dir: &Path,
+    album_id: i64,
+    cover: &Image,
+) -> i64 {
     let mut stmt = conn
         .prepare("SELECT cover_id FROM Albums WHERE album_id = ?1")
         .unwrap();
@@ -233,22 +264,28 @@ fn get_or_create_cover_id(conn: &Connection, album_id: i64, cover: &Image) -> i6
         if cover_id != 0 {
             return cover_id;
         } else {
-            let b64_string = cover_as_base64(cover);
+            let hash = match store_cover(covers_dir, cover) {
+                Ok(hash) => hash,
+                Err(error) => {
+                    error!("{}", error);
+                    return 0;
+                }
+            };
 
-            let mut insert_stmt = conn
-                .prepare("INSERT INTO Covers (album_id, base64) VALUES (?1, ?2)")
-                .unwrap();
-            insert_stmt.bind((1, album_id)).unwrap();
-            insert_stmt.bind((2, b64_string.as_str())).unwrap();
-            insert_stmt.next().unwrap();
+            // Albums with the same image share the cover
+            let mut cover_id = get_cover_id_by_hash(conn, &hash);
+            if cover_id == 0 {
+                let mut insert_stmt = conn
+                    .prepare("INSERT INTO Covers (album_id, hash) VALUES (?1, ?2)")
+                    .unwrap();
+                insert_stmt.bind((1, album_id)).unwrap();
+                insert_stmt.bind((2, hash.as_str())).unwrap();
+                insert_stmt.next().unwrap();
 
-            let mut stmt = conn
-                .prepare("SELECT cover_id FROM Covers WHERE album_id = ?1")
-                .unwrap();
-            stmt.bind((1, album_id)).unwrap();
+                cover_id = get_cover_id_by_hash(conn, &hash);
+            }
 
-            if let Ok(State::Row) = stmt.next() {
-                let cover_id = stmt.read::<i64, _>("cover_id").unwrap_or_default();
+            if cover_id != 0 {
                 let mut insert_stmt = conn
                     .prepare("UPDATE Albums SET cover_id = ?1 WHERE album_id = ?2")
                     .unwrap();
@@ -264,6 +301,19 @@ fn get_or_create_cover_id(conn: &Connection, album_id: i64, cover: &Image) -> i6
     return 0;
 }
 
+fn get_cover_id_by_hash(conn: &Connection, hash: &str) -> i64 {
+    let mut stmt = conn
+        .prepare("SELECT cover_id FROM Covers WHERE hash = ?1")
+        .unwrap();
+    stmt.bind((1, hash)).unwrap();
+
+    if let Ok(State::Row) = stmt.next() {
+        return stmt.read::<i64, _>("cover_id").unwrap_or_default();
+    }
+
+    return 0;
+}
+
 fn fix_umlauts(text: String) -> String {
     // TODO: differences between host systems? Below is working and required for NixOS with BTRFS.
     // Replace umlaut escape sequence with the single byte umlaut, where possible.
@@ -311,10 +361,22 @@ fn fix_umlauts(text: String) -> String {
     return result;
 }
 
-fn extract_tracks_and_playlists(xml_path_string: String, conn: &Connection) {
-    // TODO: sanity checks to ensure this is a library.xml file
+fn extract_tracks_and_playlists(xml_path_string: String, conn: &Connection) -> ImportReport {
+    let mut report = ImportReport::default();
+
     // TODO: differences in versions we need to cover?
-    let mut reader = Reader::from_file(xml_path_string).unwrap();
+    if let Err(error) = validate_library_xml(&xml_path_string) {
+        report.error = Some(error);
+        return report;
+    }
+
+    let mut reader = match Reader::from_file(&xml_path_string) {
+        Ok(reader) => reader,
+        Err(error) => {
+            report.error = Some(error.to_string());
+            return report;
+        }
+    };
     // Do not trim, otherwise spaces between text and special chars are removed...
     //reader.config_mut().trim_text(true);
 
@@ -327,23 +389,35 @@ fn extract_tracks_and_playlists(xml_path_string: String, conn: &Connection) {
 
     loop {
         match reader.read_event_into(&mut buf) {
-            Err(e) => panic!("Error at position {}: {:?}", reader.error_position(), e),
+            Err(e) => {
+                report.error = Some(format!(
+                    "Error at position {}: {:?}",
+                    reader.error_position(),
+                    e
+                ));
+                break;
+            }
             Ok(Event::Eof) => break,
             Ok(Event::Start(e)) => {
                 if extract {
                     extract = false;
 
                     if str::from_utf8(e.name().as_ref()).unwrap() == "dict" && found_tracks {
-                        extract_and_insert_tracks(&mut reader, &mut depth, conn);
+                        extract_and_insert_tracks(&mut reader, &mut depth, conn, &mut report);
                         found_tracks = false;
                     }
 
                     if str::from_utf8(e.name().as_ref()).unwrap() == "array" && found_playlists {
-                        extract_and_insert_playlists(&mut reader, &mut depth, conn);
+                        extract_and_insert_playlists(&mut reader, &mut depth, conn, &mut report);
+                        link_playlist_parents(conn);
                         found_playlists = false;
                     }
                 }
 
+                if report.error.is_some() {
+                    break;
+                }
+
                 if depth == 2 && str::from_utf8(e.name().as_ref()).unwrap() == "key" {
                     is_tracks_or_playlist_depth = true;
                 } else if depth != 2 {
@@ -353,11 +427,11 @@ fn extract_tracks_and_playlists(xml_path_string: String, conn: &Connection) {
             }
             Ok(Event::Text(e)) => {
                 if is_tracks_or_playlist_depth {
-                    if e.decode().unwrap().into_owned() == "Tracks" {
+                    if e.decode().unwrap_or_default().into_owned() == "Tracks" {
                         println!("Found Tracks at depth: {}", depth);
                         found_tracks = true;
                     }
-                    if e.decode().unwrap().into_owned() == "Playlists" {
+                    if e.decode().unwrap_or_default().into_owned() == "Playlists" {
                         println!("Found Playlists at depth: {}", depth);
                         found_playlists = true;
                     }
@@ -378,12 +452,15 @@ fn extract_tracks_and_playlists(xml_path_string: String, conn: &Connection) {
         // clear the buffer to keep memory usage low
         buf.clear();
     }
+
+    return report;
 }
 
 fn extract_and_insert_tracks(
     reader: &mut Reader<BufReader<fs::File>>,
     depth: &mut usize,
     conn: &Connection,
+    report: &mut ImportReport,
 ) {
     let initial_depth = depth.clone();
     println!(
@@ -395,19 +472,35 @@ fn extract_and_insert_tracks(
     let mut next_is_id = false;
     let mut next_dict_is_track = false;
 
+    // Persistent ids already in the library, importing the same library twice would duplicate every track
+    let mut persistent_ids: HashSet<String> = HashSet::new();
+    let mut stmt = conn
+        .prepare("SELECT persistent_id FROM Tracks WHERE persistent_id != ''")
+        .unwrap();
+    while let Ok(State::Row) = stmt.next() {
+        persistent_ids.insert(stmt.read::<String, _>("persistent_id").unwrap_or_default());
+    }
+
     let mut buf = Vec::new();
     let mut tracks: Vec<DBTrack> = Vec::new();
     loop {
         // SQLite 3.32.0 can handle up to 32766 variables
-        // Imported track has 31, thus max 1056 tracks can be batch imported
+        // Imported track has 44, thus max 744 tracks can be batch imported
         // Leaving some headroom
-        if tracks.len() > 1000 {
+        if tracks.len() > 700 {
             insert_tracks_batch(conn, tracks.clone(), true);
             tracks.clear();
         }
 
         match reader.read_event_into(&mut buf) {
-            Err(e) => panic!("Error at position {}: {:?}", reader.error_position(), e),
+            Err(e) => {
+                report.error = Some(format!(
+                    "Error at position {}: {:?}",
+                    reader.error_position(),
+                    e
+                ));
+                break;
+            }
             Ok(Event::Eof) => break,
             Ok(Event::Start(e)) => {
                 *depth += 1;
@@ -417,15 +510,29 @@ fn extract_and_insert_tracks(
                 if str::from_utf8(e.name().as_ref()).unwrap() == "dict" && next_dict_is_track {
                     next_dict_is_track = false;
 
-                    if let Some(track) = extract_track(reader, depth, &current_track_id, conn) {
+                    if let Some(track) = extract_track(
+                        reader,
+                        depth,
+                        &current_track_id,
+                        conn,
+                        report,
+                        &mut persistent_ids,
+                    ) {
+                        if !track.location.is_empty() && !Path::new(&track.location).exists() {
+                            report.missing_files.push(track.location.clone());
+                        }
+                        report.tracks_imported += 1;
                         tracks.push(track);
                     }
+                    if report.error.is_some() {
+                        break;
+                    }
                 }
             }
             Ok(Event::Text(e)) => {
                 if next_is_id {
                     next_is_id = false;
-                    current_track_id = e.decode().unwrap().into_owned();
+                    current_track_id = e.decode().unwrap_or_default().into_owned();
                     /*println!("track id: {}", current_track_id);*/
                     next_dict_is_track = true;
                 }
@@ -452,17 +559,20 @@ fn extract_track(
     depth: &mut usize,
     track_id: &String,
     conn: &Connection,
+    report: &mut ImportReport,
+    persistent_ids: &mut HashSet<String>,
 ) -> Option<DBTrack> {
     let initial_depth = depth.clone();
 
     let mut track = DBTrack {
         orig_track_id: 0,
         name: "".to_string(),
-        artist_id: get_or_create_artist_id(conn, "", ""),
-        album_artist_id: get_or_create_artist_id(conn, "", ""),
-        composer_id: get_or_create_composer_id(conn, ""),
+        // Ids are set once the track is known not to be a duplicate
+        artist_id: 0,
+        album_artist_id: 0,
+        composer_id: 0,
         album_id: 0, // Only create empty album if necessary
-        genre_id: get_or_create_genre_id(conn, ""),
+        genre_id: 0,
         kind: "".to_string(),
         size: 0,
         total_time: 0,
@@ -490,6 +600,30 @@ fn extract_track(
         location: "".to_string(),
         file_folder_count: 0,
         library_folder_count: 0,
+        replaygain: ReplayGain::default(),
+        gapless: None, // probed once the import is done
+        rating: 0,
+        loved: 0,
+        plays: 0,
+        skips: 0,
+        play_date: "".to_string(),
+        skip_date: "".to_string(),
+        comments: "".to_string(),
+        grouping: "".to_string(),
+        bpm: 0,
+        volume_adjustment: 0,
+        start_time: 0,
+        stop_time: 0,
+        compilation: 0,
+        lyrics: "".to_string(),
+        isrc: "".to_string(),
+        label: "".to_string(),
+        original_date: "".to_string(),
+        musicbrainz_track_id: "".to_string(),
+        musicbrainz_album_id: "".to_string(),
+        musicbrainz_artist_id: "".to_string(),
+        musicbrainz_album_artist_id: "".to_string(),
+        musicbrainz_release_group_id: "".to_string(),
     };
 
     // Create placeholders
@@ -498,6 +632,16 @@ fn extract_track(
     let mut artist = "".to_string();
     let mut sort_artist = "".to_string();
     let mut album_artist = "".to_string();
+    let mut composer = "".to_string();
+    let mut genre = "".to_string();
+    // Computed ratings are derived by iTunes from the other ratings, thus not imported
+    let mut rating_computed = false;
+    let mut album_rating = 0;
+    let mut album_rating_computed = false;
+    let mut album_loved = 0;
+
+    // Key of the first malformed value, the track is skipped then
+    let mut invalid_key: Option<String> = None;
 
     let mut current_key = "".to_string();
     let mut current_text = "".to_string();
@@ -506,7 +650,14 @@ fn extract_track(
     let mut buf = Vec::new();
     loop {
         match reader.read_event_into(&mut buf) {
-            Err(e) => panic!("Error at position {}: {:?}", reader.error_position(), e),
+            Err(e) => {
+                report.error = Some(format!(
+                    "Error at position {}: {:?}",
+                    reader.error_position(),
+                    e
+                ));
+                break;
+            }
             Ok(Event::Eof) => break,
             Ok(Event::Start(e)) => {
                 *depth += 1;
@@ -557,6 +708,42 @@ fn extract_track(
                             }
                             _ => {}
                         },
+                        "Rating Computed" => {
+                            rating_computed = str::from_utf8(e.name().as_ref()).unwrap() == "true";
+                        }
+                        "Album Rating Computed" => {
+                            album_rating_computed =
+                                str::from_utf8(e.name().as_ref()).unwrap() == "true";
+                        }
+                        "Loved" => {
+                            if str::from_utf8(e.name().as_ref()).unwrap() == "true" {
+                                track.loved = 1;
+                            }
+                        }
+                        "Disliked" => {
+                            if str::from_utf8(e.name().as_ref()).unwrap() == "true" {
+                                track.loved = -1;
+                            }
+                        }
+                        "Album Loved" => {
+                            if str::from_utf8(e.name().as_ref()).unwrap() == "true" {
+                                album_loved = 1;
+                            }
+                        }
+                        "Album Disliked" => {
+                            if str::from_utf8(e.name().as_ref()).unwrap() == "true" {
+                                album_loved = -1;
+                            }
+                        }
+                        "Compilation" => match str::from_utf8(e.name().as_ref()).unwrap() {
+                            "true" => {
+                                track.compilation = 1;
+                            }
+                            "false" => {
+                                track.compilation = 0;
+                            }
+                            _ => {}
+                        },
                         _ => {}
                     }
                 }
@@ -570,7 +757,7 @@ fn extract_track(
                     The special char is stripped of the "&" and ";" and can only be decoded with resolve_char_ref().
                      */
 
-                    let text = &e.decode().unwrap().into_owned();
+                    let text = &e.decode().unwrap_or_default().into_owned();
 
                     if let Ok(escaped) = unescape(&text) {
                         current_text.push_str(&escaped);
@@ -582,7 +769,7 @@ fn extract_track(
                     }
                 }
                 if next_is_key {
-                    current_key = e.decode().unwrap().into_owned().to_string();
+                    current_key = e.decode().unwrap_or_default().into_owned().to_string();
                 }
             }
             Ok(Event::GeneralRef(e)) => {
@@ -594,16 +781,7 @@ fn extract_track(
                     The special char is stripped of the "&" and ";" and can only be decoded with resolve_char_ref().
                      */
 
-                    if let Some(text) = e.resolve_char_ref().unwrap() {
-                        current_text.push(text);
-                    } else if let Ok(escaped) = unescape(&e.decode().unwrap().into_owned()) {
-                        current_text.push_str(&escaped);
-                    } else {
-                        let extended = format!("&{};", &e.decode().unwrap().into_owned());
-                        if let Ok(escaped) = unescape(&extended) {
-                            current_text.push_str(&escaped);
-                        }
-                    }
+                    push_general_ref(&e, &mut current_text);
                 }
             }
             Ok(Event::End(_)) => {
@@ -612,7 +790,8 @@ fn extract_track(
 
                     match current_key.as_str() {
                         "Track ID" => {
-                            track.orig_track_id = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.orig_track_id =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Name" => {
                             track.name = current_text;
@@ -624,37 +803,44 @@ fn extract_track(
                             album_artist = current_text;
                         }
                         "Composer" => {
-                            track.composer_id = get_or_create_composer_id(conn, &current_text);
+                            composer = current_text;
                         }
                         "Album" => {
                             album = current_text;
                         }
                         "Genre" => {
-                            track.genre_id = get_or_create_genre_id(conn, &current_text);
+                            genre = current_text;
                         }
                         "Kind" => {
                             track.kind = current_text;
                         }
                         "Size" => {
-                            track.size = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.size =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Total Time" => {
-                            track.total_time = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.total_time =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Disc Number" => {
-                            track.disc_number = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.disc_number =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Disc Count" => {
-                            track.disc_count = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.disc_count =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Track Number" => {
-                            track.track_number = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.track_number =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Track Count" => {
-                            track.track_count = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.track_count =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Year" => {
-                            track.year = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.year =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Date Modified" => {
                             track.date_modified = current_text;
@@ -663,16 +849,19 @@ fn extract_track(
                             track.date_added = current_text;
                         }
                         "Bit Rate" => {
-                            track.bit_rate = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.bit_rate =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Sample Rate" => {
-                            track.sample_rate = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.sample_rate =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Release Date" => {
                             track.release_date = current_text;
                         }
                         "Normalization" => {
-                            track.normalization = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.normalization =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Sort Album" => {
                             sort_album = current_text;
@@ -684,7 +873,8 @@ fn extract_track(
                             track.sort_name = current_text;
                         }
                         "Artwork Count" => {
-                            track.artwork_count = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.artwork_count =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Persistent ID" => {
                             track.persistent_id = current_text;
@@ -693,24 +883,76 @@ fn extract_track(
                             track.track_type = current_text;
                         }
                         "Video Width" => {
-                            track.video_width = i64::from_str_radix(&current_text, 10).unwrap();
+                            track.video_width =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Video Height" => {
-                            track.video_height = i64::from_str_radix(&current_text, 10).unwrap();
-                        }
-                        "Location" => {
-                            track.location = fix_umlauts(
-                                urlencoding::decode(&current_text).unwrap().to_string(),
-                            )
-                            .replace("file://", "");
+                            track.video_height =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
+                        "Location" => match urlencoding::decode(&current_text) {
+                            Ok(location) => {
+                                track.location =
+                                    fix_umlauts(location.to_string()).replace("file://", "");
+                            }
+                            // Escapes that do not decode to UTF-8
+                            Err(_) => {
+                                if invalid_key.is_none() {
+                                    invalid_key = Some(current_key.clone());
+                                }
+                            }
+                        },
                         "File Folder Count" => {
                             track.file_folder_count =
-                                i64::from_str_radix(&current_text, 10).unwrap();
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Library Folder Count" => {
                             track.library_folder_count =
-                                i64::from_str_radix(&current_text, 10).unwrap();
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
+                        }
+                        "Rating" => {
+                            track.rating =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
+                        }
+                        "Album Rating" => {
+                            album_rating =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
+                        }
+                        "Play Count" => {
+                            track.plays =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
+                        }
+                        "Play Date UTC" => {
+                            track.play_date = current_text;
+                        }
+                        "Skip Count" => {
+                            track.skips =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
+                        }
+                        "Skip Date" => {
+                            track.skip_date = current_text;
+                        }
+                        "Comments" => {
+                            track.comments = current_text;
+                        }
+                        "Grouping" => {
+                            track.grouping = current_text;
+                        }
+                        "BPM" => {
+                            track.bpm =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
+                        }
+                        "Volume Adjustment" => {
+                            track.volume_adjustment =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
+                        }
+                        "Start Time" => {
+                            track.start_time =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
+                        }
+                        "Stop Time" => {
+                            track.stop_time =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         _ => {}
                     }
@@ -734,6 +976,34 @@ fn extract_track(
         buf.clear();
     }
 
+    if report.error.is_some() {
+        return None;
+    }
+
+    if let Some(key) = invalid_key {
+        report
+            .skipped
+            .push((track_id.clone(), format!("invalid value for {}", key)));
+        return None;
+    }
+
+    if i64::from_str_radix(track_id.trim(), 10).ok() != Some(track.orig_track_id) {
+        report.skipped.push((
+            track_id.clone(),
+            "Track ID does not match its key".to_string(),
+        ));
+        return None;
+    }
+
+    // Before creating its artists and album, a duplicate must not leave anything behind
+    if !track.persistent_id.is_empty() && !persistent_ids.insert(track.persistent_id.clone()) {
+        report.duplicate_persistent_ids.push(track.persistent_id);
+        return None;
+    }
+
+    track.composer_id = get_or_create_composer_id(conn, &composer);
+    track.genre_id = get_or_create_genre_id(conn, &genre);
+
     // Retrieving artist_id requires sort_artist, if present
     track.artist_id = get_or_create_artist_id(conn, artist.as_str(), sort_artist.as_str());
 
@@ -764,11 +1034,102 @@ fn extract_track(
         Some((&track.date_modified, &track.date_added)),
     );
 
-    if track.orig_track_id == i64::from_str_radix(&track_id, 10).unwrap() {
-        return Some(track);
+    if rating_computed {
+        track.rating = 0;
     }
 
-    return None;
+    if album_rating_computed {
+        album_rating = 0;
+    }
+
+    // Every track of an album carries the album rating, only set it when there is one
+    if album_rating != 0 || album_loved != 0 {
+        set_album_rating_and_loved(conn, track.album_id, album_rating, album_loved);
+    }
+
+    return Some(track);
+}
+
+// Characters quick_xml reports as references, iTunes writes &#38; while our export writes &amp;
+fn push_general_ref(reference: &BytesRef, text: &mut String) {
+    if let Some(character) = reference.resolve_char_ref().unwrap_or_default() {
+        text.push(character);
+    } else if let Ok(escaped) = unescape(&format!("&{};", reference.decode().unwrap_or_default())) {
+        text.push_str(&escaped);
+    }
+}
+
+// Returns 0 for malformed integers and remembers the first key with one
+fn parse_plist_integer(text: &str, key: &str, invalid_key: &mut Option<String>) -> i64 {
+    match i64::from_str_radix(text.trim(), 10) {
+        Ok(number) => return number,
+        Err(_) => {
+            if invalid_key.is_none() {
+                *invalid_key = Some(key.to_string());
+            }
+            return 0;
+        }
+    }
+}
+
+/*
+Checks that the file is an Apple plist with a top level dict containing Tracks.
+Only the header keys before Tracks are read.
+ */
+fn validate_library_xml(xml_path_string: &str) -> Result<(), String> {
+    let mut reader = Reader::from_file(xml_path_string).map_err(|error| error.to_string())?;
+
+    let mut depth = 0;
+    let mut found_plist = false;
+    let mut found_dict = false;
+    let mut is_top_level_key = false;
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Err(e) => {
+                return Err(format!(
+                    "Error at position {}: {:?}",
+                    reader.error_position(),
+                    e
+                ));
+            }
+            Ok(Event::Eof) => {
+                return Err("no Tracks found in library file".to_string());
+            }
+            Ok(Event::Start(e)) => {
+                let name = e.name();
+                match (depth, name.as_ref()) {
+                    (0, b"plist") => found_plist = true,
+                    (0, _) => return Err("not a plist file".to_string()),
+                    (1, b"dict") => found_dict = true,
+                    (1, _) => return Err("plist does not contain a dict".to_string()),
+                    (2, b"key") => is_top_level_key = true,
+                    _ => {}
+                }
+                depth += 1;
+            }
+            Ok(Event::Text(e)) => {
+                if is_top_level_key
+                    && found_plist
+                    && found_dict
+                    && e.decode().unwrap_or_default() == "Tracks"
+                {
+                    return Ok(());
+                }
+            }
+            Ok(Event::End(_)) => {
+                is_top_level_key = false;
+                if depth == 0 {
+                    return Err("unexpected closing tag".to_string());
+                }
+                depth -= 1;
+            }
+            _ => (),
+        }
+        // clear the buffer to keep memory usage low
+        buf.clear();
+    }
 }
 
 fn insert_tracks_batch(conn: &Connection, tracks: Vec<DBTrack>, import: bool) {
@@ -805,11 +1166,25 @@ fn insert_tracks_batch(conn: &Connection, tracks: Vec<DBTrack>, import: bool) {
                 music_video,
                 location,
                 file_folder_count,
-                library_folder_count
+                library_folder_count,
+                rating,
+                loved,
+                plays,
+                skips,
+                last_played,
+                last_skipped,
+                comments,
+                grouping,
+                bpm,
+                volume_adjustment,
+                start_time,
+                stop_time,
+                compilation
             ) VALUES"
             .to_string();
         let mut values = " (
-                ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
+                ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+                ?, ?, CAST(strftime('%s', ?) AS INTEGER), CAST(strftime('%s', ?) AS INTEGER), ?, ?, ?, ?, ?, ?, ?
             ),".repeat(tracks.len());
         values.pop();
         query.push_str(values.as_str());
@@ -817,7 +1192,7 @@ fn insert_tracks_batch(conn: &Connection, tracks: Vec<DBTrack>, import: bool) {
         let mut stmt = conn.prepare(query).unwrap();
 
         for (index, track) in tracks.iter().enumerate() {
-            let offset = index * 31;
+            let offset = index * 44;
             stmt.bind((offset + 1, track.orig_track_id)).unwrap();
             stmt.bind((offset + 2, track.name.as_str())).unwrap();
             stmt.bind((offset + 3, track.artist_id)).unwrap();
@@ -853,6 +1228,19 @@ fn insert_tracks_batch(conn: &Connection, tracks: Vec<DBTrack>, import: bool) {
             stmt.bind((offset + 30, track.file_folder_count)).unwrap();
             stmt.bind((offset + 31, track.library_folder_count))
                 .unwrap();
+            stmt.bind((offset + 32, track.rating)).unwrap();
+            stmt.bind((offset + 33, track.loved)).unwrap();
+            stmt.bind((offset + 34, track.plays)).unwrap();
+            stmt.bind((offset + 35, track.skips)).unwrap();
+            stmt.bind((offset + 36, track.play_date.as_str())).unwrap();
+            stmt.bind((offset + 37, track.skip_date.as_str())).unwrap();
+            stmt.bind((offset + 38, track.comments.as_str())).unwrap();
+            stmt.bind((offset + 39, track.grouping.as_str())).unwrap();
+            stmt.bind((offset + 40, track.bpm)).unwrap();
+            stmt.bind((offset + 41, track.volume_adjustment)).unwrap();
+            stmt.bind((offset + 42, track.start_time)).unwrap();
+            stmt.bind((offset + 43, track.stop_time)).unwrap();
+            stmt.bind((offset + 44, track.compilation)).unwrap();
         }
 
         let res = stmt.next();
@@ -880,12 +1268,17 @@ fn insert_tracks_batch(conn: &Connection, tracks: Vec<DBTrack>, import: bool) {
                 release_date,
                 has_video,
                 music_video,
-                location
+                location,
+                replaygain_track_gain,
+                replaygain_track_peak,
+                replaygain_album_gain,
+                replaygain_album_peak,
+                rating
             ) VALUES"
             .to_string();
 
         let mut values = " (
-                ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
+                ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
             ),"
         .repeat(tracks.len());
         values.pop();
@@ -894,7 +1287,7 @@ fn insert_tracks_batch(conn: &Connection, tracks: Vec<DBTrack>, import: bool) {
         let mut stmt = conn.prepare(query).unwrap();
 
         for (index, track) in tracks.iter().enumerate() {
-            let offset = index * 21;
+            let offset = index * 26;
             stmt.bind((offset + 1, track.orig_track_id)).unwrap();
             stmt.bind((offset + 2, track.name.as_str())).unwrap();
             stmt.bind((offset + 3, track.artist_id)).unwrap();
@@ -917,6 +1310,15 @@ fn insert_tracks_batch(conn: &Connection, tracks: Vec<DBTrack>, import: bool) {
             stmt.bind((offset + 19, track.has_video)).unwrap();
             stmt.bind((offset + 20, track.music_video)).unwrap();
             stmt.bind((offset + 21, track.location.as_str())).unwrap();
+            stmt.bind((offset + 22, track.replaygain.track_gain))
+                .unwrap();
+            stmt.bind((offset + 23, track.replaygain.track_peak))
+                .unwrap();
+            stmt.bind((offset + 24, track.replaygain.album_gain))
+                .unwrap();
+            stmt.bind((offset + 25, track.replaygain.album_peak))
+                .unwrap();
+            stmt.bind((offset + 26, track.rating)).unwrap();
         }
         let _ = stmt.next();
     }
@@ -957,9 +1359,23 @@ fn insert_track(conn: &Connection, track: DBTrack, import: bool) {
                 music_video,
                 location,
                 file_folder_count,
-                library_folder_count
+                library_folder_count,
+                rating,
+                loved,
+                plays,
+                skips,
+                last_played,
+                last_skipped,
+                comments,
+                grouping,
+                bpm,
+                volume_adjustment,
+                start_time,
+                stop_time,
+                compilation
             ) VALUES (
-                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31, ?32, ?33,
+                ?34, ?35, CAST(strftime('%s', ?36) AS INTEGER), CAST(strftime('%s', ?37) AS INTEGER), ?38, ?39, ?40, ?41, ?42, ?43, ?44
             )",
             )
             .unwrap();
@@ -995,6 +1411,19 @@ fn insert_track(conn: &Connection, track: DBTrack, import: bool) {
         stmt.bind((29, track.location.as_str())).unwrap();
         stmt.bind((30, track.file_folder_count)).unwrap();
         stmt.bind((31, track.library_folder_count)).unwrap();
+        stmt.bind((32, track.rating)).unwrap();
+        stmt.bind((33, track.loved)).unwrap();
+        stmt.bind((34, track.plays)).unwrap();
+        stmt.bind((35, track.skips)).unwrap();
+        stmt.bind((36, track.play_date.as_str())).unwrap();
+        stmt.bind((37, track.skip_date.as_str())).unwrap();
+        stmt.bind((38, track.comments.as_str())).unwrap();
+        stmt.bind((39, track.grouping.as_str())).unwrap();
+        stmt.bind((40, track.bpm)).unwrap();
+        stmt.bind((41, track.volume_adjustment)).unwrap();
+        stmt.bind((42, track.start_time)).unwrap();
+        stmt.bind((43, track.stop_time)).unwrap();
+        stmt.bind((44, track.compilation)).unwrap();
 
         let _ = stmt.next();
     } else {
@@ -1021,9 +1450,32 @@ fn insert_track(conn: &Connection, track: DBTrack, import: bool) {
                 release_date,
                 has_video,
                 music_video,
-                location
+                location,
+                replaygain_track_gain,
+                replaygain_track_peak,
+                replaygain_album_gain,
+                replaygain_album_peak,
+                rating,
+                sort_name,
+                comments,
+                grouping,
+                bpm,
+                compilation,
+                lyrics,
+                isrc,
+                label,
+                original_date,
+                musicbrainz_track_id,
+                musicbrainz_album_id,
+                musicbrainz_artist_id,
+                musicbrainz_album_artist_id,
+                musicbrainz_release_group_id,
+                encoder_delay,
+                encoder_padding,
+                frame_count
             ) VALUES (
-                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26,
+                ?27, ?28, ?29, ?30, ?31, ?32, ?33, ?34, ?35, ?36, ?37, ?38, ?39, ?40, ?41, ?42, ?43
             )",
             )
             .unwrap();
@@ -1049,6 +1501,48 @@ fn insert_track(conn: &Connection, track: DBTrack, import: bool) {
         stmt.bind((19, track.has_video)).unwrap();
         stmt.bind((20, track.music_video)).unwrap();
         stmt.bind((21, track.location.as_str())).unwrap();
+        stmt.bind((22, track.replaygain.track_gain)).unwrap();
+        stmt.bind((23, track.replaygain.track_peak)).unwrap();
+        stmt.bind((24, track.replaygain.album_gain)).unwrap();
+        stmt.bind((25, track.replaygain.album_peak)).unwrap();
+        stmt.bind((26, track.rating)).unwrap();
+        stmt.bind((27, track.sort_name.as_str())).unwrap();
+        stmt.bind((28, track.comments.as_str())).unwrap();
+        stmt.bind((29, track.grouping.as_str())).unwrap();
+        stmt.bind((30, track.bpm)).unwrap();
+        stmt.bind((31, track.compilation)).unwrap();
+        stmt.bind((32, track.lyrics.as_str())).unwrap();
+        stmt.bind((33, track.isrc.as_str())).unwrap();
+        stmt.bind((34, track.label.as_str())).unwrap();
+        stmt.bind((35, track.original_date.as_str())).unwrap();
+        stmt.bind((36, track.musicbrainz_track_id.as_str()))
+            .unwrap();
+        stmt.bind((37, track.musicbrainz_album_id.as_str()))
+            .unwrap();
+        stmt.bind((38, track.musicbrainz_artist_id.as_str()))
+            .unwrap();
+        stmt.bind((39, track.musicbrainz_album_artist_id.as_str()))
+            .unwrap();
+        stmt.bind((40, track.musicbrainz_release_group_id.as_str()))
+            .unwrap();
+        stmt.bind((
+            41,
+            track.gapless.as_ref().map(|gapless| gapless.encoder_delay),
+        ))
+        .unwrap();
+        stmt.bind((
+            42,
+            track
+                .gapless
+                .as_ref()
+                .map(|gapless| gapless.encoder_padding),
+        ))
+        .unwrap();
+        stmt.bind((
+            43,
+            track.gapless.as_ref().map(|gapless| gapless.frame_count),
+        ))
+        .unwrap();
 
         let _ = stmt.next();
     }
@@ -1058,6 +1552,7 @@ fn extract_and_insert_playlists(
     reader: &mut Reader<BufReader<fs::File>>,
     depth: &mut usize,
     conn: &Connection,
+    report: &mut ImportReport,
 ) {
     let initial_depth = depth.clone();
     println!(
@@ -1068,13 +1563,24 @@ fn extract_and_insert_playlists(
     let mut buf = Vec::new();
     loop {
         match reader.read_event_into(&mut buf) {
-            Err(e) => panic!("Error at position {}: {:?}", reader.error_position(), e),
+            Err(e) => {
+                report.error = Some(format!(
+                    "Error at position {}: {:?}",
+                    reader.error_position(),
+                    e
+                ));
+                break;
+            }
             Ok(Event::Eof) => break,
             Ok(Event::Start(e)) => {
                 *depth += 1;
                 if str::from_utf8(e.name().as_ref()).unwrap() == "dict" {
-                    if let Some(playlist) = extract_playlist(reader, depth, conn) {
+                    if let Some(playlist) = extract_playlist(reader, depth, conn, report) {
                         insert_playlist(conn, playlist, true);
+                        report.playlists_imported += 1;
+                    }
+                    if report.error.is_some() {
+                        break;
                     }
                 }
             }
@@ -1096,6 +1602,7 @@ fn extract_playlist(
     reader: &mut Reader<BufReader<fs::File>>,
     depth: &mut usize,
     conn: &Connection,
+    report: &mut ImportReport,
 ) -> Option<DBPlaylist> {
     let initial_depth = depth.clone();
 
@@ -1103,14 +1610,25 @@ fn extract_playlist(
         ..Default::default()
     };
 
+    // Key of the first malformed value, the playlist is skipped then
+    let mut invalid_key: Option<String> = None;
+
     let mut current_key = "".to_string();
+    let mut current_text = "".to_string();
     let mut next_is_key = false;
     let mut next_start_is_value = false;
     let mut next_is_value = false;
     let mut buf = Vec::new();
     loop {
         match reader.read_event_into(&mut buf) {
-            Err(e) => panic!("Error at position {}: {:?}", reader.error_position(), e),
+            Err(e) => {
+                report.error = Some(format!(
+                    "Error at position {}: {:?}",
+                    reader.error_position(),
+                    e
+                ));
+                break;
+            }
             Ok(Event::Eof) => break,
             Ok(Event::Start(e)) => {
                 *depth += 1;
@@ -1127,7 +1645,7 @@ fn extract_playlist(
                     && str::from_utf8(e.name().as_ref()).unwrap() == "array"
                 {
                     next_is_value = false;
-                    playlist.tracks = extract_playlist_tracks(reader, depth, conn);
+                    playlist.tracks = extract_playlist_tracks(reader, depth, conn, report);
                 }
             }
             Ok(Event::Empty(e)) => {
@@ -1176,54 +1694,56 @@ fn extract_playlist(
                 }
             }
             Ok(Event::Text(e)) => {
+                if next_is_value {
+                    // Names with special chars are split into several Text and GeneralRef events
+                    current_text.push_str(&e.decode().unwrap_or_default());
+                }
+                if next_is_key {
+                    current_key = e.decode().unwrap_or_default().into_owned().to_string();
+                }
+            }
+            Ok(Event::GeneralRef(e)) => {
+                if next_is_value {
+                    push_general_ref(&e, &mut current_text);
+                }
+            }
+            Ok(Event::End(_)) => {
                 if next_is_value {
                     next_is_value = false;
 
                     match current_key.as_str() {
                         "Playlist ID" => {
-                            playlist.orig_playlist_id = i64::from_str_radix(
-                                &e.decode().unwrap().into_owned().to_string(),
-                                10,
-                            )
-                            .unwrap();
+                            playlist.orig_playlist_id =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Name" => {
-                            playlist.name = e.decode().unwrap().into_owned().to_string();
+                            playlist.name = current_text.clone();
                         }
                         "Description" => {
-                            playlist.description = e.decode().unwrap().into_owned().to_string();
+                            playlist.description = current_text.clone();
                         }
                         "Playlist Persistent ID" => {
-                            playlist.persistent_id = e.decode().unwrap().into_owned().to_string();
+                            playlist.persistent_id = current_text.clone();
                         }
                         "Parent Persistent ID" => {
-                            playlist.parent_persistent_id =
-                                e.decode().unwrap().into_owned().to_string();
+                            playlist.parent_persistent_id = current_text.clone();
                         }
                         "Distinguished Kind" => {
-                            playlist.distinguished_kind = i64::from_str_radix(
-                                &e.decode().unwrap().into_owned().to_string(),
-                                10,
-                            )
-                            .unwrap();
+                            playlist.distinguished_kind =
+                                parse_plist_integer(&current_text, &current_key, &mut invalid_key);
                         }
                         "Smart Info" => {
                             // TODO: correct? field is data, not string as for others
-                            playlist.smart_info = e.decode().unwrap().into_owned().to_string();
+                            playlist.smart_info = current_text.clone();
                         }
                         "Smart Criteria" => {
                             // TODO: correct? field is data, not string as for others
-                            playlist.smart_criteria = e.decode().unwrap().into_owned().to_string();
+                            playlist.smart_criteria = current_text.clone();
                         }
                         _ => {}
                     }
+                    current_text = "".to_string();
                 }
-                if next_is_key {
-                    current_key = e.decode().unwrap().into_owned().to_string();
-                }
-            }
-            Ok(Event::End(_)) => {
-                next_is_value = false;
                 if next_is_key {
                     next_is_key = false;
                     next_start_is_value = true;
@@ -1240,6 +1760,24 @@ fn extract_playlist(
         buf.clear();
     }
 
+    if report.error.is_some() {
+        return None;
+    }
+
+    if let Some(key) = invalid_key {
+        report
+            .skipped
+            .push((playlist.name, format!("invalid value for {}", key)));
+        return None;
+    }
+
+    if !playlist.persistent_id.is_empty()
+        && playlist_persistent_id_exists(conn, &playlist.persistent_id)
+    {
+        report.duplicate_persistent_ids.push(playlist.persistent_id);
+        return None;
+    }
+
     return Some(playlist);
 }
 
@@ -1247,6 +1785,7 @@ fn extract_playlist_tracks(
     reader: &mut Reader<BufReader<fs::File>>,
     depth: &mut usize,
     conn: &Connection,
+    report: &mut ImportReport,
 ) -> Vec<i64> {
     println!("extract_playlist_tracks");
     let initial_depth = depth.clone();
@@ -1259,7 +1798,14 @@ fn extract_playlist_tracks(
     let mut buf = Vec::new();
     loop {
         match reader.read_event_into(&mut buf) {
-            Err(e) => panic!("Error at position {}: {:?}", reader.error_position(), e),
+            Err(e) => {
+                report.error = Some(format!(
+                    "Error at position {}: {:?}",
+                    reader.error_position(),
+                    e
+                ));
+                break;
+            }
             Ok(Event::Eof) => break,
             Ok(Event::Start(e)) => {
                 *depth += 1;
@@ -1271,10 +1817,12 @@ fn extract_playlist_tracks(
                 if next_is_value {
                     next_is_value = false;
 
-                    orig_track_ids.push(
-                        i64::from_str_radix(&e.decode().unwrap().into_owned().to_string(), 10)
-                            .unwrap(),
-                    );
+                    // Malformed items are left out, the rest of the playlist is kept
+                    if let Ok(orig_track_id) =
+                        i64::from_str_radix(e.decode().unwrap_or_default().trim(), 10)
+                    {
+                        orig_track_ids.push(orig_track_id);
+                    }
                 }
                 if next_is_key {
                     next_is_key = false;
@@ -1317,6 +1865,15 @@ fn extract_playlist_tracks(
     return track_ids;
 }
 
+fn playlist_persistent_id_exists(conn: &Connection, persistent_id: &str) -> bool {
+    let mut stmt = conn
+        .prepare("SELECT playlist_id FROM Playlists WHERE playlist_persistent_id = ?1")
+        .unwrap();
+    stmt.bind((1, persistent_id)).unwrap();
+
+    return matches!(stmt.next(), Ok(State::Row));
+}
+
 fn insert_playlist(conn: &Connection, playlist: DBPlaylist, import: bool) {
     if import {
         let mut stmt = conn
@@ -1367,17 +1924,27 @@ fn insert_playlist(conn: &Connection, playlist: DBPlaylist, import: bool) {
                 "INSERT INTO Playlists (
                 name,
                 description,
-                tracks
+                smart_criteria,
+                tracks,
+                folder,
+                parent_id
             ) VALUES (
-                ?1, ?2, ?3
+                ?1, ?2, ?3, ?4, ?5, ?6
             )",
             )
             .unwrap();
 
         stmt.bind((1, playlist.name.as_str())).unwrap();
         stmt.bind((2, playlist.description.as_str())).unwrap();
-        stmt.bind((3, serde_json::to_string(&playlist.tracks).unwrap().as_str()))
+        stmt.bind((3, playlist.smart_criteria.as_str())).unwrap();
+        stmt.bind((4, serde_json::to_string(&playlist.tracks).unwrap().as_str()))
             .unwrap();
+        stmt.bind((5, playlist.folder)).unwrap();
+        if playlist.parent_id > 0 {
+            stmt.bind((6, playlist.parent_id)).unwrap();
+        } else {
+            stmt.bind((6, ())).unwrap();
+        }
 
         let _ = stmt.next();
     }
@@ -1578,7 +2145,6 @@ fn get_init_data(conn: &Connection) -> Data {
         artist_tracks: Some(get_artist_tracks(conn)),
         composers: Some(get_composers(conn)),
         composer_tracks: Some(get_composer_tracks(conn)),
-        covers: None, // excluded, takes too long... Some(get_covers(conn)),
         playlists: Some(get_playlists(conn)),
         genres: Some(get_genres(conn)),
         genre_tracks: Some(get_genre_tracks(conn)),
@@ -1611,6 +2177,10 @@ fn get_init_data(conn: &Connection) -> Data {
         error: None,
         loading: None,
         is_init: Some(true),
+        top_tracks: None,
+        top_albums: None,
+        top_artists: None,
+        recently_played: None,
     };
 
     /*
@@ -1628,8 +2198,9 @@ fn get_init_data(conn: &Connection) -> Data {
 
 fn get_albums(conn: &Connection) -> Vec<Album> {
     let query = r#"
-    SELECT *
+    SELECT Albums.*, Covers.hash AS cover_hash
     FROM Albums
+    LEFT JOIN Covers ON Albums.cover_id = Covers.cover_id
     "#
     .to_string();
 
@@ -1650,6 +2221,9 @@ fn get_albums(conn: &Connection) -> Vec<Album> {
             date_added: stmt.read::<String, _>("date_added").unwrap_or_default(),
             tracks: vec![],
             cover_id: stmt.read::<i64, _>("cover_id").unwrap_or_default(),
+            cover_hash: stmt.read::<String, _>("cover_hash").unwrap_or_default(),
+            rating: stmt.read::<i64, _>("rating").unwrap_or_default(),
+            loved: stmt.read::<i64, _>("loved").unwrap_or_default(),
         };
 
         let tracks_query = r#"
@@ -1719,67 +2293,6 @@ fn get_composers(conn: &Connection) -> Vec<Composer> {
     return composers;
 }
 
-fn _get_covers(conn: &Connection) -> Vec<Cover> {
-    let query = r#"
-    SELECT *
-    FROM Covers
-    "#
-    .to_string();
-
-    let mut stmt = conn.prepare(query).unwrap();
-
-    let mut covers: Vec<Cover> = Vec::new();
-
-    while let Ok(State::Row) = stmt.next() {
-        let cover = Cover {
-            id: stmt.read::<i64, _>("cover_id").unwrap_or_default(),
-            album_id: stmt.read::<i64, _>("album_id").unwrap_or_default(),
-            data: stmt.read::<String, _>("base64").unwrap_or_default(),
-        };
-
-        covers.push(cover);
-    }
-
-    return covers;
-}
-
-fn get_covers_by_id(conn: &Connection, vec_id: &Vec<i64>) -> Option<Vec<Cover>> {
-    let mut query = r#"
-    SELECT *
-    FROM Covers
-    WHERE cover_id IN (
-    "#
-    .to_string();
-    for _ in vec_id {
-        query.push_str("?,");
-    }
-    query.pop(); // Remove the trailing comma
-    query.push(')');
-
-    let mut stmt = conn.prepare(query).unwrap();
-    for (index, cover_id) in vec_id.iter().enumerate() {
-        stmt.bind((index + 1, cover_id.clone())).unwrap();
-    }
-
-    let mut covers: Vec<Cover> = Vec::new();
-
-    while let Ok(State::Row) = stmt.next() {
-        let cover = Cover {
-            id: stmt.read::<i64, _>("cover_id").unwrap_or_default(),
-            album_id: stmt.read::<i64, _>("album_id").unwrap_or_default(),
-            data: stmt.read::<String, _>("base64").unwrap_or_default(),
-        };
-
-        covers.push(cover);
-    }
-
-    if covers.len() != 0 {
-        return Some(covers);
-    }
-
-    return None;
-}
-
 fn get_genres(conn: &Connection) -> Vec<Genre> {
     let query = r#"
     SELECT *
@@ -1825,6 +2338,11 @@ fn get_tracks(conn: &Connection) -> Vec<Track> {
             total_time: stmt.read::<i64, _>("total_time").unwrap_or_default() as i32,
             disc_number: stmt.read::<i64, _>("disc_number").unwrap_or_default() as i32,
             track_number: stmt.read::<i64, _>("track_number").unwrap_or_default() as i32,
+            plays: stmt.read::<i64, _>("plays").unwrap_or_default(),
+            skips: stmt.read::<i64, _>("skips").unwrap_or_default(),
+            last_played: stmt.read::<i64, _>("last_played").unwrap_or_default(),
+            rating: stmt.read::<i64, _>("rating").unwrap_or_default(),
+            loved: stmt.read::<i64, _>("loved").unwrap_or_default(),
         };
 
         tracks.push(track);
@@ -1922,7 +2440,7 @@ fn copy_unmanaged_tracks(conn: &Connection, media_path: String) {
 
 fn get_playlists(conn: &Connection) -> Vec<Playlist> {
     let query = r#"
-    SELECT playlist_id, name, description, tracks
+    SELECT playlist_id, name, description, smart_info, smart_criteria, tracks, folder, parent_id
     FROM Playlists
     "#
     .to_string();
@@ -1941,6 +2459,12 @@ fn get_playlists(conn: &Connection) -> Vec<Playlist> {
             name: stmt.read::<String, _>("name").unwrap_or_default(),
             description: stmt.read::<String, _>("description").unwrap_or_default(),
             tracks: tracks,
+            smart_criteria: decode_smart_criteria(
+                &stmt.read::<String, _>("smart_info").unwrap_or_default(),
+                &stmt.read::<String, _>("smart_criteria").unwrap_or_default(),
+            ),
+            folder: stmt.read::<i64, _>("folder").unwrap_or_default() == 1,
+            parent_id: stmt.read::<i64, _>("parent_id").unwrap_or_default(),
         };
 
         playlists.push(playlist);
@@ -1949,10 +2473,100 @@ fn get_playlists(conn: &Connection) -> Vec<Playlist> {
     return playlists;
 }
 
-fn get_artist_albums(conn: &Connection) -> Vec<ArtistAlbums> {
+/*
+Re-evaluates the live updating smart playlists and stores their tracks.
+Called after changes to the library, plays, ratings and loved, playlists are read as stored.
+ */
+fn refresh_smart_playlists(conn: &Connection) {
     let query = r#"
-    SELECT artist_id
-    FROM Artists
+    SELECT playlist_id, smart_info, smart_criteria
+    FROM Playlists
+    WHERE smart_criteria IS NOT NULL AND smart_criteria != ''
+    "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+
+    let mut smart_playlists: Vec<(i64, SmartCriteria)> = Vec::new();
+
+    while let Ok(State::Row) = stmt.next() {
+        if let Some(criteria) = decode_smart_criteria(
+            &stmt.read::<String, _>("smart_info").unwrap_or_default(),
+            &stmt.read::<String, _>("smart_criteria").unwrap_or_default(),
+        ) {
+            smart_playlists.push((
+                stmt.read::<i64, _>("playlist_id").unwrap_or_default(),
+                criteria,
+            ));
+        }
+    }
+
+    for (playlist_id, criteria) in smart_playlists {
+        update_smart_playlist_tracks(conn, playlist_id, &criteria);
+    }
+}
+
+fn evaluate_smart_playlist(conn: &Connection, criteria: &SmartCriteria) -> Option<Vec<i64>> {
+    let (query, values) = smart_criteria_to_sql(criteria)?;
+
+    let mut stmt = match conn.prepare(query) {
+        Ok(stmt) => stmt,
+        Err(error) => {
+            error!("{}", error);
+            return None;
+        }
+    };
+
+    for (index, value) in values.into_iter().enumerate() {
+        stmt.bind((index + 1, value)).unwrap();
+    }
+
+    let mut rows: Vec<(i64, i64, i64)> = Vec::new();
+
+    while let Ok(State::Row) = stmt.next() {
+        rows.push((
+            stmt.read::<i64, _>("track_id").unwrap_or_default(),
+            stmt.read::<i64, _>("total_time").unwrap_or_default(),
+            stmt.read::<i64, _>("size").unwrap_or_default(),
+        ));
+    }
+
+    return Some(apply_smart_limit(&criteria.limit, rows));
+}
+
+fn update_smart_playlist_tracks(
+    conn: &Connection,
+    playlist_id: i64,
+    criteria: &SmartCriteria,
+) -> Option<Vec<i64>> {
+    // Playlists without live updating keep the tracks they were saved with, same as in iTunes
+    if !criteria.live_updating {
+        return None;
+    }
+
+    let tracks = evaluate_smart_playlist(conn, criteria)?;
+
+    let query = r#"
+    UPDATE Playlists
+    SET tracks = ?1
+    WHERE playlist_id = ?2
+    "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+    stmt.bind((1, serde_json::to_string(&tracks).unwrap().as_str()))
+        .unwrap();
+    stmt.bind((2, playlist_id)).unwrap();
+
+    let _ = stmt.next();
+
+    return Some(tracks);
+}
+
+fn get_artist_albums(conn: &Connection) -> Vec<ArtistAlbums> {
+    let query = r#"
+    SELECT artist_id
+    FROM Artists
     "#
     .to_string();
 
@@ -2353,6 +2967,10 @@ fn get_tracks_order(conn: &Connection, vec_order: Vec<Order>) -> Vec<i64> {
     ByGenre,
     BySize,
     ByTime,
+    ByLastPlayed,
+    ByPlays,
+    BySkips,
+    ByRating,
     ByNameInverse,
     ByReleaseDateInverse,
     ByAddedDateInverse,
@@ -2363,6 +2981,10 @@ fn get_tracks_order(conn: &Connection, vec_order: Vec<Order>) -> Vec<i64> {
     ByGenreInverse,
     BySizeInverse,
     ByTimeInverse,
+    ByLastPlayedInverse,
+    ByPlaysInverse,
+    BySkipsInverse,
+    ByRatingInverse,
      */
     let mut query = r#"
     SELECT track_id
@@ -2447,6 +3069,30 @@ fn get_tracks_order(conn: &Connection, vec_order: Vec<Order>) -> Vec<i64> {
             Order::ByTimeInverse => {
                 query.push_str(" total_time DESC,");
             }
+            Order::ByLastPlayed => {
+                query.push_str(" IFNULL(Tracks.last_played, 0) ASC,");
+            }
+            Order::ByPlays => {
+                query.push_str(" Tracks.plays ASC,");
+            }
+            Order::BySkips => {
+                query.push_str(" Tracks.skips ASC,");
+            }
+            Order::ByRating => {
+                query.push_str(" Tracks.rating ASC,");
+            }
+            Order::ByLastPlayedInverse => {
+                query.push_str(" IFNULL(Tracks.last_played, 0) DESC,");
+            }
+            Order::ByPlaysInverse => {
+                query.push_str(" Tracks.plays DESC,");
+            }
+            Order::BySkipsInverse => {
+                query.push_str(" Tracks.skips DESC,");
+            }
+            Order::ByRatingInverse => {
+                query.push_str(" Tracks.rating DESC,");
+            }
             _ => {}
         }
     }
@@ -2485,6 +3131,11 @@ fn get_track_by_id(conn: &Connection, track_id: i64) -> Option<Track> {
             total_time: stmt.read::<i64, _>("total_time").unwrap_or_default() as i32,
             disc_number: stmt.read::<i64, _>("disc_number").unwrap_or_default() as i32,
             track_number: stmt.read::<i64, _>("track_number").unwrap_or_default() as i32,
+            plays: stmt.read::<i64, _>("plays").unwrap_or_default(),
+            skips: stmt.read::<i64, _>("skips").unwrap_or_default(),
+            last_played: stmt.read::<i64, _>("last_played").unwrap_or_default(),
+            rating: stmt.read::<i64, _>("rating").unwrap_or_default(),
+            loved: stmt.read::<i64, _>("loved").unwrap_or_default(),
         };
 
         return Some(track);
@@ -2519,9 +3170,10 @@ fn get_artist_by_id(conn: &Connection, artist_id: i64) -> Option<Artist> {
 
 fn get_album_by_id(conn: &Connection, album_id: i64) -> Option<Album> {
     let query = r#"
-        SELECT *
+        SELECT Albums.*, Covers.hash AS cover_hash
         FROM Albums
-        WHERE album_id = ?1
+        LEFT JOIN Covers ON Albums.cover_id = Covers.cover_id
+        WHERE Albums.album_id = ?1
         "#
     .to_string();
 
@@ -2541,6 +3193,9 @@ fn get_album_by_id(conn: &Connection, album_id: i64) -> Option<Album> {
             date_added: stmt.read::<String, _>("date_added").unwrap_or_default(),
             tracks: vec![],
             cover_id: stmt.read::<i64, _>("cover_id").unwrap_or_default(),
+            cover_hash: stmt.read::<String, _>("cover_hash").unwrap_or_default(),
+            rating: stmt.read::<i64, _>("rating").unwrap_or_default(),
+            loved: stmt.read::<i64, _>("loved").unwrap_or_default(),
         };
 
         let tracks_query = r#"
@@ -2586,14 +3241,90 @@ fn get_genre_by_id(conn: &Connection, genre_id: i64) -> Option<Genre> {
     return None;
 }
 
-fn cover_as_base64(cover: &Image) -> String {
-    println!("load_cover_as_base64: {:?}", cover.media_type);
+// Covers were stored as base64 data URLs before 1.8.0
+fn base64_as_cover(base64_string: &str) -> Option<Image> {
+    let (header, data) = base64_string
+        .strip_prefix("data:")?
+        .split_once(";base64,")?;
+
+    return Some(Image {
+        media_type: header.to_string(),
+        data: general_purpose::STANDARD.decode(data).ok()?,
+    });
+}
+
+/*
+Writes the metadata of the tracks from the db into their files.
+Returns the files that could not be written with the reason.
+ */
+fn write_track_tags(
+    conn: &Connection,
+    covers_dir: &Path,
+    track_ids: &[i64],
+    with_cover: bool,
+) -> Vec<(String, String)> {
+    let query = r#"
+        SELECT Tracks.name, Tracks.location, Tracks.track_number, Tracks.track_count,
+            Tracks.disc_number, Tracks.disc_count,
+            CASE WHEN Albums.year > 0 THEN Albums.year ELSE Tracks.year END AS year,
+            Artists.name AS artist, AlbumArtists.name AS album_artist, Albums.name AS album,
+            Genres.name AS genre, Composers.name AS composer, Covers.hash AS cover_hash
+        FROM Tracks
+        LEFT JOIN Artists ON Tracks.artist_id = Artists.artist_id
+        LEFT JOIN Artists AS AlbumArtists ON Tracks.album_artist_id = AlbumArtists.artist_id
+        LEFT JOIN Albums ON Tracks.album_id = Albums.album_id
+        LEFT JOIN Genres ON Tracks.genre_id = Genres.genre_id
+        LEFT JOIN Composers ON Tracks.composer_id = Composers.composer_id
+        LEFT JOIN Covers ON Albums.cover_id = Covers.cover_id
+        WHERE Tracks.track_id = ?1
+        "#
+    .to_string();
+
+    let mut failed: Vec<(String, String)> = Vec::new();
+
+    for track_id in track_ids {
+        let mut stmt = conn.prepare(&query).unwrap();
+        stmt.bind((1, *track_id)).unwrap();
+
+        if let Ok(State::Row) = stmt.next() {
+            let location = stmt.read::<String, _>("location").unwrap_or_default();
+
+            let update = TagUpdate {
+                title: stmt.read::<String, _>("name").unwrap_or_default(),
+                artist: stmt.read::<String, _>("artist").unwrap_or_default(),
+                album_artist: stmt.read::<String, _>("album_artist").unwrap_or_default(),
+                album: stmt.read::<String, _>("album").unwrap_or_default(),
+                genre: stmt.read::<String, _>("genre").unwrap_or_default(),
+                composer: stmt.read::<String, _>("composer").unwrap_or_default(),
+                track_number: stmt.read::<i64, _>("track_number").unwrap_or_default(),
+                track_count: stmt.read::<i64, _>("track_count").unwrap_or_default(),
+                disc_number: stmt.read::<i64, _>("disc_number").unwrap_or_default(),
+                disc_count: stmt.read::<i64, _>("disc_count").unwrap_or_default(),
+                year: stmt.read::<i64, _>("year").unwrap_or_default(),
+                cover: if with_cover {
+                    load_cover(
+                        covers_dir,
+                        &stmt.read::<String, _>("cover_hash").unwrap_or_default(),
+                    )
+                } else {
+                    None
+                },
+            };
+
+            match write_tags(Path::new(&location), &update) {
+                Ok(()) => {}
+                Err(error) => {
+                    error!("{}: {}", location, error);
+                    failed.push((location, error));
+                }
+            }
+        }
+    }
 
-    let base64_string = general_purpose::STANDARD.encode(cover.data.clone());
-    return format!("data:{};base64,{}", cover.media_type, base64_string);
+    return failed;
 }
 
-fn extract_cover_by_album(conn: &Connection, album_ids: Vec<i64>) {
+fn extract_cover_by_album(conn: &Connection, covers_dir: &Path, album_ids: Vec<i64>) {
     for album_id in album_ids {
         let mut stmt = conn
             .prepare(
@@ -2610,44 +3341,105 @@ fn extract_cover_by_album(conn: &Connection, album_ids: Vec<i64>) {
             locations.push(stmt.read::<String, _>("location").unwrap());
         }
 
-        for location in locations {
+        // Embedded covers first, then images in the track directories
+        let mut cover: Option<Image> = None;
+        for location in &locations {
             eprintln!("extract location: {}", location.clone());
-            let path = Path::new(&location);
+            let path = Path::new(location);
 
             if let Some(meta) = extract_metadata(path) {
-                if let Some(ref cover) = meta.cover {
-                    let cover_id = get_or_create_cover_id(&conn, album_id, cover);
-
-                    let mut stmt = conn
-                        .prepare(
-                            "UPDATE Albums
-                            SET cover_id = ?1
-                            WHERE album_id = ?2",
-                        )
-                        .unwrap();
-                    stmt.bind((1, cover_id)).unwrap();
-                    stmt.bind((2, album_id)).unwrap();
-                    let _ = stmt.next();
-
+                if meta.cover.is_some() {
+                    cover = meta.cover;
+                    break;
+                }
+            }
+        }
+        if cover.is_none() {
+            let mut folder_covers: HashMap<PathBuf, Option<Image>> = HashMap::new();
+            for location in &locations {
+                cover = get_folder_cover(Path::new(location), &HashMap::new(), &mut folder_covers);
+                if cover.is_some() {
                     break;
                 }
             }
         }
+
+        if let Some(ref cover) = cover {
+            let cover_id = get_or_create_cover_id(&conn, covers_dir, album_id, cover);
+
+            let mut stmt = conn
+                .prepare(
+                    "UPDATE Albums
+                    SET cover_id = ?1
+                    WHERE album_id = ?2",
+                )
+                .unwrap();
+            stmt.bind((1, cover_id)).unwrap();
+            stmt.bind((2, album_id)).unwrap();
+            let _ = stmt.next();
+        }
+    }
+}
+
+/*
+Cover image in the directory of the track, for files without embedded cover.
+Uses the images grouped during import if there are any, otherwise the directory is searched.
+Each directory is only read once.
+ */
+fn get_folder_cover(
+    path: &Path,
+    directory_images: &HashMap<PathBuf, Vec<PathBuf>>,
+    folder_covers: &mut HashMap<PathBuf, Option<Image>>,
+) -> Option<Image> {
+    let directory = path.parent()?.to_path_buf();
+    if let Some(cover) = folder_covers.get(&directory) {
+        return cover.clone();
+    }
+
+    let cover_file = match directory_images.get(&directory) {
+        Some(images) => choose_cover_file(images),
+        None => find_cover_file(&directory),
+    };
+    let cover = cover_file.and_then(|file| read_cover_file(&file));
+    folder_covers.insert(directory, cover.clone());
+    return cover;
+}
+
+/*
+Stars in the search, e.g. "queen ***" or "★★★★", filter for a minimum rating.
+Returns the search without the stars and the minimum rating (0-100).
+ */
+fn split_rating_filter(search: &str) -> (String, i64) {
+    let mut words: Vec<&str> = Vec::new();
+    let mut min_rating = 0;
+
+    for word in search.split(' ') {
+        if word.len() > 0 && word.chars().all(|c| c == '*' || c == '★') {
+            min_rating = (word.chars().count() as i64 * 20).min(100);
+        } else {
+            words.push(word);
+        }
     }
+
+    return (words.join(" ").trim().to_string(), min_rating);
 }
 
 fn search_tracks(conn: &Connection, search: String, limit: i64) -> Option<Vec<i64>> {
     let query = r#"
                         SELECT track_id
                         FROM Tracks
-                        WHERE name LIKE ?1
-                        LIMIT ?2
+                        WHERE name LIKE ?1 AND rating >= ?2
+                        ORDER BY rating DESC
+                        LIMIT ?3
                         "#
     .to_string();
 
+    let (search, min_rating) = split_rating_filter(&search);
+
     let mut stmt = conn.prepare(query).unwrap();
     stmt.bind((1, format!("%{}%", search).as_str())).unwrap();
-    stmt.bind((2, limit)).unwrap();
+    stmt.bind((2, min_rating)).unwrap();
+    stmt.bind((3, limit)).unwrap();
 
     let mut track_ids: Vec<i64> = vec![];
 
@@ -2669,14 +3461,18 @@ fn search_albums(conn: &Connection, search: String, limit: i64) -> Option<Vec<i6
     let query = r#"
                         SELECT album_id
                         FROM Albums
-                        WHERE name LIKE ?1
-                        LIMIT ?2
+                        WHERE name LIKE ?1 AND rating >= ?2
+                        ORDER BY rating DESC
+                        LIMIT ?3
                         "#
     .to_string();
 
+    let (search, min_rating) = split_rating_filter(&search);
+
     let mut stmt = conn.prepare(query).unwrap();
     stmt.bind((1, format!("%{}%", search).as_str())).unwrap();
-    stmt.bind((2, limit)).unwrap();
+    stmt.bind((2, min_rating)).unwrap();
+    stmt.bind((3, limit)).unwrap();
 
     let mut album_ids: Vec<i64> = vec![];
 
@@ -2810,13 +3606,64 @@ fn search_playlists(conn: &Connection, search: String, limit: i64) -> Option<Vec
     return None;
 }
 
+fn read_replaygain(stmt: &Statement) -> ReplayGain {
+    let mut replaygain = ReplayGain {
+        track_gain: stmt
+            .read::<Option<f64>, _>("replaygain_track_gain")
+            .unwrap_or_default(),
+        track_peak: stmt
+            .read::<Option<f64>, _>("replaygain_track_peak")
+            .unwrap_or_default(),
+        album_gain: stmt
+            .read::<Option<f64>, _>("replaygain_album_gain")
+            .unwrap_or_default(),
+        album_peak: stmt
+            .read::<Option<f64>, _>("replaygain_album_peak")
+            .unwrap_or_default(),
+    };
+
+    // Tracks imported from library.xml only have the SoundCheck value of iTunes
+    if replaygain.track_gain.is_none() {
+        let normalization = stmt.read::<i64, _>("normalization").unwrap_or_default();
+        if normalization > 0 {
+            replaygain.track_gain = Some(-10.0 * (normalization as f64 / 1000.0).log10());
+        }
+    }
+
+    return replaygain;
+}
+
+// Tracks that were not probed yet are played without skipping delay and padding
+fn read_gapless(stmt: &Statement) -> Gapless {
+    return Gapless {
+        encoder_delay: stmt
+            .read::<Option<i64>, _>("encoder_delay")
+            .unwrap_or_default()
+            .unwrap_or_default(),
+        encoder_padding: stmt
+            .read::<Option<i64>, _>("encoder_padding")
+            .unwrap_or_default()
+            .unwrap_or_default(),
+        frame_count: stmt
+            .read::<Option<i64>, _>("frame_count")
+            .unwrap_or_default()
+            .unwrap_or_default(),
+        sample_rate: stmt
+            .read::<Option<i64>, _>("sample_rate")
+            .unwrap_or_default()
+            .unwrap_or_default(),
+    };
+}
+
 fn get_audiotracks_by_id(conn: &Connection, vec_id: &Vec<i64>) -> Option<Vec<AudioTrack>> {
     /*
     TODO:
     What happens if a track_id in vec_id does not exist? Should never happen.
      */
     let mut query = r#"
-        SELECT track_id, location
+        SELECT track_id, album_id, location, plays, rating, normalization,
+            replaygain_track_gain, replaygain_track_peak, replaygain_album_gain, replaygain_album_peak,
+            encoder_delay, encoder_padding, frame_count, sample_rate
         FROM Tracks
         WHERE track_id IN (
         "#
@@ -2837,7 +3684,12 @@ fn get_audiotracks_by_id(conn: &Connection, vec_id: &Vec<i64>) -> Option<Vec<Aud
     while let Ok(State::Row) = stmt.next() {
         let audiotrack = AudioTrack {
             id: stmt.read::<i64, _>("track_id").unwrap_or_default(),
+            album_id: stmt.read::<i64, _>("album_id").unwrap_or_default(),
             location: stmt.read::<String, _>("location").unwrap_or_default(),
+            plays: stmt.read::<i64, _>("plays").unwrap_or_default(),
+            rating: stmt.read::<i64, _>("rating").unwrap_or_default(),
+            replaygain: read_replaygain(&stmt),
+            gapless: read_gapless(&stmt),
         };
 
         audiotracks.push(audiotrack);
@@ -2866,7 +3718,9 @@ fn get_audiotracks_from_album_by_id(
     vec_id: &Vec<i64>,
 ) -> Option<Vec<AudioTrack>> {
     let query = r#"
-    SELECT location, track_id
+    SELECT location, track_id, album_id, plays, rating, normalization,
+        replaygain_track_gain, replaygain_track_peak, replaygain_album_gain, replaygain_album_peak,
+        encoder_delay, encoder_padding, frame_count, sample_rate
     FROM Tracks
     WHERE album_id = ?1
     ORDER BY disc_number, track_number
@@ -2881,7 +3735,12 @@ fn get_audiotracks_from_album_by_id(
         while let Ok(State::Row) = stmt.next() {
             let audiotrack = AudioTrack {
                 id: stmt.read::<i64, _>("track_id").unwrap(),
+                album_id: stmt.read::<i64, _>("album_id").unwrap_or_default(),
                 location: stmt.read::<String, _>("location").unwrap(),
+                plays: stmt.read::<i64, _>("plays").unwrap_or_default(),
+                rating: stmt.read::<i64, _>("rating").unwrap_or_default(),
+                replaygain: read_replaygain(&stmt),
+                gapless: read_gapless(&stmt),
             };
             audiotracks.push(audiotrack);
         }
@@ -2899,7 +3758,7 @@ fn get_audiotracks_from_playlist_by_id(
     vec_id: &Vec<i64>,
 ) -> Option<Vec<AudioTrack>> {
     let query = r#"
-    SELECT tracks
+    SELECT smart_info, smart_criteria, tracks
     FROM Playlists
     WHERE playlist_id = ?1
     "#
@@ -2907,15 +3766,57 @@ fn get_audiotracks_from_playlist_by_id(
 
     let mut audiotracks: Vec<AudioTrack> = Vec::new();
 
+    // Folders play all playlists below them, each track only once
+    let mut playlist_ids: Vec<i64> = Vec::new();
+    let mut from_folder = false;
     for playlist_id in vec_id {
+        if is_playlist_folder(conn, *playlist_id) {
+            from_folder = true;
+            for descendant in get_playlist_descendants(conn, *playlist_id) {
+                if !is_playlist_folder(conn, descendant) {
+                    playlist_ids.push(descendant);
+                }
+            }
+        } else {
+            playlist_ids.push(*playlist_id);
+        }
+    }
+
+    let mut seen_track_ids: HashSet<i64> = HashSet::new();
+
+    for playlist_id in &playlist_ids {
         let mut stmt = conn.prepare(&query).unwrap();
         stmt.bind((1, playlist_id.clone())).unwrap();
 
+        let mut playlists: Vec<(Vec<i64>, Option<SmartCriteria>)> = Vec::new();
+
         while let Ok(State::Row) = stmt.next() {
             let tracks_string = stmt.read::<String, _>("tracks").unwrap_or_default();
 
             let track_ids: Vec<i64> = serde_json::from_str(&tracks_string).unwrap();
 
+            let smart_criteria = decode_smart_criteria(
+                &stmt.read::<String, _>("smart_info").unwrap_or_default(),
+                &stmt.read::<String, _>("smart_criteria").unwrap_or_default(),
+            );
+
+            playlists.push((track_ids, smart_criteria));
+        }
+
+        for (mut track_ids, smart_criteria) in playlists {
+            if let Some(criteria) = smart_criteria {
+                if let Some(tracks) = update_smart_playlist_tracks(conn, *playlist_id, &criteria) {
+                    track_ids = tracks;
+                }
+            }
+
+            if from_folder {
+                track_ids.retain(|track_id| seen_track_ids.insert(*track_id));
+                if track_ids.len() == 0 {
+                    continue;
+                }
+            }
+
             if let Some(audiotracks_from_ids) = get_audiotracks_by_id(conn, &track_ids) {
                 audiotracks.append(&mut audiotracks_from_ids.clone());
             };
@@ -2936,7 +3837,9 @@ fn get_audiotracks_from_artist_by_id(
     // TODO: sort by album name? year? release_date? date_added?
     // Default sort by year, album, disk number, track number
     let query = r#"
-    SELECT track_id, location
+    SELECT track_id, album_id, location, plays, rating, normalization,
+        replaygain_track_gain, replaygain_track_peak, replaygain_album_gain, replaygain_album_peak,
+        encoder_delay, encoder_padding, frame_count, sample_rate
     FROM Tracks
     WHERE artist_id = ?1 OR album_artist_id = ?1
     ORDER BY year, album_id, disc_number, track_number
@@ -2951,7 +3854,12 @@ fn get_audiotracks_from_artist_by_id(
         while let Ok(State::Row) = stmt.next() {
             let audiotrack = AudioTrack {
                 id: stmt.read::<i64, _>("track_id").unwrap(),
+                album_id: stmt.read::<i64, _>("album_id").unwrap_or_default(),
                 location: stmt.read::<String, _>("location").unwrap(),
+                plays: stmt.read::<i64, _>("plays").unwrap_or_default(),
+                rating: stmt.read::<i64, _>("rating").unwrap_or_default(),
+                replaygain: read_replaygain(&stmt),
+                gapless: read_gapless(&stmt),
             };
             audiotracks.push(audiotrack);
         }
@@ -2981,6 +3889,10 @@ fn get_audiotracks_from_composer_by_id_and_order(
     ByGenre,
     BySize,
     ByTime,
+    ByLastPlayed,
+    ByPlays,
+    BySkips,
+    ByRating,
     ByNameInverse,
     ByReleaseDateInverse,
     ByAddedDateInverse,
@@ -2990,9 +3902,15 @@ fn get_audiotracks_from_composer_by_id_and_order(
     ByGenreInverse,
     BySizeInverse,
     ByTimeInverse,
+    ByLastPlayedInverse,
+    ByPlaysInverse,
+    BySkipsInverse,
+    ByRatingInverse,
      */
     let mut query = r#"
-    SELECT track_id, location
+    SELECT track_id, Tracks.album_id, location, Tracks.plays, Tracks.rating, normalization,
+        replaygain_track_gain, replaygain_track_peak, replaygain_album_gain, replaygain_album_peak,
+        encoder_delay, encoder_padding, frame_count, Tracks.sample_rate
     FROM Tracks
     LEFT JOIN Artists Artist ON Tracks.artist_id = Artist.artist_id
     LEFT JOIN Artists AlbumArtist ON Tracks.album_artist_id = AlbumArtist.artist_id
@@ -3067,6 +3985,30 @@ fn get_audiotracks_from_composer_by_id_and_order(
             Order::ByTimeInverse => {
                 query.push_str(" total_time DESC,");
             }
+            Order::ByLastPlayed => {
+                query.push_str(" IFNULL(Tracks.last_played, 0) ASC,");
+            }
+            Order::ByPlays => {
+                query.push_str(" Tracks.plays ASC,");
+            }
+            Order::BySkips => {
+                query.push_str(" Tracks.skips ASC,");
+            }
+            Order::ByRating => {
+                query.push_str(" Tracks.rating ASC,");
+            }
+            Order::ByLastPlayedInverse => {
+                query.push_str(" IFNULL(Tracks.last_played, 0) DESC,");
+            }
+            Order::ByPlaysInverse => {
+                query.push_str(" Tracks.plays DESC,");
+            }
+            Order::BySkipsInverse => {
+                query.push_str(" Tracks.skips DESC,");
+            }
+            Order::ByRatingInverse => {
+                query.push_str(" Tracks.rating DESC,");
+            }
             _ => {}
         }
     }
@@ -3081,7 +4023,12 @@ fn get_audiotracks_from_composer_by_id_and_order(
         while let Ok(State::Row) = stmt.next() {
             let audiotrack = AudioTrack {
                 id: stmt.read::<i64, _>("track_id").unwrap(),
+                album_id: stmt.read::<i64, _>("album_id").unwrap_or_default(),
                 location: stmt.read::<String, _>("location").unwrap(),
+                plays: stmt.read::<i64, _>("plays").unwrap_or_default(),
+                rating: stmt.read::<i64, _>("rating").unwrap_or_default(),
+                replaygain: read_replaygain(&stmt),
+                gapless: read_gapless(&stmt),
             };
             audiotracks.push(audiotrack);
         }
@@ -3110,6 +4057,10 @@ fn get_audiotracks_from_genre_by_id_and_order(
     ByAlbum,
     BySize,
     ByTime,
+    ByLastPlayed,
+    ByPlays,
+    BySkips,
+    ByRating,
     ByNameInverse,
     ByReleaseDateInverse,
     ByAddedDateInverse,
@@ -3119,9 +4070,15 @@ fn get_audiotracks_from_genre_by_id_and_order(
     ByAlbumInverse,
     BySizeInverse,
     ByTimeInverse,
+    ByLastPlayedInverse,
+    ByPlaysInverse,
+    BySkipsInverse,
+    ByRatingInverse,
      */
     let mut query = r#"
-    SELECT track_id, location
+    SELECT track_id, Tracks.album_id, location, Tracks.plays, Tracks.rating, normalization,
+        replaygain_track_gain, replaygain_track_peak, replaygain_album_gain, replaygain_album_peak,
+        encoder_delay, encoder_padding, frame_count, Tracks.sample_rate
     FROM Tracks
     LEFT JOIN Artists Artist ON Tracks.artist_id = Artist.artist_id
     LEFT JOIN Artists AlbumArtist ON Tracks.album_artist_id = AlbumArtist.artist_id
@@ -3194,6 +4151,30 @@ fn get_audiotracks_from_genre_by_id_and_order(
             Order::ByTimeInverse => {
                 query.push_str(" total_time DESC,");
             }
+            Order::ByLastPlayed => {
+                query.push_str(" IFNULL(Tracks.last_played, 0) ASC,");
+            }
+            Order::ByPlays => {
+                query.push_str(" Tracks.plays ASC,");
+            }
+            Order::BySkips => {
+                query.push_str(" Tracks.skips ASC,");
+            }
+            Order::ByRating => {
+                query.push_str(" Tracks.rating ASC,");
+            }
+            Order::ByLastPlayedInverse => {
+                query.push_str(" IFNULL(Tracks.last_played, 0) DESC,");
+            }
+            Order::ByPlaysInverse => {
+                query.push_str(" Tracks.plays DESC,");
+            }
+            Order::BySkipsInverse => {
+                query.push_str(" Tracks.skips DESC,");
+            }
+            Order::ByRatingInverse => {
+                query.push_str(" Tracks.rating DESC,");
+            }
             _ => {}
         }
     }
@@ -3208,7 +4189,12 @@ fn get_audiotracks_from_genre_by_id_and_order(
         while let Ok(State::Row) = stmt.next() {
             let audiotrack = AudioTrack {
                 id: stmt.read::<i64, _>("track_id").unwrap(),
+                album_id: stmt.read::<i64, _>("album_id").unwrap_or_default(),
                 location: stmt.read::<String, _>("location").unwrap(),
+                plays: stmt.read::<i64, _>("plays").unwrap_or_default(),
+                rating: stmt.read::<i64, _>("rating").unwrap_or_default(),
+                replaygain: read_replaygain(&stmt),
+                gapless: read_gapless(&stmt),
             };
             audiotracks.push(audiotrack);
         }
@@ -3338,7 +4324,7 @@ fn longest_common_path(paths: Vec<String>) -> Option<String> {
     Some(common_path.to_string_lossy().into_owned())
 }
 
-fn is_db_or_init(conn: &Connection) {
+fn is_db_or_init(conn: &Connection, covers_dir: &Path) {
     // Check if a table exists in the database
     let mut stmt = conn
         .prepare("SELECT name FROM sqlite_master WHERE type='table' AND name='Version'")
@@ -3358,7 +4344,7 @@ fn is_db_or_init(conn: &Connection) {
                     let patch = version_stmt.read::<i64, _>("patch").unwrap_or_default();
 
                     if DB_MAJOR != major || DB_MINOR != minor || DB_PATCH != patch {
-                        update_db_version(conn, major, minor, patch);
+                        update_db_version(conn, covers_dir, major, minor, patch);
                     }
                 }
                 State::Done => {
@@ -3416,7 +4402,36 @@ fn init_db(conn: &Connection) {
             location TEXT,
             file_folder_count INTEGER,
             library_folder_count INTEGER,
-            plays INTEGER NOT NULL DEFAULT 0
+            plays INTEGER NOT NULL DEFAULT 0,
+            replaygain_track_gain REAL,
+            replaygain_track_peak REAL,
+            replaygain_album_gain REAL,
+            replaygain_album_peak REAL,
+            skips INTEGER NOT NULL DEFAULT 0,
+            last_played INTEGER,
+            rating INTEGER NOT NULL DEFAULT 0,
+            loved INTEGER NOT NULL DEFAULT 0,
+            last_skipped INTEGER,
+            comments TEXT,
+            grouping TEXT,
+            bpm INTEGER,
+            volume_adjustment INTEGER,
+            start_time INTEGER,
+            stop_time INTEGER,
+            compilation INTEGER NOT NULL DEFAULT 0,
+            lyrics TEXT,
+            isrc TEXT,
+            label TEXT,
+            original_date TEXT,
+            musicbrainz_track_id TEXT,
+            musicbrainz_album_id TEXT,
+            musicbrainz_artist_id TEXT,
+            musicbrainz_album_artist_id TEXT,
+            musicbrainz_release_group_id TEXT,
+            encoder_delay INTEGER,
+            encoder_padding INTEGER,
+            frame_count INTEGER,
+            loudness_scan_failed INTEGER NOT NULL DEFAULT 0
         );
         CREATE TABLE Artists (
             artist_id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
@@ -3441,12 +4456,14 @@ fn init_db(conn: &Connection) {
             release_date TEXT,
             date_modified TEXT DEFAULT CURRENT_TIMESTAMP,
             date_added TEXT DEFAULT CURRENT_TIMESTAMP,
-            cover_id INTEGER
+            cover_id INTEGER,
+            rating INTEGER NOT NULL DEFAULT 0,
+            loved INTEGER NOT NULL DEFAULT 0
         );
         CREATE TABLE Covers (
             cover_id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
             album_id INTEGER REFERENCES Albums(album_id),
-            base64 TEXT
+            hash TEXT
         );
         CREATE TABLE Playlists (
             playlist_id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
@@ -3464,7 +4481,18 @@ fn init_db(conn: &Connection) {
             smart_criteria TEXT,
             date_modified TEXT DEFAULT CURRENT_TIMESTAMP,
             date_added TEXT DEFAULT CURRENT_TIMESTAMP,
-            tracks TEXT
+            tracks TEXT,
+            parent_id INTEGER REFERENCES Playlists(playlist_id)
+        );
+        CREATE TABLE PlayHistory (
+            play_id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+            track_id INTEGER REFERENCES Tracks(track_id),
+            started_at INTEGER NOT NULL,
+            duration_listened INTEGER NOT NULL DEFAULT 0,
+            completed INTEGER NOT NULL DEFAULT 0,
+            skipped INTEGER NOT NULL DEFAULT 0,
+            source_type TEXT,
+            source_id INTEGER
         );
         CREATE INDEX idx_tracks_track_id ON Tracks(track_id);
         CREATE INDEX idx_tracks_artist_id ON Tracks(artist_id);
@@ -3487,8 +4515,11 @@ fn init_db(conn: &Connection) {
         CREATE INDEX idx_composers_name ON Composers(name);
         CREATE INDEX idx_genres_genre_id ON Genres(genre_id);
         CREATE INDEX idx_genres_name ON Genres(name);
+        CREATE INDEX idx_covers_hash ON Covers(hash);
         CREATE INDEX idx_playlists_playlist_id ON Playlists(playlist_id);
         CREATE INDEX idx_playlists_name ON Playlists(name);
+        CREATE INDEX idx_playhistory_track_id ON PlayHistory(track_id);
+        CREATE INDEX idx_playhistory_started_at ON PlayHistory(started_at);
     ";
 
     conn.execute(sql_statements).unwrap();
@@ -3496,9 +4527,189 @@ fn init_db(conn: &Connection) {
     insert_db_version(conn, None);
 }
 
-fn update_db_version(conn: &Connection, major: i64, minor: i64, patch: i64) {
-    // In the future, provide code to update DB from version 1.0.0
-    // First create a backup of the current db file.
+fn update_db_version(conn: &Connection, covers_dir: &Path, major: i64, minor: i64, patch: i64) {
+    // TODO: First create a backup of the current db file.
+    // Each step updates the DB from the previous version, so old DBs run through all of them.
+    if major == 1 && minor < 1 {
+        // 1.1.0: ReplayGain values per track
+        let sql_statements = "
+            ALTER TABLE Tracks ADD COLUMN replaygain_track_gain REAL;
+            ALTER TABLE Tracks ADD COLUMN replaygain_track_peak REAL;
+            ALTER TABLE Tracks ADD COLUMN replaygain_album_gain REAL;
+            ALTER TABLE Tracks ADD COLUMN replaygain_album_peak REAL;
+        ";
+
+        match conn.execute(sql_statements) {
+            Ok(()) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+
+    if major == 1 && minor < 2 {
+        // 1.2.0: Play history
+        let sql_statements = "
+            CREATE TABLE PlayHistory (
+                play_id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+                track_id INTEGER REFERENCES Tracks(track_id),
+                started_at INTEGER NOT NULL,
+                duration_listened INTEGER NOT NULL DEFAULT 0,
+                completed INTEGER NOT NULL DEFAULT 0,
+                skipped INTEGER NOT NULL DEFAULT 0,
+                source_type TEXT,
+                source_id INTEGER
+            );
+            CREATE INDEX idx_playhistory_track_id ON PlayHistory(track_id);
+            CREATE INDEX idx_playhistory_started_at ON PlayHistory(started_at);
+        ";
+
+        match conn.execute(sql_statements) {
+            Ok(()) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+
+    if major == 1 && minor < 3 {
+        // 1.3.0: Skips and last played date per track
+        let sql_statements = "
+            ALTER TABLE Tracks ADD COLUMN skips INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE Tracks ADD COLUMN last_played INTEGER;
+        ";
+
+        match conn.execute(sql_statements) {
+            Ok(()) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+
+    if major == 1 && minor < 4 {
+        // 1.4.0: Ratings and loved/disliked flags for tracks and albums
+        let sql_statements = "
+            ALTER TABLE Tracks ADD COLUMN rating INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE Tracks ADD COLUMN loved INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE Albums ADD COLUMN rating INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE Albums ADD COLUMN loved INTEGER NOT NULL DEFAULT 0;
+        ";
+
+        match conn.execute(sql_statements) {
+            Ok(()) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+
+    if major == 1 && minor < 5 {
+        // 1.5.0: Remaining iTunes track fields
+        let sql_statements = "
+            ALTER TABLE Tracks ADD COLUMN last_skipped INTEGER;
+            ALTER TABLE Tracks ADD COLUMN comments TEXT;
+            ALTER TABLE Tracks ADD COLUMN grouping TEXT;
+            ALTER TABLE Tracks ADD COLUMN bpm INTEGER;
+            ALTER TABLE Tracks ADD COLUMN volume_adjustment INTEGER;
+            ALTER TABLE Tracks ADD COLUMN start_time INTEGER;
+            ALTER TABLE Tracks ADD COLUMN stop_time INTEGER;
+            ALTER TABLE Tracks ADD COLUMN compilation INTEGER NOT NULL DEFAULT 0;
+        ";
+
+        match conn.execute(sql_statements) {
+            Ok(()) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+
+    if major == 1 && minor < 6 {
+        // 1.6.0: Playlist folders reference their parent by id
+        let sql_statements = "
+            ALTER TABLE Playlists ADD COLUMN parent_id INTEGER REFERENCES Playlists(playlist_id);
+        ";
+
+        match conn.execute(sql_statements) {
+            Ok(()) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+
+        link_playlist_parents(conn);
+    }
+
+    if major == 1 && minor < 7 {
+        // 1.7.0: Lyrics and identifiers read from the file tags
+        let sql_statements = "
+            ALTER TABLE Tracks ADD COLUMN lyrics TEXT;
+            ALTER TABLE Tracks ADD COLUMN isrc TEXT;
+            ALTER TABLE Tracks ADD COLUMN label TEXT;
+            ALTER TABLE Tracks ADD COLUMN original_date TEXT;
+            ALTER TABLE Tracks ADD COLUMN musicbrainz_track_id TEXT;
+            ALTER TABLE Tracks ADD COLUMN musicbrainz_album_id TEXT;
+            ALTER TABLE Tracks ADD COLUMN musicbrainz_artist_id TEXT;
+            ALTER TABLE Tracks ADD COLUMN musicbrainz_album_artist_id TEXT;
+            ALTER TABLE Tracks ADD COLUMN musicbrainz_release_group_id TEXT;
+        ";
+
+        match conn.execute(sql_statements) {
+            Ok(()) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+
+    if major == 1 && minor < 8 {
+        // 1.8.0: Covers moved from base64 TEXT to the cover store
+        let sql_statements = "
+            ALTER TABLE Covers ADD COLUMN hash TEXT;
+            CREATE INDEX idx_covers_hash ON Covers(hash);
+        ";
+
+        match conn.execute(sql_statements) {
+            Ok(()) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+
+        move_covers_to_store(conn, covers_dir);
+    }
+
+    if major == 1 && minor < 9 {
+        // 1.9.0: Encoder delay and padding for gapless playback, probed in the background
+        let sql_statements = "
+            ALTER TABLE Tracks ADD COLUMN encoder_delay INTEGER;
+            ALTER TABLE Tracks ADD COLUMN encoder_padding INTEGER;
+            ALTER TABLE Tracks ADD COLUMN frame_count INTEGER;
+        ";
+
+        match conn.execute(sql_statements) {
+            Ok(()) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+
+    if major == 1 && minor < 10 {
+        // 1.10.0: Tracks that could not be decoded are not scanned for loudness again
+        let sql_statements = "
+            ALTER TABLE Tracks ADD COLUMN loudness_scan_failed INTEGER NOT NULL DEFAULT 0;
+        ";
+
+        match conn.execute(sql_statements) {
+            Ok(()) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+
     insert_db_version(
         conn,
         Some(format!(
@@ -3508,6 +4719,57 @@ fn update_db_version(conn: &Connection, major: i64, minor: i64, patch: i64) {
     );
 }
 
+/*
+Writes the base64 covers into the cover store and removes them from the db.
+Covers that cannot be decoded or written keep their base64 and are not shown.
+ */
+fn move_covers_to_store(conn: &Connection, covers_dir: &Path) {
+    let mut cover_ids: Vec<i64> = Vec::new();
+    let mut stmt = conn
+        .prepare("SELECT cover_id FROM Covers WHERE base64 IS NOT NULL")
+        .unwrap();
+    while let Ok(State::Row) = stmt.next() {
+        cover_ids.push(stmt.read::<i64, _>("cover_id").unwrap_or_default());
+    }
+
+    // One at a time, all covers at once could take up a lot of memory
+    for cover_id in cover_ids {
+        let mut stmt = conn
+            .prepare("SELECT base64 FROM Covers WHERE cover_id = ?1")
+            .unwrap();
+        stmt.bind((1, cover_id)).unwrap();
+        if let Ok(State::Row) = stmt.next() {
+            let base64_string = stmt.read::<String, _>("base64").unwrap_or_default();
+            let Some(cover) = base64_as_cover(&base64_string) else {
+                error!("cover {} is not a base64 image", cover_id);
+                continue;
+            };
+
+            match store_cover(covers_dir, &cover) {
+                Ok(hash) => {
+                    let mut update_stmt = conn
+                        .prepare("UPDATE Covers SET hash = ?1, base64 = NULL WHERE cover_id = ?2")
+                        .unwrap();
+                    update_stmt.bind((1, hash.as_str())).unwrap();
+                    update_stmt.bind((2, cover_id)).unwrap();
+                    let _ = update_stmt.next();
+                }
+                Err(error) => {
+                    error!("cover {}: {}", cover_id, error);
+                }
+            }
+        }
+    }
+
+    // Give the space of the base64 strings back
+    match conn.execute("VACUUM") {
+        Ok(()) => {}
+        Err(error) => {
+            error!("{}", error);
+        }
+    }
+}
+
 fn insert_db_version(conn: &Connection, opt_comment: Option<String>) {
     let mut stmt = conn
         .prepare(
@@ -3778,16 +5040,452 @@ fn delete_playlists(conn: &Connection, vec_playlist_id: &Vec<i64>) {
     stmt.next().unwrap();
 }
 
-fn collect_files_in_directory(dir_path: &Path, paths_vec: &mut Vec<String>, max_level: u8) {
-    if let Ok(entries) = fs::read_dir(dir_path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let entry_path = entry.path();
-                if entry_path.is_file() {
-                    paths_vec.push(entry_path.to_string_lossy().into_owned());
-                } else if entry_path.is_dir() {
-                    if max_level != 0 {
-                        collect_files_in_directory(&entry_path, paths_vec, max_level - 1);
+/*
+Returns the name of the playlist and its tracks in playlist order.
+Folders contain the tracks of all playlists below them.
+ */
+fn get_playlist_entries(
+    conn: &Connection,
+    playlist_id: i64,
+) -> Option<(String, Vec<PlaylistEntry>)> {
+    let query = r#"
+        SELECT name
+        FROM Playlists
+        WHERE playlist_id = ?1
+        "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+    stmt.bind((1, playlist_id)).unwrap();
+
+    let name = match stmt.next() {
+        Ok(State::Row) => stmt.read::<String, _>("name").unwrap_or_default(),
+        _ => {
+            return None;
+        }
+    };
+
+    let mut entries: Vec<PlaylistEntry> = Vec::new();
+
+    let audiotracks =
+        get_audiotracks_from_playlist_by_id(conn, &vec![playlist_id]).unwrap_or_default();
+
+    let track_query = r#"
+        SELECT Tracks.name AS title, Tracks.location, Tracks.total_time,
+            Artists.name AS artist, Albums.name AS album
+        FROM Tracks
+        LEFT JOIN Artists ON Tracks.artist_id = Artists.artist_id
+        LEFT JOIN Albums ON Tracks.album_id = Albums.album_id
+        WHERE Tracks.track_id = ?1
+        "#
+    .to_string();
+
+    for audiotrack in audiotracks {
+        let mut track_stmt = conn.prepare(&track_query).unwrap();
+        track_stmt.bind((1, audiotrack.id)).unwrap();
+
+        if let Ok(State::Row) = track_stmt.next() {
+            entries.push(PlaylistEntry {
+                location: track_stmt.read::<String, _>("location").unwrap_or_default(),
+                title: track_stmt.read::<String, _>("title").unwrap_or_default(),
+                artist: track_stmt.read::<String, _>("artist").unwrap_or_default(),
+                album: track_stmt.read::<String, _>("album").unwrap_or_default(),
+                total_time: track_stmt.read::<i64, _>("total_time").unwrap_or_default(),
+            });
+        }
+    }
+
+    return Some((name, entries));
+}
+
+// Persistent id tags for tracks and playlists without an iTunes persistent id ("anmt", "anmp")
+static TRACK_ID_TAG: u32 = 0x616E6D74;
+static PLAYLIST_ID_TAG: u32 = 0x616E6D70;
+
+fn get_library_tracks(conn: &Connection) -> Vec<LibraryTrack> {
+    let query = r#"
+        SELECT Tracks.track_id, Tracks.name, Tracks.kind, Tracks.size, Tracks.total_time,
+            Tracks.disc_number, Tracks.disc_count, Tracks.track_number, Tracks.track_count,
+            Tracks.year, Tracks.bit_rate, Tracks.sample_rate, Tracks.normalization,
+            Tracks.artwork_count, Tracks.sort_name, Tracks.persistent_id, Tracks.location,
+            Tracks.file_folder_count, Tracks.library_folder_count, Tracks.plays, Tracks.skips,
+            Tracks.rating, Tracks.loved, Tracks.comments, Tracks.grouping, Tracks.bpm,
+            Tracks.volume_adjustment, Tracks.start_time, Tracks.stop_time, Tracks.compilation,
+            strftime('%Y-%m-%dT%H:%M:%SZ', Tracks.date_modified) AS date_modified,
+            strftime('%Y-%m-%dT%H:%M:%SZ', Tracks.date_added) AS date_added,
+            strftime('%Y-%m-%dT%H:%M:%SZ', Tracks.release_date) AS release_date,
+            strftime('%Y-%m-%dT%H:%M:%SZ', Tracks.last_played, 'unixepoch') AS play_date,
+            strftime('%Y-%m-%dT%H:%M:%SZ', Tracks.last_skipped, 'unixepoch') AS skip_date,
+            Artists.name AS artist, Artists.sort_artist, AlbumArtists.name AS album_artist,
+            Composers.name AS composer, Albums.name AS album, Albums.sort_album,
+            Albums.rating AS album_rating, Albums.loved AS album_loved, Genres.name AS genre
+        FROM Tracks
+        LEFT JOIN Artists ON Tracks.artist_id = Artists.artist_id
+        LEFT JOIN Artists AS AlbumArtists ON Tracks.album_artist_id = AlbumArtists.artist_id
+        LEFT JOIN Composers ON Tracks.composer_id = Composers.composer_id
+        LEFT JOIN Albums ON Tracks.album_id = Albums.album_id
+        LEFT JOIN Genres ON Tracks.genre_id = Genres.genre_id
+        ORDER BY Tracks.track_id
+        "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+    let mut tracks: Vec<LibraryTrack> = Vec::new();
+
+    // Keys in the order iTunes writes them, empty values are left out
+    let string_keys = [
+        ("Name", "name"),
+        ("Artist", "artist"),
+        ("Album Artist", "album_artist"),
+        ("Composer", "composer"),
+        ("Album", "album"),
+        ("Grouping", "grouping"),
+        ("Genre", "genre"),
+        ("Kind", "kind"),
+    ];
+    let integer_keys = [
+        ("Size", "size"),
+        ("Total Time", "total_time"),
+        ("Start Time", "start_time"),
+        ("Stop Time", "stop_time"),
+        ("Disc Number", "disc_number"),
+        ("Disc Count", "disc_count"),
+        ("Track Number", "track_number"),
+        ("Track Count", "track_count"),
+        ("Year", "year"),
+        ("BPM", "bpm"),
+        ("Bit Rate", "bit_rate"),
+        ("Sample Rate", "sample_rate"),
+        ("Volume Adjustment", "volume_adjustment"),
+        ("Play Count", "plays"),
+        ("Skip Count", "skips"),
+        ("Rating", "rating"),
+        ("Album Rating", "album_rating"),
+        ("Normalization", "normalization"),
+        ("Artwork Count", "artwork_count"),
+    ];
+    let date_keys = [
+        ("Date Modified", "date_modified"),
+        ("Date Added", "date_added"),
+        ("Play Date UTC", "play_date"),
+        ("Skip Date", "skip_date"),
+        ("Release Date", "release_date"),
+    ];
+    let sort_keys = [
+        ("Comments", "comments"),
+        ("Sort Album", "sort_album"),
+        ("Sort Artist", "sort_artist"),
+        ("Sort Name", "sort_name"),
+    ];
+
+    while let Ok(State::Row) = stmt.next() {
+        let track_id = stmt.read::<i64, _>("track_id").unwrap_or_default();
+        let mut values: Vec<(&'static str, PlistValue)> =
+            vec![("Track ID", PlistValue::Integer(track_id))];
+
+        for (key, column) in string_keys {
+            let text = stmt.read::<String, _>(column).unwrap_or_default();
+            if !text.is_empty() {
+                values.push((key, PlistValue::String(text)));
+            }
+        }
+        for (key, column) in integer_keys {
+            let number = stmt.read::<i64, _>(column).unwrap_or_default();
+            if number != 0 {
+                values.push((key, PlistValue::Integer(number)));
+            }
+        }
+        for (key, column) in date_keys {
+            if let Ok(Some(date)) = stmt.read::<Option<String>, _>(column) {
+                values.push((key, PlistValue::Date(date)));
+            }
+        }
+        for (key, column) in sort_keys {
+            let text = stmt.read::<String, _>(column).unwrap_or_default();
+            if !text.is_empty() {
+                values.push((key, PlistValue::String(text)));
+            }
+        }
+
+        match stmt.read::<i64, _>("loved").unwrap_or_default() {
+            1 => values.push(("Loved", PlistValue::Bool(true))),
+            -1 => values.push(("Disliked", PlistValue::Bool(true))),
+            _ => {}
+        }
+        match stmt.read::<i64, _>("album_loved").unwrap_or_default() {
+            1 => values.push(("Album Loved", PlistValue::Bool(true))),
+            -1 => values.push(("Album Disliked", PlistValue::Bool(true))),
+            _ => {}
+        }
+        if stmt.read::<i64, _>("compilation").unwrap_or_default() == 1 {
+            values.push(("Compilation", PlistValue::Bool(true)));
+        }
+
+        values.push((
+            "Persistent ID",
+            PlistValue::String(persistent_id(
+                &stmt.read::<String, _>("persistent_id").unwrap_or_default(),
+                TRACK_ID_TAG,
+                track_id,
+            )),
+        ));
+        values.push(("Track Type", PlistValue::String("File".to_string())));
+
+        let location = stmt.read::<String, _>("location").unwrap_or_default();
+        if !location.is_empty() {
+            values.push(("Location", PlistValue::String(location_to_url(&location))));
+        }
+
+        values.push((
+            "File Folder Count",
+            PlistValue::Integer(stmt.read::<i64, _>("file_folder_count").unwrap_or_default()),
+        ));
+        values.push((
+            "Library Folder Count",
+            PlistValue::Integer(
+                stmt.read::<i64, _>("library_folder_count")
+                    .unwrap_or_default(),
+            ),
+        ));
+
+        tracks.push(LibraryTrack { track_id, values });
+    }
+
+    return tracks;
+}
+
+/*
+Smart playlists and folders are exported with their resolved tracks, iTunes does the same for folders.
+Without an imported master playlist a "Library" master playlist with all tracks is added, many tools expect one.
+ */
+fn get_library_playlists(conn: &Connection, all_track_ids: Vec<i64>) -> Vec<LibraryPlaylist> {
+    // Persistent ids are needed up front, parents can come after their children
+    let mut persistent_ids: HashMap<i64, String> = HashMap::new();
+    let mut has_master = false;
+
+    let mut stmt = conn
+        .prepare("SELECT playlist_id, playlist_persistent_id, master FROM Playlists")
+        .unwrap();
+    while let Ok(State::Row) = stmt.next() {
+        let playlist_id = stmt.read::<i64, _>("playlist_id").unwrap_or_default();
+        persistent_ids.insert(
+            playlist_id,
+            persistent_id(
+                &stmt
+                    .read::<String, _>("playlist_persistent_id")
+                    .unwrap_or_default(),
+                PLAYLIST_ID_TAG,
+                playlist_id,
+            ),
+        );
+        if stmt.read::<i64, _>("master").unwrap_or_default() == 1 {
+            has_master = true;
+        }
+    }
+
+    let mut playlists: Vec<LibraryPlaylist> = Vec::new();
+
+    if !has_master {
+        playlists.push(LibraryPlaylist {
+            values: vec![
+                ("Master", PlistValue::Bool(true)),
+                ("Playlist ID", PlistValue::Integer(0)),
+                (
+                    "Playlist Persistent ID",
+                    PlistValue::String(persistent_id("", PLAYLIST_ID_TAG, 0)),
+                ),
+                ("All Items", PlistValue::Bool(true)),
+                ("Visible", PlistValue::Bool(false)),
+                ("Name", PlistValue::String("Library".to_string())),
+            ],
+            track_ids: all_track_ids,
+        });
+    }
+
+    let query = r#"
+        SELECT playlist_id, name, description, master, distinguished_kind, visible, all_items,
+            folder, parent_id
+        FROM Playlists
+        ORDER BY master DESC, playlist_id
+        "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+
+    while let Ok(State::Row) = stmt.next() {
+        let playlist_id = stmt.read::<i64, _>("playlist_id").unwrap_or_default();
+        let mut values: Vec<(&'static str, PlistValue)> = Vec::new();
+
+        if stmt.read::<i64, _>("master").unwrap_or_default() == 1 {
+            values.push(("Master", PlistValue::Bool(true)));
+        }
+        values.push(("Playlist ID", PlistValue::Integer(playlist_id)));
+        values.push((
+            "Playlist Persistent ID",
+            PlistValue::String(persistent_ids[&playlist_id].clone()),
+        ));
+        let parent_id = stmt.read::<i64, _>("parent_id").unwrap_or_default();
+        if let Some(parent_persistent_id) = persistent_ids.get(&parent_id) {
+            values.push((
+                "Parent Persistent ID",
+                PlistValue::String(parent_persistent_id.clone()),
+            ));
+        }
+        let distinguished_kind = stmt
+            .read::<i64, _>("distinguished_kind")
+            .unwrap_or_default();
+        if distinguished_kind != 0 {
+            values.push((
+                "Distinguished Kind",
+                PlistValue::Integer(distinguished_kind),
+            ));
+        }
+        values.push((
+            "All Items",
+            PlistValue::Bool(stmt.read::<i64, _>("all_items").unwrap_or(1) == 1),
+        ));
+        if stmt.read::<i64, _>("visible").unwrap_or(1) == 0 {
+            values.push(("Visible", PlistValue::Bool(false)));
+        }
+        if stmt.read::<i64, _>("folder").unwrap_or_default() == 1 {
+            values.push(("Folder", PlistValue::Bool(true)));
+        }
+        values.push((
+            "Name",
+            PlistValue::String(stmt.read::<String, _>("name").unwrap_or_default()),
+        ));
+        let description = stmt.read::<String, _>("description").unwrap_or_default();
+        if !description.is_empty() {
+            values.push(("Description", PlistValue::String(description)));
+        }
+
+        let track_ids = get_audiotracks_from_playlist_by_id(conn, &vec![playlist_id])
+            .unwrap_or_default()
+            .iter()
+            .map(|audiotrack| audiotrack.id)
+            .collect();
+
+        playlists.push(LibraryPlaylist { values, track_ids });
+    }
+
+    return playlists;
+}
+
+fn get_utc_now(conn: &Connection) -> String {
+    let mut stmt = conn
+        .prepare("SELECT strftime('%Y-%m-%dT%H:%M:%SZ', 'now') AS now")
+        .unwrap();
+
+    if let Ok(State::Row) = stmt.next() {
+        return stmt.read::<String, _>("now").unwrap_or_default();
+    }
+
+    return "".to_string();
+}
+
+fn get_track_id_by_location(conn: &Connection, location: &str) -> Option<i64> {
+    let query = r#"
+        SELECT track_id
+        FROM Tracks
+        WHERE location = ?1
+        "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+    stmt.bind((1, location)).unwrap();
+
+    if let Ok(State::Row) = stmt.next() {
+        return stmt.read::<i64, _>("track_id").ok();
+    }
+
+    return None;
+}
+
+// Imported playlists only know the persistent id of their parent folder
+fn link_playlist_parents(conn: &Connection) {
+    let query = r#"
+        UPDATE Playlists
+        SET parent_id = (
+            SELECT Parent.playlist_id
+            FROM Playlists Parent
+            WHERE Parent.playlist_persistent_id = Playlists.parent_persistent_id
+            LIMIT 1
+        )
+        WHERE parent_persistent_id IS NOT NULL AND parent_persistent_id != ''
+        "#
+    .to_string();
+
+    match conn.execute(query) {
+        Ok(()) => {}
+        Err(error) => {
+            error!("{}", error);
+        }
+    }
+}
+
+fn is_playlist_folder(conn: &Connection, playlist_id: i64) -> bool {
+    let query = r#"
+        SELECT folder
+        FROM Playlists
+        WHERE playlist_id = ?1
+        "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+    stmt.bind((1, playlist_id)).unwrap();
+
+    if let Ok(State::Row) = stmt.next() {
+        return stmt.read::<i64, _>("folder").unwrap_or_default() == 1;
+    }
+
+    return false;
+}
+
+/*
+Returns all playlists and folders below the given folder, depth first and ordered by name.
+A folder comes before its children.
+ */
+fn get_playlist_descendants(conn: &Connection, folder_id: i64) -> Vec<i64> {
+    let query = r#"
+        SELECT playlist_id
+        FROM Playlists
+        WHERE parent_id = ?1
+        ORDER BY LOWER(name) ASC
+        "#
+    .to_string();
+
+    let mut children: Vec<i64> = Vec::new();
+
+    let mut stmt = conn.prepare(query).unwrap();
+    stmt.bind((1, folder_id)).unwrap();
+
+    while let Ok(State::Row) = stmt.next() {
+        children.push(stmt.read::<i64, _>("playlist_id").unwrap_or_default());
+    }
+
+    let mut descendants: Vec<i64> = Vec::new();
+    for child in children {
+        // Guards against cycles from broken imports
+        if child == folder_id || descendants.contains(&child) {
+            continue;
+        }
+        descendants.push(child);
+        descendants.append(&mut get_playlist_descendants(conn, child));
+    }
+
+    return descendants;
+}
+
+fn collect_files_in_directory(dir_path: &Path, paths_vec: &mut Vec<String>, max_level: u8) {
+    if let Ok(entries) = fs::read_dir(dir_path) {
+        for entry in entries {
+            if let Ok(entry) = entry {
+                let entry_path = entry.path();
+                if entry_path.is_file() {
+                    paths_vec.push(entry_path.to_string_lossy().into_owned());
+                } else if entry_path.is_dir() {
+                    if max_level != 0 {
+                        collect_files_in_directory(&entry_path, paths_vec, max_level - 1);
                     }
                 }
             }
@@ -3795,9 +5493,22 @@ fn collect_files_in_directory(dir_path: &Path, paths_vec: &mut Vec<String>, max_
     }
 }
 
-fn increment_plays_for_track(conn: &Connection, id: i64) {
+fn increment_plays_for_track(conn: &Connection, id: i64, last_played: i64) {
+    let query = r#"
+        UPDATE Tracks SET plays = plays + 1, last_played = ? WHERE track_id = ?
+        "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+    stmt.bind((1, last_played)).unwrap();
+    stmt.bind((2, id)).unwrap();
+
+    stmt.next().unwrap();
+}
+
+fn increment_skips_for_track(conn: &Connection, id: i64) {
     let query = r#"
-        UPDATE Tracks SET plays = plays + 1 WHERE track_id = ?
+        UPDATE Tracks SET skips = skips + 1 WHERE track_id = ?
         "#
     .to_string();
 
@@ -3807,6 +5518,473 @@ fn increment_plays_for_track(conn: &Connection, id: i64) {
     stmt.next().unwrap();
 }
 
+fn set_album_rating_and_loved(conn: &Connection, album_id: i64, rating: i64, loved: i64) {
+    let query = r#"
+        UPDATE Albums SET rating = ?1, loved = ?2 WHERE album_id = ?3
+        "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+    stmt.bind((1, rating)).unwrap();
+    stmt.bind((2, loved)).unwrap();
+    stmt.bind((3, album_id)).unwrap();
+
+    stmt.next().unwrap();
+}
+
+/*
+Sets column (rating or loved) of the tracks or albums with the given ids.
+Other datatypes are not rated.
+ */
+fn set_rating_column(
+    conn: &Connection,
+    datatype: &DataType,
+    ids: &Vec<i64>,
+    column: &str,
+    value: i64,
+) {
+    let table = match datatype {
+        DataType::Track => "Tracks",
+        DataType::Album => "Albums",
+        _ => {
+            debug!("{:?} cannot be rated", datatype);
+            return;
+        }
+    };
+    let id_column = match datatype {
+        DataType::Album => "album_id",
+        _ => "track_id",
+    };
+
+    let query = format!(
+        "UPDATE {} SET {} = ?1 WHERE {} = ?2",
+        table, column, id_column
+    );
+
+    for id in ids {
+        let mut stmt = conn.prepare(query.as_str()).unwrap();
+        stmt.bind((1, value)).unwrap();
+        stmt.bind((2, *id)).unwrap();
+
+        match stmt.next() {
+            Ok(_) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+}
+
+fn get_track_location(conn: &Connection, track_id: i64) -> Option<String> {
+    let query = r#"
+        SELECT location
+        FROM Tracks
+        WHERE track_id = ?1
+        "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+    stmt.bind((1, track_id)).unwrap();
+
+    if let Ok(State::Row) = stmt.next() {
+        return stmt.read::<String, _>("location").ok();
+    }
+
+    return None;
+}
+
+fn get_source_datatype(source_type: &str) -> Option<DataType> {
+    match source_type {
+        "Album" => return Some(DataType::Album),
+        "Artist" => return Some(DataType::Artist),
+        "Composer" => return Some(DataType::Composer),
+        "Genre" => return Some(DataType::Genre),
+        "Playlist" => return Some(DataType::Playlist),
+        _ => return None,
+    }
+}
+
+// Tracks played from a single album, artist, ... keep it as their source
+fn get_play_source(datatype: &DataType, vec_id: &Vec<i64>) -> Option<PlaySource> {
+    match datatype {
+        DataType::Track | DataType::Cover | DataType::Video => return None,
+        _ => {}
+    }
+
+    if vec_id.len() != 1 {
+        return None;
+    }
+
+    return Some(PlaySource {
+        datatype: datatype.clone(),
+        id: vec_id[0],
+    });
+}
+
+fn insert_play_history(conn: &Connection, played_track: &PlayedTrack) {
+    let query = r#"
+        INSERT INTO PlayHistory (
+            track_id, started_at, duration_listened, completed, skipped, source_type, source_id
+        ) VALUES (
+            ?1, ?2, ?3, ?4, ?5, ?6, ?7
+        )
+        "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+    stmt.bind((1, played_track.track_id)).unwrap();
+    stmt.bind((2, played_track.started_at)).unwrap();
+    stmt.bind((3, played_track.duration_listened)).unwrap();
+    stmt.bind((4, played_track.completed as i64)).unwrap();
+    stmt.bind((5, played_track.skipped as i64)).unwrap();
+    match &played_track.source {
+        Some(source) => {
+            stmt.bind((6, format!("{:?}", source.datatype).as_str()))
+                .unwrap();
+            stmt.bind((7, source.id)).unwrap();
+        }
+        None => {
+            stmt.bind((6, ())).unwrap();
+            stmt.bind((7, ())).unwrap();
+        }
+    }
+
+    match stmt.next() {
+        Ok(_) => {}
+        Err(error) => {
+            error!("{}", error);
+        }
+    }
+}
+
+/*
+Listens grouped by column of Tracks within from and to, most played first.
+Listens of deleted tracks are not part of the stats.
+ */
+fn get_top_by_column(
+    conn: &Connection,
+    column: &str,
+    from: i64,
+    to: i64,
+    limit: i64,
+) -> Vec<PlayStats> {
+    let query = format!(
+        r#"
+        SELECT Tracks.{column} AS id,
+            SUM(PlayHistory.completed) AS plays,
+            SUM(PlayHistory.skipped) AS skips,
+            SUM(PlayHistory.duration_listened) AS duration_listened
+        FROM PlayHistory
+        JOIN Tracks ON Tracks.track_id = PlayHistory.track_id
+        WHERE PlayHistory.started_at >= ? AND PlayHistory.started_at < ?
+        AND IFNULL(Tracks.{column}, 0) != 0
+        GROUP BY Tracks.{column}
+        ORDER BY plays DESC, duration_listened DESC
+        LIMIT ?
+        "#
+    );
+
+    let mut stmt = conn.prepare(query).unwrap();
+    stmt.bind((1, from)).unwrap();
+    stmt.bind((2, to)).unwrap();
+    stmt.bind((3, limit)).unwrap();
+
+    let mut vec_stats: Vec<PlayStats> = vec![];
+
+    while let Ok(State::Row) = stmt.next() {
+        vec_stats.push(PlayStats {
+            id: stmt.read::<i64, _>("id").unwrap_or_default(),
+            plays: stmt.read::<i64, _>("plays").unwrap_or_default(),
+            skips: stmt.read::<i64, _>("skips").unwrap_or_default(),
+            duration_listened: stmt.read::<i64, _>("duration_listened").unwrap_or_default(),
+        });
+    }
+
+    return vec_stats;
+}
+
+fn get_recently_played(conn: &Connection, limit: i64) -> Vec<PlayedTrack> {
+    let query = r#"
+        SELECT PlayHistory.*
+        FROM PlayHistory
+        JOIN Tracks ON Tracks.track_id = PlayHistory.track_id
+        ORDER BY PlayHistory.started_at DESC, PlayHistory.play_id DESC
+        LIMIT ?
+        "#
+    .to_string();
+
+    let mut stmt = conn.prepare(query).unwrap();
+    stmt.bind((1, limit)).unwrap();
+
+    let mut played_tracks: Vec<PlayedTrack> = vec![];
+
+    while let Ok(State::Row) = stmt.next() {
+        let source_type = stmt.read::<String, _>("source_type").unwrap_or_default();
+        let source = match get_source_datatype(&source_type) {
+            Some(datatype) => Some(PlaySource {
+                datatype: datatype,
+                id: stmt.read::<i64, _>("source_id").unwrap_or_default(),
+            }),
+            None => None,
+        };
+
+        played_tracks.push(PlayedTrack {
+            track_id: stmt.read::<i64, _>("track_id").unwrap_or_default(),
+            started_at: stmt.read::<i64, _>("started_at").unwrap_or_default(),
+            duration_listened: stmt.read::<i64, _>("duration_listened").unwrap_or_default(),
+            completed: stmt.read::<i64, _>("completed").unwrap_or_default() != 0,
+            skipped: stmt.read::<i64, _>("skipped").unwrap_or_default() != 0,
+            source: source,
+        });
+    }
+
+    return played_tracks;
+}
+
+// Errors of requests the user is waiting for, e.g. an export
+fn emit_error(app: &AppHandle, error: String) {
+    error!("{}", error);
+    let _ = app.emit(
+        "backend_message",
+        BackendMessage {
+            notification: None,
+            error: Some(error),
+            warning: None,
+            progress: None,
+        },
+    );
+}
+
+fn emit_loudness_progress(app: &AppHandle, value: Option<i64>, done: bool) {
+    let _ = app.emit(
+        "backend_message",
+        BackendMessage {
+            notification: None,
+            error: None,
+            warning: None,
+            progress: Some(Progress {
+                info: ProgressInfo::LoudnessScan,
+                value: value,
+                done: done,
+            }),
+        },
+    );
+}
+
+/*
+Measure loudness of all tracks without ReplayGain values and store the result as ReplayGain.
+Runs in its own thread with its own connection, since decoding takes a while.
+Albums are scanned completely if any of their tracks is missing the album gain.
+ */
+fn scan_loudness(app: AppHandle, db_path: PathBuf, is_scanning: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        let mut conn = match sqlite::open(db_path.as_path()) {
+            Ok(conn) => conn,
+            Err(error) => {
+                error!("{}", error);
+                is_scanning.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        // The db thread uses the db at the same time
+        let _ = conn.set_busy_timeout(5000);
+
+        emit_loudness_progress(&app, None, false);
+
+        // Tracks that failed before are skipped, albums with such a track get no album gain
+        let query = r#"
+        WITH ScanAlbums AS (
+            SELECT album_id
+            FROM Tracks
+            WHERE replaygain_album_gain IS NULL AND album_id > 0
+            EXCEPT
+            SELECT album_id
+            FROM Tracks
+            WHERE loudness_scan_failed = 1
+        )
+        SELECT track_id, album_id, location, replaygain_track_gain,
+            album_id IN (SELECT album_id FROM ScanAlbums) AS scan_album
+        FROM Tracks
+        WHERE (replaygain_track_gain IS NULL AND loudness_scan_failed = 0)
+        OR album_id IN (SELECT album_id FROM ScanAlbums)
+        ORDER BY album_id
+        "#
+        .to_string();
+
+        // Tracks grouped by album, tracks without album are a group of their own
+        // The album gain is only measured if all tracks of the album are in the group
+        let mut groups: Vec<(i64, bool, Vec<(i64, String, bool)>)> = vec![];
+
+        let mut stmt = conn.prepare(query).unwrap();
+        while let Ok(State::Row) = stmt.next() {
+            let track_id = stmt.read::<i64, _>("track_id").unwrap_or_default();
+            let album_id = stmt.read::<i64, _>("album_id").unwrap_or_default();
+            let location = stmt.read::<String, _>("location").unwrap_or_default();
+            let has_track_gain = stmt
+                .read::<Option<f64>, _>("replaygain_track_gain")
+                .unwrap_or_default()
+                .is_some();
+            let scan_album = stmt.read::<i64, _>("scan_album").unwrap_or_default() == 1;
+
+            match groups.last_mut() {
+                Some((last_album_id, _, tracks)) if album_id > 0 && *last_album_id == album_id => {
+                    tracks.push((track_id, location, has_track_gain));
+                }
+                _ => {
+                    groups.push((
+                        album_id,
+                        scan_album,
+                        vec![(track_id, location, has_track_gain)],
+                    ));
+                }
+            }
+        }
+        drop(stmt);
+
+        let mut count = 0;
+
+        for (album_id, scan_album, tracks) in groups {
+            let mut album_blocks: Vec<f64> = vec![];
+            let mut album_peak: f64 = 0.0;
+            let mut album_failed = false;
+
+            for (track_id, location, has_track_gain) in tracks {
+                if let Some(track_loudness) = measure_loudness(Path::new(&location)) {
+                    if !has_track_gain {
+                        let mut stmt = conn
+                            .prepare(
+                                "UPDATE Tracks
+                                SET replaygain_track_gain = ?1, replaygain_track_peak = ?2
+                                WHERE track_id = ?3",
+                            )
+                            .unwrap();
+                        stmt.bind((1, get_replaygain(track_loudness.integrated)))
+                            .unwrap();
+                        stmt.bind((2, track_loudness.true_peak)).unwrap();
+                        stmt.bind((3, track_id)).unwrap();
+
+                        match stmt.next() {
+                            Ok(_) => {}
+                            Err(error) => {
+                                error!("{}", error);
+                            }
+                        }
+                    }
+
+                    album_peak = album_peak.max(track_loudness.true_peak);
+                    album_blocks.extend(track_loudness.blocks);
+                } else {
+                    error!("Could not measure loudness of {}", location);
+                    album_failed = true;
+
+                    let mut stmt = conn
+                        .prepare("UPDATE Tracks SET loudness_scan_failed = 1 WHERE track_id = ?1")
+                        .unwrap();
+                    stmt.bind((1, track_id)).unwrap();
+
+                    match stmt.next() {
+                        Ok(_) => {}
+                        Err(error) => {
+                            error!("{}", error);
+                        }
+                    }
+                }
+
+                count += 1;
+                emit_loudness_progress(&app, Some(count), false);
+            }
+
+            // The gain of the remaining tracks would be wrong for the whole album
+            if album_id > 0 && scan_album && !album_failed {
+                if let Some(album_loudness) = get_gated_loudness(&album_blocks) {
+                    let mut stmt = conn
+                        .prepare(
+                            "UPDATE Tracks
+                            SET replaygain_album_gain = ?1, replaygain_album_peak = ?2
+                            WHERE album_id = ?3",
+                        )
+                        .unwrap();
+                    stmt.bind((1, get_replaygain(album_loudness))).unwrap();
+                    stmt.bind((2, album_peak)).unwrap();
+                    stmt.bind((3, album_id)).unwrap();
+
+                    match stmt.next() {
+                        Ok(_) => {}
+                        Err(error) => {
+                            error!("{}", error);
+                        }
+                    }
+                }
+            }
+        }
+
+        is_scanning.store(false, Ordering::SeqCst);
+        emit_loudness_progress(&app, None, true);
+    });
+}
+
+/*
+Probe encoder delay and padding of all tracks that were not added from their file, e.g. imported from library.xml.
+Runs in its own thread with its own connection, only the file headers are read.
+ */
+fn probe_gapless_info(db_path: PathBuf, is_probing: Arc<AtomicBool>) {
+    // Only one probe at a time
+    if is_probing.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    thread::spawn(move || {
+        let mut conn = match sqlite::open(db_path.as_path()) {
+            Ok(conn) => conn,
+            Err(error) => {
+                error!("{}", error);
+                is_probing.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        // The db thread uses the db at the same time
+        let _ = conn.set_busy_timeout(5000);
+
+        let mut tracks: Vec<(i64, String)> = vec![];
+        let mut stmt = conn
+            .prepare("SELECT track_id, location FROM Tracks WHERE encoder_delay IS NULL")
+            .unwrap();
+        while let Ok(State::Row) = stmt.next() {
+            tracks.push((
+                stmt.read::<i64, _>("track_id").unwrap_or_default(),
+                stmt.read::<String, _>("location").unwrap_or_default(),
+            ));
+        }
+        drop(stmt);
+
+        for (track_id, location) in tracks {
+            // Files that cannot be probed are stored without gapless info, so they are not probed again
+            let gapless = extract_gapless_info(Path::new(&location)).unwrap_or_default();
+
+            let mut stmt = conn
+                .prepare(
+                    "UPDATE Tracks
+                    SET encoder_delay = ?1, encoder_padding = ?2, frame_count = ?3
+                    WHERE track_id = ?4",
+                )
+                .unwrap();
+            stmt.bind((1, gapless.encoder_delay)).unwrap();
+            stmt.bind((2, gapless.encoder_padding)).unwrap();
+            stmt.bind((3, gapless.frame_count)).unwrap();
+            stmt.bind((4, track_id)).unwrap();
+
+            match stmt.next() {
+                Ok(_) => {}
+                Err(error) => {
+                    error!("{}", error);
+                }
+            }
+        }
+
+        is_probing.store(false, Ordering::SeqCst);
+    });
+}
+
 pub fn create_sqlite_instance(
     app: AppHandle,
     db_receiver: Receiver<DBRequest>,
@@ -3816,15 +5994,26 @@ pub fn create_sqlite_instance(
     thread::spawn(move || {
         match app.path().app_local_data_dir() {
             Ok(mut data_path) => {
+                // Shared by the release and the test db, the files are content-addressed
+                let covers_dir = covers_dir(&data_path);
+
                 if tauri::is_dev() {
                     data_path.push("debug.sqlite"); // test db
                 } else {
                     data_path.push("db.sqlite"); // release db
                 }
 
-                let conn = sqlite::open(data_path.as_path()).unwrap();
+                let mut conn = sqlite::open(data_path.as_path()).unwrap();
+                // The loudness scan writes from its own thread
+                let _ = conn.set_busy_timeout(5000);
 
-                is_db_or_init(&conn);
+                is_db_or_init(&conn, &covers_dir);
+
+                let is_scanning = Arc::new(AtomicBool::new(false));
+
+                // Tracks of an older db or an unfinished probe
+                let is_probing = Arc::new(AtomicBool::new(false));
+                probe_gapless_info(data_path.clone(), is_probing.clone());
 
                 let mut db_state = get_db_state(&conn);
 
@@ -3839,7 +6028,6 @@ pub fn create_sqlite_instance(
                         artist_tracks: None,
                         composers: None,
                         composer_tracks: None,
-                        covers: None,
                         genres: None,
                         genre_tracks: None,
                         playlists: None,
@@ -3853,95 +6041,120 @@ pub fn create_sqlite_instance(
                         tracks_order: None,
                         error: None,
                         loading: None,
-                        is_init: None,
-                    };
-
-                    // Wait for user input
-                    let received_request = db_receiver.recv();
-
-                    match received_request {
-                        Ok(result) => match result {
-                            DBRequest::Play(datatype, vec_id, opt_vec_order) => match datatype {
-                                DataType::Album => {
-                                    println!("DBRequest::Play::Album {:?}", vec_id[0]);
-                                    if let Some(audiotracks) =
-                                        get_audiotracks_from_album_by_id(&conn, &vec_id)
-                                    {
-                                        let _ = data_sender.send(DBData::Play(audiotracks));
-                                    }
-                                }
-                                DataType::Playlist => {
-                                    println!("DBRequest::Play::Playlist {:?}", vec_id[0]);
-                                    if let Some(audiotracks) =
-                                        get_audiotracks_from_playlist_by_id(&conn, &vec_id)
-                                    {
-                                        let _ = data_sender.send(DBData::Play(audiotracks));
-                                    }
-                                }
-                                DataType::Track => {
-                                    println!("DBRequest::Play::Track {:?}", vec_id[0]);
-                                    if let Some(audiotracks) = get_audiotracks_by_id(&conn, &vec_id)
-                                    {
-                                        let _ = data_sender.send(DBData::Play(audiotracks));
-                                    }
-                                }
-                                DataType::Artist => {
-                                    println!("DBRequest::Play::Artist {:?}", vec_id[0]);
-                                    if let Some(audiotracks) =
-                                        get_audiotracks_from_artist_by_id(&conn, &vec_id)
-                                    {
-                                        let _ = data_sender.send(DBData::Play(audiotracks));
-                                    }
-                                }
-                                DataType::Composer => {
-                                    println!("DBRequest::Play::Composer {:?}", vec_id[0]);
-                                    if let Some(vec_order) = opt_vec_order {
+                        is_init: None,
+                        top_tracks: None,
+                        top_albums: None,
+                        top_artists: None,
+                        recently_played: None,
+                    };
+
+                    // Wait for user input
+                    let received_request = db_receiver.recv();
+
+                    match received_request {
+                        Ok(result) => match result {
+                            DBRequest::Play(datatype, vec_id, opt_vec_order) => {
+                                let source = get_play_source(&datatype, &vec_id);
+                                match datatype {
+                                    DataType::Album => {
+                                        println!("DBRequest::Play::Album {:?}", vec_id[0]);
                                         if let Some(audiotracks) =
-                                            get_audiotracks_from_composer_by_id_and_order(
-                                                &conn, &vec_id, vec_order,
-                                            )
+                                            get_audiotracks_from_album_by_id(&conn, &vec_id)
                                         {
-                                            let _ = data_sender.send(DBData::Play(audiotracks));
+                                            let _ = data_sender
+                                                .send(DBData::Play(audiotracks, source.clone()));
                                         }
-                                    } else {
-                                        // default sort by name
+                                    }
+                                    DataType::Playlist => {
+                                        println!("DBRequest::Play::Playlist {:?}", vec_id[0]);
                                         if let Some(audiotracks) =
-                                            get_audiotracks_from_composer_by_id_and_order(
-                                                &conn,
-                                                &vec_id,
-                                                vec![Order::ByName],
-                                            )
+                                            get_audiotracks_from_playlist_by_id(&conn, &vec_id)
                                         {
-                                            let _ = data_sender.send(DBData::Play(audiotracks));
+                                            let _ = data_sender
+                                                .send(DBData::Play(audiotracks, source.clone()));
                                         }
                                     }
-                                }
-                                DataType::Genre => {
-                                    println!("DBRequest::Play::Genre {:?}", vec_id[0]);
-                                    if let Some(vec_order) = opt_vec_order {
+                                    DataType::Track => {
+                                        println!("DBRequest::Play::Track {:?}", vec_id[0]);
                                         if let Some(audiotracks) =
-                                            get_audiotracks_from_genre_by_id_and_order(
-                                                &conn, &vec_id, vec_order,
-                                            )
+                                            get_audiotracks_by_id(&conn, &vec_id)
                                         {
-                                            let _ = data_sender.send(DBData::Play(audiotracks));
+                                            let _ = data_sender
+                                                .send(DBData::Play(audiotracks, source.clone()));
                                         }
-                                    } else {
-                                        // default sort by name
+                                    }
+                                    DataType::Artist => {
+                                        println!("DBRequest::Play::Artist {:?}", vec_id[0]);
                                         if let Some(audiotracks) =
-                                            get_audiotracks_from_genre_by_id_and_order(
-                                                &conn,
-                                                &vec_id,
-                                                vec![Order::ByName],
-                                            )
+                                            get_audiotracks_from_artist_by_id(&conn, &vec_id)
                                         {
-                                            let _ = data_sender.send(DBData::Play(audiotracks));
+                                            let _ = data_sender
+                                                .send(DBData::Play(audiotracks, source.clone()));
+                                        }
+                                    }
+                                    DataType::Composer => {
+                                        println!("DBRequest::Play::Composer {:?}", vec_id[0]);
+                                        if let Some(vec_order) = opt_vec_order {
+                                            if let Some(audiotracks) =
+                                                get_audiotracks_from_composer_by_id_and_order(
+                                                    &conn, &vec_id, vec_order,
+                                                )
+                                            {
+                                                let _ = data_sender.send(DBData::Play(
+                                                    audiotracks,
+                                                    source.clone(),
+                                                ));
+                                            }
+                                        } else {
+                                            // default sort by name
+                                            if let Some(audiotracks) =
+                                                get_audiotracks_from_composer_by_id_and_order(
+                                                    &conn,
+                                                    &vec_id,
+                                                    vec![Order::ByName],
+                                                )
+                                            {
+                                                let _ = data_sender.send(DBData::Play(
+                                                    audiotracks,
+                                                    source.clone(),
+                                                ));
+                                            }
+                                        }
+                                    }
+                                    DataType::Genre => {
+                                        println!("DBRequest::Play::Genre {:?}", vec_id[0]);
+                                        if let Some(vec_order) = opt_vec_order {
+                                            if let Some(audiotracks) =
+                                                get_audiotracks_from_genre_by_id_and_order(
+                                                    &conn, &vec_id, vec_order,
+                                                )
+                                            {
+                                                let _ = data_sender.send(DBData::Play(
+                                                    audiotracks,
+                                                    source.clone(),
+                                                ));
+                                            }
+                                        } else {
+                                            // default sort by name
+                                            if let Some(audiotracks) =
+                                                get_audiotracks_from_genre_by_id_and_order(
+                                                    &conn,
+                                                    &vec_id,
+                                                    vec![Order::ByName],
+                                                )
+                                            {
+                                                let _ = data_sender.send(DBData::Play(
+                                                    audiotracks,
+                                                    source.clone(),
+                                                ));
+                                            }
                                         }
                                     }
+                                    _ => {}
                                 }
-                                _ => {}
-                            },
+                            }
                             DBRequest::QueueInsert(datatype, vec_id, opt_index, opt_vec_order) => {
+                                let source = get_play_source(&datatype, &vec_id);
                                 match datatype {
                                     DataType::Album => {
                                         println!(
@@ -3952,8 +6165,11 @@ pub fn create_sqlite_instance(
                                         if let Some(audiotracks) =
                                             get_audiotracks_from_album_by_id(&conn, &vec_id)
                                         {
-                                            let _ = data_sender
-                                                .send(DBData::QueueInsert(audiotracks, opt_index));
+                                            let _ = data_sender.send(DBData::QueueInsert(
+                                                audiotracks,
+                                                opt_index,
+                                                source.clone(),
+                                            ));
                                         }
                                     }
                                     DataType::Playlist => {
@@ -3965,8 +6181,11 @@ pub fn create_sqlite_instance(
                                         if let Some(audiotracks) =
                                             get_audiotracks_from_playlist_by_id(&conn, &vec_id)
                                         {
-                                            let _ = data_sender
-                                                .send(DBData::QueueInsert(audiotracks, opt_index));
+                                            let _ = data_sender.send(DBData::QueueInsert(
+                                                audiotracks,
+                                                opt_index,
+                                                source.clone(),
+                                            ));
                                         }
                                     }
                                     DataType::Track => {
@@ -3978,8 +6197,11 @@ pub fn create_sqlite_instance(
                                         if let Some(audiotracks) =
                                             get_audiotracks_by_id(&conn, &vec_id)
                                         {
-                                            let _ = data_sender
-                                                .send(DBData::QueueInsert(audiotracks, opt_index));
+                                            let _ = data_sender.send(DBData::QueueInsert(
+                                                audiotracks,
+                                                opt_index,
+                                                source.clone(),
+                                            ));
                                         }
                                     }
                                     DataType::Artist => {
@@ -3990,8 +6212,11 @@ pub fn create_sqlite_instance(
                                         if let Some(audiotracks) =
                                             get_audiotracks_from_artist_by_id(&conn, &vec_id)
                                         {
-                                            let _ = data_sender
-                                                .send(DBData::QueueInsert(audiotracks, opt_index));
+                                            let _ = data_sender.send(DBData::QueueInsert(
+                                                audiotracks,
+                                                opt_index,
+                                                source.clone(),
+                                            ));
                                         }
                                     }
                                     DataType::Composer => {
@@ -4008,6 +6233,7 @@ pub fn create_sqlite_instance(
                                                 let _ = data_sender.send(DBData::QueueInsert(
                                                     audiotracks,
                                                     opt_index,
+                                                    source.clone(),
                                                 ));
                                             }
                                         } else {
@@ -4022,6 +6248,7 @@ pub fn create_sqlite_instance(
                                                 let _ = data_sender.send(DBData::QueueInsert(
                                                     audiotracks,
                                                     opt_index,
+                                                    source.clone(),
                                                 ));
                                             }
                                         }
@@ -4040,6 +6267,7 @@ pub fn create_sqlite_instance(
                                                 let _ = data_sender.send(DBData::QueueInsert(
                                                     audiotracks,
                                                     opt_index,
+                                                    source.clone(),
                                                 ));
                                             }
                                         } else {
@@ -4054,6 +6282,7 @@ pub fn create_sqlite_instance(
                                                 let _ = data_sender.send(DBData::QueueInsert(
                                                     audiotracks,
                                                     opt_index,
+                                                    source.clone(),
                                                 ));
                                             }
                                         }
@@ -4070,8 +6299,14 @@ pub fn create_sqlite_instance(
                             DBRequest::NewPlaylist(playlist) => {
                                 let db_playlist = DBPlaylist {
                                     name: playlist.name,
+                                    folder: playlist.folder as i64,
+                                    parent_id: playlist.parent_id,
                                     tracks: playlist.tracks,
                                     description: playlist.description,
+                                    smart_criteria: match &playlist.smart_criteria {
+                                        Some(criteria) => serde_json::to_string(criteria).unwrap(),
+                                        None => "".to_string(),
+                                    },
                                     ..Default::default()
                                 };
                                 insert_playlist(&conn, db_playlist, false);
@@ -4084,19 +6319,166 @@ pub fn create_sqlite_instance(
                                 ));
                                 db_state = get_db_state(&conn);
                             }
+                            DBRequest::NewPlaylistFolder(name, parent_id) => {
+                                if parent_id == 0 || is_playlist_folder(&conn, parent_id) {
+                                    let db_playlist = DBPlaylist {
+                                        name: name,
+                                        folder: 1,
+                                        parent_id: parent_id,
+                                        ..Default::default()
+                                    };
+                                    insert_playlist(&conn, db_playlist, false);
+                                } else {
+                                    error!("playlist {} is not a folder", parent_id);
+                                }
+
+                                data.playlists = Some(get_playlists(&conn));
+                                data.playlists_order = Some((
+                                    vec![Order::ByName],
+                                    get_playlists_order(&conn, vec![Order::ByName]),
+                                ));
+                                db_state = get_db_state(&conn);
+                            }
+                            DBRequest::MovePlaylists(vec_id, parent_id) => {
+                                if parent_id == 0 || is_playlist_folder(&conn, parent_id) {
+                                    for playlist_id in vec_id {
+                                        // A folder cannot be moved into itself or below itself
+                                        if playlist_id == parent_id
+                                            || get_playlist_descendants(&conn, playlist_id)
+                                                .contains(&parent_id)
+                                        {
+                                            error!(
+                                                "cannot move playlist {} into {}",
+                                                playlist_id, parent_id
+                                            );
+                                            continue;
+                                        }
+
+                                        let query = r#"
+                                        UPDATE Playlists
+                                        SET parent_id = ?1, date_modified = CURRENT_TIMESTAMP
+                                        WHERE playlist_id = ?2
+                                        "#
+                                        .to_string();
+
+                                        let mut stmt = conn.prepare(query).unwrap();
+                                        if parent_id > 0 {
+                                            stmt.bind((1, parent_id)).unwrap();
+                                        } else {
+                                            stmt.bind((1, ())).unwrap();
+                                        }
+                                        stmt.bind((2, playlist_id)).unwrap();
+                                        stmt.next().unwrap();
+                                    }
+                                } else {
+                                    error!("playlist {} is not a folder", parent_id);
+                                }
+
+                                data.playlists = Some(get_playlists(&conn));
+                            }
+                            DBRequest::RenamePlaylistFolder(folder_id, name) => {
+                                let query = r#"
+                                UPDATE Playlists
+                                SET name = ?1, date_modified = CURRENT_TIMESTAMP
+                                WHERE playlist_id = ?2 AND folder = 1
+                                "#
+                                .to_string();
+
+                                let mut stmt = conn.prepare(query).unwrap();
+                                stmt.bind((1, name.as_str())).unwrap();
+                                stmt.bind((2, folder_id)).unwrap();
+                                stmt.next().unwrap();
+
+                                data.playlists = Some(get_playlists(&conn));
+                                data.playlists_order = Some((
+                                    vec![Order::ByName],
+                                    get_playlists_order(&conn, vec![Order::ByName]),
+                                ));
+                            }
+                            DBRequest::ExportPlaylist(playlist_id, format, export_path) => {
+                                if let Some((name, entries)) =
+                                    get_playlist_entries(&conn, playlist_id)
+                                {
+                                    match write_playlist_file(
+                                        Path::new(&export_path),
+                                        &name,
+                                        &entries,
+                                        &format,
+                                        &config_state.playlist_export_paths,
+                                        &config_state.media_path,
+                                    ) {
+                                        Ok(()) => {
+                                            debug!(
+                                                "exported playlist {} to {}",
+                                                playlist_id, export_path
+                                            );
+                                        }
+                                        Err(error) => {
+                                            emit_error(&app, format!("{}: {}", export_path, error));
+                                        }
+                                    }
+                                } else {
+                                    emit_error(&app, format!("playlist {} not found", playlist_id));
+                                }
+                            }
+                            DBRequest::ExportLibrary(export_path) => {
+                                let tracks = get_library_tracks(&conn);
+                                let all_track_ids: Vec<i64> =
+                                    tracks.iter().map(|track| track.track_id).collect();
+                                let playlists = get_library_playlists(&conn, all_track_ids);
+                                let date = get_utc_now(&conn);
+
+                                match write_library_xml(
+                                    Path::new(&export_path),
+                                    &config_state.media_path,
+                                    &date,
+                                    &tracks,
+                                    &playlists,
+                                ) {
+                                    Ok(()) => {
+                                        debug!(
+                                            "exported {} tracks and {} playlists to {}",
+                                            tracks.len(),
+                                            playlists.len(),
+                                            export_path
+                                        );
+                                    }
+                                    Err(error) => {
+                                        emit_error(&app, format!("{}: {}", export_path, error));
+                                    }
+                                }
+                            }
                             DBRequest::AddToLibrary(paths) => {
                                 let mut paths_to_check: Vec<String> = paths;
                                 let mut import_paths: Vec<String> = vec![];
+                                let mut playlist_paths: Vec<String> = vec![];
+                                // Imported file path and its location in the library, which differs when managing folders
+                                let mut imported_locations: HashMap<String, String> =
+                                    HashMap::new();
                                 let mut artists: HashMap<String, i64> = HashMap::new();
                                 let mut albums: HashMap<String, i64> = HashMap::new();
                                 let mut composers: HashMap<String, i64> = HashMap::new();
                                 let mut genres: HashMap<String, i64> = HashMap::new();
+                                // Images grouped by directory, cover candidates for tracks without embedded cover
+                                let mut directory_images: HashMap<PathBuf, Vec<PathBuf>> =
+                                    HashMap::new();
+                                let mut folder_covers: HashMap<PathBuf, Option<Image>> =
+                                    HashMap::new();
 
                                 while let Some(path_str) = paths_to_check.pop() {
                                     let path = Path::new(&path_str);
 
                                     if path.exists() {
-                                        if path.is_file() {
+                                        if path.is_file() && is_playlist_file(path) {
+                                            playlist_paths.push(path_str);
+                                        } else if path.is_file() && is_image_file(path) {
+                                            if let Some(directory) = path.parent() {
+                                                directory_images
+                                                    .entry(directory.to_path_buf())
+                                                    .or_default()
+                                                    .push(path.to_path_buf());
+                                            }
+                                        } else if path.is_file() {
                                             import_paths.push(path_str);
                                         } else if path.is_dir() {
                                             // Add all files in the directory to the paths vector, max search level 3
@@ -4111,6 +6493,33 @@ pub fn create_sqlite_instance(
                                     }
                                 }
 
+                                // Files referenced by playlists are imported as well, unless already in the library
+                                let mut playlists_to_import: Vec<(
+                                    String,
+                                    Vec<(String, Option<String>)>,
+                                )> = Vec::new();
+                                for playlist_path in &playlist_paths {
+                                    match read_playlist_file(Path::new(playlist_path)) {
+                                        Ok((name, entries)) => {
+                                            for (_, resolved) in &entries {
+                                                if let Some(location) = resolved {
+                                                    if !import_paths.contains(location)
+                                                        && get_track_id_by_location(&conn, location)
+                                                            .is_none()
+                                                        && Path::new(location).is_file()
+                                                    {
+                                                        import_paths.push(location.clone());
+                                                    }
+                                                }
+                                            }
+                                            playlists_to_import.push((name, entries));
+                                        }
+                                        Err(error) => {
+                                            error!("{}: {}", playlist_path, error);
+                                        }
+                                    }
+                                }
+
                                 let mut count = 0;
                                 // Process the import_paths vector
                                 for path_str in import_paths {
@@ -4123,12 +6532,6 @@ pub fn create_sqlite_instance(
                                     mp4 mp3 id3v2 aac id3v1 wav flac ogg vorbis pcm mkv alac apple-lossless m4a mp2 adpcm
                                      */
 
-                                    /*
-                                    TODO:
-                                    if the file is a jpg or png, we want to consider using it as album cover. Maybe?
-                                    Problem is that we cannot easily associate a file with audio files in the same folder.
-                                    Need to restructure import to be able to achieve this.
-                                     */
                                     if let Some(meta) = extract_metadata(path) {
                                         let mut track = DBTrack {
                                             orig_track_id: 0,
@@ -4153,7 +6556,7 @@ pub fn create_sqlite_instance(
                                             release_date: meta.release_date.unwrap_or_default(),
                                             normalization: 0,
                                             artwork_count: 0,
-                                            sort_name: "".to_string(),
+                                            sort_name: meta.sort_name.clone().unwrap_or_default(),
                                             persistent_id: "".to_string(),
                                             track_type: "".to_string(),
                                             purchased: 0,
@@ -4165,6 +6568,49 @@ pub fn create_sqlite_instance(
                                             location: "".to_string(),
                                             file_folder_count: 0,
                                             library_folder_count: 0,
+                                            replaygain: meta.replaygain.clone(),
+                                            gapless: meta.gapless.clone(),
+                                            rating: meta.rating.unwrap_or_default(),
+                                            loved: 0,
+                                            plays: 0,
+                                            skips: 0,
+                                            play_date: "".to_string(),
+                                            skip_date: "".to_string(),
+                                            comments: meta.comment.clone().unwrap_or_default(),
+                                            grouping: meta.grouping.clone().unwrap_or_default(),
+                                            bpm: meta.bpm.unwrap_or_default(),
+                                            volume_adjustment: 0,
+                                            start_time: 0,
+                                            stop_time: 0,
+                                            compilation: meta.compilation.unwrap_or_default()
+                                                as i64,
+                                            lyrics: meta.lyrics.clone().unwrap_or_default(),
+                                            isrc: meta.isrc.clone().unwrap_or_default(),
+                                            label: meta.label.clone().unwrap_or_default(),
+                                            original_date: meta
+                                                .original_date
+                                                .clone()
+                                                .unwrap_or_default(),
+                                            musicbrainz_track_id: meta
+                                                .musicbrainz_track_id
+                                                .clone()
+                                                .unwrap_or_default(),
+                                            musicbrainz_album_id: meta
+                                                .musicbrainz_album_id
+                                                .clone()
+                                                .unwrap_or_default(),
+                                            musicbrainz_artist_id: meta
+                                                .musicbrainz_artist_id
+                                                .clone()
+                                                .unwrap_or_default(),
+                                            musicbrainz_album_artist_id: meta
+                                                .musicbrainz_album_artist_id
+                                                .clone()
+                                                .unwrap_or_default(),
+                                            musicbrainz_release_group_id: meta
+                                                .musicbrainz_release_group_id
+                                                .clone()
+                                                .unwrap_or_default(),
                                         };
                                         /*
                                         TODO:
@@ -4178,7 +6624,11 @@ pub fn create_sqlite_instance(
                                             if let Some(id) = artists.get(artist) {
                                                 id.to_owned()
                                             } else {
-                                                let id = get_or_create_artist_id(&conn, artist, "");
+                                                let id = get_or_create_artist_id(
+                                                    &conn,
+                                                    artist,
+                                                    &meta.sort_artist.clone().unwrap_or_default(),
+                                                );
                                                 artists.insert(artist.to_string(), id);
                                                 id
                                             }
@@ -4195,7 +6645,10 @@ pub fn create_sqlite_instance(
                                                     let id = get_or_create_artist_id(
                                                         &conn,
                                                         album_artist,
-                                                        "",
+                                                        &meta
+                                                            .sort_album_artist
+                                                            .clone()
+                                                            .unwrap_or_default(),
                                                     );
                                                     artists.insert(album_artist.to_string(), id);
                                                     id
@@ -4265,7 +6718,7 @@ pub fn create_sqlite_instance(
                                                 let id = get_or_create_album_id(
                                                     &conn,
                                                     album,
-                                                    "",
+                                                    &meta.sort_album.clone().unwrap_or_default(),
                                                     track.album_artist_id,
                                                     track.genre_id,
                                                     track.year,
@@ -4291,9 +6744,21 @@ pub fn create_sqlite_instance(
                                             id
                                         };
 
-                                        let cover_id = if let Some(ref cover) = meta.cover {
+                                        let folder_cover = if meta.cover.is_none() {
+                                            get_folder_cover(
+                                                path,
+                                                &directory_images,
+                                                &mut folder_covers,
+                                            )
+                                        } else {
+                                            None
+                                        };
+                                        let cover_id = if let Some(cover) =
+                                            meta.cover.as_ref().or(folder_cover.as_ref())
+                                        {
                                             let id = get_or_create_cover_id(
                                                 &conn,
+                                                &covers_dir,
                                                 track.album_id,
                                                 cover,
                                             );
@@ -4334,6 +6799,9 @@ pub fn create_sqlite_instance(
 
                                         track.size = metadata.len() as i64;
 
+                                        imported_locations
+                                            .insert(path_str.clone(), track.location.clone());
+
                                         // TODO: Instead collect tracks and batch insert
                                         println!("AddToLibrary, implement track batch import!!!");
                                         insert_track(&conn, track, false);
@@ -4355,7 +6823,49 @@ pub fn create_sqlite_instance(
                                     }
                                 }
 
+                                for (name, entries) in playlists_to_import {
+                                    let mut track_ids: Vec<i64> = Vec::new();
+                                    let mut unresolved: Vec<String> = Vec::new();
+
+                                    for (entry, resolved) in entries {
+                                        let track_id = resolved.and_then(|location| {
+                                            let location = imported_locations
+                                                .get(&location)
+                                                .cloned()
+                                                .unwrap_or(location);
+                                            get_track_id_by_location(&conn, &location)
+                                        });
+
+                                        match track_id {
+                                            Some(track_id) => track_ids.push(track_id),
+                                            None => unresolved.push(entry),
+                                        }
+                                    }
+
+                                    let db_playlist = DBPlaylist {
+                                        name: name.clone(),
+                                        tracks: track_ids,
+                                        ..Default::default()
+                                    };
+                                    insert_playlist(&conn, db_playlist, false);
+
+                                    if unresolved.len() > 0 {
+                                        let _ = app.emit(
+                                            "backend_message",
+                                            BackendMessage {
+                                                notification: None,
+                                                error: None,
+                                                warning: Some(Warning::UnresolvedPlaylistEntries(
+                                                    name, unresolved,
+                                                )),
+                                                progress: None,
+                                            },
+                                        );
+                                    }
+                                }
+
                                 let _ = app.emit("db_state", get_db_state(&conn));
+                                refresh_smart_playlists(&conn);
                                 data = get_init_data(&conn);
                                 db_state = get_db_state(&conn);
                                 let _ = app.emit(
@@ -4376,7 +6886,9 @@ pub fn create_sqlite_instance(
                                 let path = Path::new(&path_string);
 
                                 if path.exists() {
-                                    if path.is_file() && path.extension().unwrap() == "xml" {
+                                    if path.is_file()
+                                        && path.extension().unwrap_or_default() == "xml"
+                                    {
                                         let prev_db_state = get_db_state(&conn);
 
                                         let _ = app.emit(
@@ -4392,7 +6904,12 @@ pub fn create_sqlite_instance(
                                                 }),
                                             },
                                         );
-                                        extract_tracks_and_playlists(path_string, &conn);
+                                        let report =
+                                            extract_tracks_and_playlists(path_string, &conn);
+                                        if let Some(error) = &report.error {
+                                            error!("{}", error);
+                                        }
+                                        probe_gapless_info(data_path.clone(), is_probing.clone());
 
                                         /*
                                         TODO:
@@ -4472,14 +6989,18 @@ pub fn create_sqlite_instance(
                                         }
 
                                         let _ = app.emit("db_state", get_db_state(&conn));
+                                        refresh_smart_playlists(&conn);
                                         data = get_init_data(&conn);
 
                                         db_state = get_db_state(&conn);
+                                        let import_error = report.error.clone();
                                         let _ = app.emit(
                                             "backend_message",
                                             BackendMessage {
-                                                notification: Some(Notification::LibraryImport),
-                                                error: None,
+                                                notification: Some(Notification::LibraryImport(
+                                                    report,
+                                                )),
+                                                error: import_error,
                                                 warning: None,
                                                 progress: Some(Progress {
                                                     info: ProgressInfo::LibraryImport,
@@ -4520,8 +7041,18 @@ pub fn create_sqlite_instance(
                                 );
 
                                 if datatype == DataType::Playlist {
-                                    // Delete the playlists but no tracks
-                                    delete_playlists(&conn, &vec_id);
+                                    // Delete the playlists but no tracks, folders take their contents with them
+                                    let mut vec_playlist_id = vec_id.clone();
+                                    for playlist_id in &vec_id {
+                                        for descendant in
+                                            get_playlist_descendants(&conn, *playlist_id)
+                                        {
+                                            if !vec_playlist_id.contains(&descendant) {
+                                                vec_playlist_id.push(descendant);
+                                            }
+                                        }
+                                    }
+                                    delete_playlists(&conn, &vec_playlist_id);
 
                                     data.playlists = Some(get_playlists(&conn));
                                     data.playlists_order = Some((
@@ -4615,6 +7146,7 @@ pub fn create_sqlite_instance(
                                     );
 
                                     // Too many db entries might change, easier to resend all data
+                                    refresh_smart_playlists(&conn);
                                     data = get_init_data(&conn);
                                     db_state = get_db_state(&conn);
                                     let _ = app.emit("db_state", db_state.clone());
@@ -4732,9 +7264,6 @@ pub fn create_sqlite_instance(
                                     }
                                 }
                             }
-                            DBRequest::GetCoversById(vec_id) => {
-                                data.covers = get_covers_by_id(&conn, &vec_id);
-                            }
                             DBRequest::GetTrackPaths(_vec_id) => {
                                 // TODO: return the track paths for a given track_ids
                             }
@@ -4970,11 +7499,30 @@ pub fn create_sqlite_instance(
                                     }
                                 }
 
+                                if config_state.write_tags {
+                                    let track_ids: Vec<i64> =
+                                        tracks.iter().map(|track| track.id).collect();
+                                    let failed =
+                                        write_track_tags(&conn, &covers_dir, &track_ids, false);
+                                    if failed.len() > 0 {
+                                        let _ = app.emit(
+                                            "backend_message",
+                                            BackendMessage {
+                                                notification: None,
+                                                error: None,
+                                                warning: Some(Warning::TagWriteFailed(failed)),
+                                                progress: None,
+                                            },
+                                        );
+                                    }
+                                }
+
                                 delete_unused_entries(&conn);
 
                                 let _ = app.emit("db_state", get_db_state(&conn));
                                 db_state = get_db_state(&conn);
                                 // Too much can change, easier to resend whole init data.
+                                refresh_smart_playlists(&conn);
                                 data = get_init_data(&conn);
                                 let _ = app.emit(
                                     "backend_message",
@@ -5093,6 +7641,8 @@ pub fn create_sqlite_instance(
                                         stmt.next().unwrap();
                                     }
 
+                                    let album_track_ids = db_album.tracks.clone();
+
                                     if move_track_multi && config_state.manage_folders {
                                         // Move the tracks to the new correct location of artist and album
                                         let num_tracks = db_album.tracks.len();
@@ -5105,6 +7655,26 @@ pub fn create_sqlite_instance(
                                         );
                                     }
 
+                                    if config_state.write_tags {
+                                        let failed = write_track_tags(
+                                            &conn,
+                                            &covers_dir,
+                                            &album_track_ids,
+                                            true,
+                                        );
+                                        if failed.len() > 0 {
+                                            let _ = app.emit(
+                                                "backend_message",
+                                                BackendMessage {
+                                                    notification: None,
+                                                    error: None,
+                                                    warning: Some(Warning::TagWriteFailed(failed)),
+                                                    progress: None,
+                                                },
+                                            );
+                                        }
+                                    }
+
                                     delete_unused_entries(&conn);
                                     let _ = app.emit(
                                         "backend_message",
@@ -5124,6 +7694,7 @@ pub fn create_sqlite_instance(
                                 let _ = app.emit("db_state", get_db_state(&conn));
                                 db_state = get_db_state(&conn);
                                 // Too much can change, easier to resend whole init data.
+                                refresh_smart_playlists(&conn);
                                 data = get_init_data(&conn);
                             }
                             DBRequest::UpdateArtist(artist) => {
@@ -5248,6 +7819,34 @@ pub fn create_sqlite_instance(
 
                                         let _ = update_stmt.next().unwrap();
 
+                                        let mut playlist = playlist;
+                                        if let Some(criteria) = &playlist.smart_criteria {
+                                            // Rules edited in anmutunes are always stored as JSON
+                                            let mut criteria_stmt = conn
+                                                .prepare(
+                                                    "UPDATE Playlists SET smart_criteria = ?1 WHERE playlist_id = ?2",
+                                                )
+                                                .unwrap();
+                                            criteria_stmt
+                                                .bind((
+                                                    1,
+                                                    serde_json::to_string(criteria)
+                                                        .unwrap()
+                                                        .as_str(),
+                                                ))
+                                                .unwrap();
+                                            criteria_stmt.bind((2, playlist.id)).unwrap();
+                                            let _ = criteria_stmt.next();
+
+                                            if let Some(tracks) = update_smart_playlist_tracks(
+                                                &conn,
+                                                playlist.id,
+                                                criteria,
+                                            ) {
+                                                playlist.tracks = tracks;
+                                            }
+                                        }
+
                                         // TODO: verify playlist update, then send updated playlist
                                         data.playlists = Some(vec![playlist]);
                                         data.playlists_order = Some((
@@ -5285,7 +7884,7 @@ pub fn create_sqlite_instance(
                                     album_ids.push(stmt.read::<i64, _>("album_id").unwrap());
                                 }
 
-                                extract_cover_by_album(&conn, album_ids);
+                                extract_cover_by_album(&conn, &covers_dir, album_ids);
                                 data.albums = Some(get_albums(&conn));
 
                                 let _ = app.emit(
@@ -5316,7 +7915,7 @@ pub fn create_sqlite_instance(
                                         }),
                                     },
                                 );
-                                extract_cover_by_album(&conn, vec![album_id]);
+                                extract_cover_by_album(&conn, &covers_dir, vec![album_id]);
                                 // TODO: only update album for given id, frontend requests cover when required
                                 data.albums = Some(get_albums(&conn));
                                 //data.covers = Some(get_covers(&conn));
@@ -5334,8 +7933,13 @@ pub fn create_sqlite_instance(
                                     },
                                 );
                             }
+                            DBRequest::RefreshSmartPlaylists => {
+                                refresh_smart_playlists(&conn);
+                                data.playlists = Some(get_playlists(&conn));
+                            }
                             DBRequest::Init => {
                                 let _ = app.emit("db_state", get_db_state(&conn));
+                                refresh_smart_playlists(&conn);
                                 data = get_init_data(&conn);
 
                                 //let _ = app.emit("data", get_init_data(&conn));
@@ -5414,8 +8018,112 @@ pub fn create_sqlite_instance(
                                     }
                                 }
                             }
-                            DBRequest::ListenedToTrack(id) => {
-                                increment_plays_for_track(&conn, id);
+                            DBRequest::ListenedToTrack(played_track) => {
+                                insert_play_history(&conn, &played_track);
+                                if played_track.completed {
+                                    increment_plays_for_track(
+                                        &conn,
+                                        played_track.track_id,
+                                        played_track.started_at,
+                                    );
+                                } else if played_track.skipped {
+                                    increment_skips_for_track(&conn, played_track.track_id);
+                                }
+                                // Rules on plays, skips and last played
+                                refresh_smart_playlists(&conn);
+                                data.playlists = Some(get_playlists(&conn));
+                            }
+                            DBRequest::SetRating(datatype, vec_id, rating) => {
+                                let rating = rating.clamp(0, 100);
+                                set_rating_column(&conn, &datatype, &vec_id, "rating", rating);
+
+                                match datatype {
+                                    DataType::Track => {
+                                        let mut tracks: Vec<Track> = Vec::new();
+                                        for id in vec_id {
+                                            // Other players may use the rating tags differently, so this is opt-in on its own
+                                            if config_state.write_ratings {
+                                                if let Some(location) =
+                                                    get_track_location(&conn, id)
+                                                {
+                                                    match write_rating(Path::new(&location), rating)
+                                                    {
+                                                        Ok(()) => {}
+                                                        Err(error) => {
+                                                            error!("{}: {}", location, error);
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                            if let Some(track) = get_track_by_id(&conn, id) {
+                                                tracks.push(track);
+                                            }
+                                        }
+                                        data.tracks = Some(tracks);
+                                    }
+                                    DataType::Album => {
+                                        data.albums = Some(
+                                            vec_id
+                                                .iter()
+                                                .filter_map(|id| get_album_by_id(&conn, *id))
+                                                .collect(),
+                                        );
+                                    }
+                                    _ => {}
+                                }
+                                refresh_smart_playlists(&conn);
+                                data.playlists = Some(get_playlists(&conn));
+                            }
+                            DBRequest::SetLoved(datatype, vec_id, loved) => {
+                                let loved = loved.clamp(-1, 1);
+                                set_rating_column(&conn, &datatype, &vec_id, "loved", loved);
+
+                                match datatype {
+                                    DataType::Track => {
+                                        data.tracks = Some(
+                                            vec_id
+                                                .iter()
+                                                .filter_map(|id| get_track_by_id(&conn, *id))
+                                                .collect(),
+                                        );
+                                    }
+                                    DataType::Album => {
+                                        data.albums = Some(
+                                            vec_id
+                                                .iter()
+                                                .filter_map(|id| get_album_by_id(&conn, *id))
+                                                .collect(),
+                                        );
+                                    }
+                                    _ => {}
+                                }
+                                refresh_smart_playlists(&conn);
+                                data.playlists = Some(get_playlists(&conn));
+                            }
+                            DBRequest::GetTopTracks(from, to, limit) => {
+                                data.top_tracks =
+                                    Some(get_top_by_column(&conn, "track_id", from, to, limit));
+                            }
+                            DBRequest::GetTopAlbums(from, to, limit) => {
+                                data.top_albums =
+                                    Some(get_top_by_column(&conn, "album_id", from, to, limit));
+                            }
+                            DBRequest::GetTopArtists(from, to, limit) => {
+                                data.top_artists =
+                                    Some(get_top_by_column(&conn, "artist_id", from, to, limit));
+                            }
+                            DBRequest::GetRecentlyPlayed(limit) => {
+                                data.recently_played = Some(get_recently_played(&conn, limit));
+                            }
+                            DBRequest::ScanLoudness => {
+                                // Only one scan at a time
+                                if !is_scanning.swap(true, Ordering::SeqCst) {
+                                    scan_loudness(
+                                        app.clone(),
+                                        data_path.clone(),
+                                        is_scanning.clone(),
+                                    );
+                                }
                             }
                         },
                         Err(error) => {
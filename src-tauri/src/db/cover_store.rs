@@ -0,0 +1,246 @@
+// Content-addressed cover store, original images and JPEG thumbnails below the app data directory
+use crate::defs::Image;
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+use log::error;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+// Thumbnail sizes in px, a request for another size gets the next larger thumbnail
+pub static THUMBNAIL_SIZES: [u32; 3] = [128, 256, 512];
+
+pub fn covers_dir(data_path: &Path) -> PathBuf {
+    return data_path.join("covers");
+}
+
+pub fn cover_hash(data: &[u8]) -> String {
+    return Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+}
+
+// Also guards the protocol against paths outside of the store
+fn is_hash(hash: &str) -> bool {
+    return hash.len() == 64
+        && hash
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+}
+
+// Files are spread over subdirectories by the first two characters of the hash
+fn original_path(covers_dir: &Path, hash: &str) -> PathBuf {
+    return covers_dir.join(&hash[..2]).join(hash);
+}
+
+fn thumbnail_path(covers_dir: &Path, hash: &str, size: u32) -> PathBuf {
+    return covers_dir
+        .join(&hash[..2])
+        .join(format!("{}_{}.jpg", hash, size));
+}
+
+fn media_type(data: &[u8]) -> &'static str {
+    return match image::guess_format(data) {
+        Ok(format) => format.to_mime_type(),
+        Err(_) => "application/octet-stream",
+    };
+}
+
+/*
+Stores the original image and returns its hash, the same image is only stored once.
+The image is written to a temporary file first, so an interrupted write never leaves a broken file behind.
+ */
+pub fn store_cover(covers_dir: &Path, cover: &Image) -> Result<String, String> {
+    let hash = cover_hash(&cover.data);
+    let path = original_path(covers_dir, &hash);
+
+    if !path.exists() {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|error| error.to_string())?;
+        }
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, &cover.data).map_err(|error| error.to_string())?;
+        fs::rename(&temp_path, &path).map_err(|error| error.to_string())?;
+    }
+
+    return Ok(hash);
+}
+
+pub fn load_cover(covers_dir: &Path, hash: &str) -> Option<Image> {
+    if !is_hash(hash) {
+        return None;
+    }
+    let data = fs::read(original_path(covers_dir, hash)).ok()?;
+
+    return Some(Image {
+        media_type: media_type(&data).to_string(),
+        data: data,
+    });
+}
+
+// Thumbnails are created on first request and kept next to the original
+fn load_thumbnail(covers_dir: &Path, hash: &str, size: u32) -> Result<Vec<u8>, String> {
+    let path = thumbnail_path(covers_dir, hash, size);
+    if let Ok(data) = fs::read(&path) {
+        return Ok(data);
+    }
+
+    let original = fs::read(original_path(covers_dir, hash)).map_err(|error| error.to_string())?;
+    let mut image = image::load_from_memory(&original).map_err(|error| error.to_string())?;
+    // Smaller images are not scaled up
+    if image.width() > size || image.height() > size {
+        image = image.resize(size, size, FilterType::Lanczos3);
+    }
+
+    // JPEG has no alpha channel
+    let mut data: Vec<u8> = Vec::new();
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_to(&mut Cursor::new(&mut data), ImageFormat::Jpeg)
+        .map_err(|error| error.to_string())?;
+
+    // Without the file the thumbnail is just created again next time
+    if let Err(error) = fs::write(&path, &data) {
+        error!("{}: {}", path.display(), error);
+    }
+
+    return Ok(data);
+}
+
+// The smallest thumbnail at least as large as requested, None for the original
+fn thumbnail_size(size: u32) -> Option<u32> {
+    return THUMBNAIL_SIZES
+        .iter()
+        .find(|thumbnail_size| **thumbnail_size >= size)
+        .copied();
+}
+
+/*
+Answers a request of the cover protocol, "<hash>" for the original image or "<hash>/<size>" for a thumbnail.
+Returns the media type and the image data.
+ */
+pub fn read_cover_request(
+    covers_dir: &Path,
+    request_path: &str,
+) -> Result<(String, Vec<u8>), String> {
+    let request_path = urlencoding::decode(request_path.trim_start_matches('/'))
+        .map_err(|error| error.to_string())?;
+
+    let (hash, size) = match request_path.split_once('/') {
+        Some((hash, size)) => (
+            hash,
+            Some(
+                size.parse::<u32>()
+                    .map_err(|_| format!("invalid cover size {}", size))?,
+            ),
+        ),
+        None => (request_path.as_ref(), None),
+    };
+    if !is_hash(hash) {
+        return Err(format!("invalid cover {}", hash));
+    }
+
+    match size.and_then(thumbnail_size) {
+        Some(size) => {
+            return Ok((
+                "image/jpeg".to_string(),
+                load_thumbnail(covers_dir, hash, size)?,
+            ));
+        }
+        None => {
+            let cover =
+                load_cover(covers_dir, hash).ok_or_else(|| format!("cover {} not found", hash))?;
+            return Ok((cover.media_type, cover.data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    fn png(width: u32, height: u32) -> Image {
+        let mut data: Vec<u8> = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::new(width, height))
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        return Image {
+            media_type: "image/png".to_string(),
+            data: data,
+        };
+    }
+
+    fn temp_covers_dir(name: &str) -> PathBuf {
+        let covers_dir =
+            std::env::temp_dir().join(format!("anmutunes-covers-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&covers_dir);
+        return covers_dir;
+    }
+
+    #[test]
+    fn stores_covers_by_content() {
+        let covers_dir = temp_covers_dir("store");
+        let cover = png(600, 300);
+
+        let hash = store_cover(&covers_dir, &cover).unwrap();
+        assert_eq!(hash, cover_hash(&cover.data));
+        assert_eq!(store_cover(&covers_dir, &cover).unwrap(), hash);
+
+        let (media_type, data) = read_cover_request(&covers_dir, &format!("/{}", hash)).unwrap();
+        assert_eq!(media_type, "image/png");
+        assert_eq!(data, cover.data);
+
+        let _ = fs::remove_dir_all(&covers_dir);
+    }
+
+    #[test]
+    fn serves_thumbnails() {
+        let covers_dir = temp_covers_dir("thumbnail");
+        let hash = store_cover(&covers_dir, &png(600, 300)).unwrap();
+
+        let cases = [("100", (128, 64)), ("256", (256, 128)), ("300", (512, 256))];
+        for (size, expected) in cases {
+            // convertFileSrc encodes the slash
+            let (media_type, data) =
+                read_cover_request(&covers_dir, &format!("/{}%2F{}", hash, size)).unwrap();
+            let thumbnail = image::load_from_memory(&data).unwrap();
+            assert_eq!(media_type, "image/jpeg");
+            assert_eq!(
+                (thumbnail.width(), thumbnail.height()),
+                expected,
+                "{}",
+                size
+            );
+        }
+        assert!(thumbnail_path(&covers_dir, &hash, 128).exists());
+
+        // Larger than every thumbnail gets the original
+        let (media_type, _) = read_cover_request(&covers_dir, &format!("/{}/1000", hash)).unwrap();
+        assert_eq!(media_type, "image/png");
+
+        let _ = fs::remove_dir_all(&covers_dir);
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        let covers_dir = temp_covers_dir("invalid");
+        let hash = "0".repeat(64);
+        let cases = [
+            "/../../etc/passwd".to_string(),
+            format!("/{}", hash.to_uppercase().replace('0', "A")),
+            format!("/{}/large", hash),
+            format!("/{}", hash),
+            "/".to_string(),
+        ];
+        for request_path in cases {
+            assert!(
+                read_cover_request(&covers_dir, &request_path).is_err(),
+                "{}",
+                request_path
+            );
+        }
+    }
+}
@@ -0,0 +1,306 @@
+// Writing the library as iTunes library.xml (Apple plist), readable by other tools and our own import
+use super::playlist_files::path_to_uri;
+use quick_xml::escape::escape;
+use std::{fs, path::Path};
+
+pub enum PlistValue {
+    Integer(i64),
+    String(String),
+    Date(String), // ISO 8601, e.g. 2024-01-31T12:00:00Z
+    Bool(bool),
+}
+
+pub struct LibraryTrack {
+    pub track_id: i64,
+    pub values: Vec<(&'static str, PlistValue)>,
+}
+
+pub struct LibraryPlaylist {
+    pub values: Vec<(&'static str, PlistValue)>,
+    pub track_ids: Vec<i64>,
+}
+
+/*
+Tracks and playlists created in anmutunes have no persistent id.
+Derive a stable one from the row id, so repeated exports match.
+ */
+pub fn persistent_id(stored: &str, tag: u32, id: i64) -> String {
+    if !stored.is_empty() {
+        return stored.to_string();
+    }
+    return format!("{:08X}{:08X}", tag, id);
+}
+
+pub fn location_to_url(location: &str) -> String {
+    return path_to_uri(location, true);
+}
+
+fn value_to_xml(value: &PlistValue) -> String {
+    match value {
+        PlistValue::Integer(number) => return format!("<integer>{}</integer>", number),
+        PlistValue::String(text) => return format!("<string>{}</string>", escape(text.as_str())),
+        PlistValue::Date(date) => return format!("<date>{}</date>", date),
+        PlistValue::Bool(true) => return "<true/>".to_string(),
+        PlistValue::Bool(false) => return "<false/>".to_string(),
+    }
+}
+
+fn push_dict_values(content: &mut String, values: &[(&str, PlistValue)], indent: &str) {
+    for (key, value) in values {
+        content.push_str(&format!(
+            "{}<key>{}</key>{}\n",
+            indent,
+            escape(*key),
+            value_to_xml(value)
+        ));
+    }
+}
+
+pub fn write_library_xml(
+    export_path: &Path,
+    music_folder: &str,
+    date: &str,
+    tracks: &[LibraryTrack],
+    playlists: &[LibraryPlaylist],
+) -> Result<(), String> {
+    let mut content = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple Computer//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+"#
+    .to_string();
+
+    let mut folder_url = location_to_url(music_folder);
+    if !folder_url.ends_with('/') {
+        folder_url.push('/');
+    }
+
+    push_dict_values(
+        &mut content,
+        &[
+            ("Major Version", PlistValue::Integer(1)),
+            ("Minor Version", PlistValue::Integer(1)),
+            ("Date", PlistValue::Date(date.to_string())),
+            (
+                "Application Version",
+                PlistValue::String(format!("anmutunes {}", env!("CARGO_PKG_VERSION"))),
+            ),
+            ("Features", PlistValue::Integer(5)),
+            ("Show Content Ratings", PlistValue::Bool(true)),
+            ("Music Folder", PlistValue::String(folder_url)),
+        ],
+        "\t",
+    );
+
+    content.push_str("\t<key>Tracks</key>\n\t<dict>\n");
+    for track in tracks {
+        content.push_str(&format!("\t\t<key>{}</key>\n\t\t<dict>\n", track.track_id));
+        push_dict_values(&mut content, &track.values, "\t\t\t");
+        content.push_str("\t\t</dict>\n");
+    }
+    content.push_str("\t</dict>\n");
+
+    content.push_str("\t<key>Playlists</key>\n\t<array>\n");
+    for playlist in playlists {
+        content.push_str("\t\t<dict>\n");
+        push_dict_values(&mut content, &playlist.values, "\t\t\t");
+        if !playlist.track_ids.is_empty() {
+            content.push_str("\t\t\t<key>Playlist Items</key>\n\t\t\t<array>\n");
+            for track_id in &playlist.track_ids {
+                content.push_str(&format!(
+                    "\t\t\t\t<dict>\n\t\t\t\t\t<key>Track ID</key><integer>{}</integer>\n\t\t\t\t</dict>\n",
+                    track_id
+                ));
+            }
+            content.push_str("\t\t\t</array>\n");
+        }
+        content.push_str("\t\t</dict>\n");
+    }
+    content.push_str("\t</array>\n");
+
+    content.push_str("</dict>\n</plist>\n");
+
+    return fs::write(export_path, content).map_err(|error| error.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{extract_tracks_and_playlists, init_db};
+    use super::*;
+    use sqlite::State;
+
+    fn track(track_id: i64, name: &str, location: &str) -> LibraryTrack {
+        return LibraryTrack {
+            track_id,
+            values: vec![
+                ("Track ID", PlistValue::Integer(track_id)),
+                ("Name", PlistValue::String(name.to_string())),
+                (
+                    "Artist",
+                    PlistValue::String("Simon & Garfunkel".to_string()),
+                ),
+                ("Album", PlistValue::String("Live <1981>".to_string())),
+                ("Rating", PlistValue::Integer(80)),
+                (
+                    "Date Added",
+                    PlistValue::Date("2024-01-31T12:00:00Z".to_string()),
+                ),
+                (
+                    "Persistent ID",
+                    PlistValue::String(persistent_id("", 1, track_id)),
+                ),
+                ("Location", PlistValue::String(location_to_url(location))),
+            ],
+        };
+    }
+
+    #[test]
+    fn library_round_trip() {
+        let dir = std::env::temp_dir().join(format!("anmutunes-library-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let export_path = dir.join("Library.xml");
+
+        let tracks = [
+            track(
+                7,
+                "Mrs. Robinson",
+                "/music/Simon & Garfunkel/01 Mrs. Robinson.flac",
+            ),
+            track(9, "\"Ärger\" <50%>", "/music/Ärzte/02 #1 [Live].mp3"),
+        ];
+        let playlists = [
+            LibraryPlaylist {
+                values: vec![
+                    ("Playlist ID", PlistValue::Integer(1)),
+                    (
+                        "Playlist Persistent ID",
+                        PlistValue::String("AAAA".to_string()),
+                    ),
+                    ("Folder", PlistValue::Bool(true)),
+                    ("Name", PlistValue::String("Rock & Roll".to_string())),
+                ],
+                track_ids: vec![7, 9],
+            },
+            LibraryPlaylist {
+                values: vec![
+                    ("Playlist ID", PlistValue::Integer(2)),
+                    (
+                        "Playlist Persistent ID",
+                        PlistValue::String("BBBB".to_string()),
+                    ),
+                    (
+                        "Parent Persistent ID",
+                        PlistValue::String("AAAA".to_string()),
+                    ),
+                    ("Name", PlistValue::String("<Live> & \"Loud\"".to_string())),
+                ],
+                track_ids: vec![9],
+            },
+        ];
+        write_library_xml(
+            &export_path,
+            "file:///music/",
+            "2024-02-01T08:00:00Z",
+            &tracks,
+            &playlists,
+        )
+        .unwrap();
+
+        let conn = sqlite::open(":memory:").unwrap();
+        init_db(&conn);
+        let report = extract_tracks_and_playlists(export_path.to_string_lossy().to_string(), &conn);
+        assert_eq!(report.error, None);
+        assert_eq!(report.tracks_imported, 2);
+        assert!(report.skipped.is_empty());
+
+        let mut stmt = conn
+            .prepare(
+                r#"
+                SELECT track_id, orig_track_id, Tracks.name, Artists.name AS artist,
+                    Albums.name AS album, Tracks.rating, Tracks.date_added, persistent_id,
+                    location
+                FROM Tracks
+                JOIN Artists ON Artists.artist_id = Tracks.artist_id
+                JOIN Albums ON Albums.album_id = Tracks.album_id
+                ORDER BY orig_track_id
+                "#,
+            )
+            .unwrap();
+        let mut track_ids: Vec<i64> = Vec::new();
+        let mut rows: Vec<[String; 7]> = Vec::new();
+        while let Ok(State::Row) = stmt.next() {
+            track_ids.push(stmt.read::<i64, _>("track_id").unwrap());
+            rows.push([
+                stmt.read::<i64, _>("orig_track_id").unwrap().to_string(),
+                stmt.read::<String, _>("name").unwrap(),
+                stmt.read::<String, _>("artist").unwrap(),
+                stmt.read::<String, _>("album").unwrap(),
+                stmt.read::<i64, _>("rating").unwrap().to_string(),
+                stmt.read::<String, _>("date_added").unwrap(),
+                stmt.read::<String, _>("location").unwrap(),
+            ]);
+            assert!(stmt
+                .read::<String, _>("persistent_id")
+                .unwrap()
+                .starts_with("00000001"));
+        }
+        let expected = [
+            [
+                "7",
+                "Mrs. Robinson",
+                "Simon & Garfunkel",
+                "Live <1981>",
+                "80",
+                "2024-01-31T12:00:00Z",
+                "/music/Simon & Garfunkel/01 Mrs. Robinson.flac",
+            ],
+            [
+                "9",
+                "\"Ärger\" <50%>",
+                "Simon & Garfunkel",
+                "Live <1981>",
+                "80",
+                "2024-01-31T12:00:00Z",
+                "/music/Ärzte/02 #1 [Live].mp3",
+            ],
+        ];
+        assert_eq!(rows, expected.map(|row| row.map(|value| value.to_string())));
+
+        let mut stmt = conn
+            .prepare(
+                r#"
+                SELECT Playlists.name, Playlists.tracks, Parent.name AS parent
+                FROM Playlists
+                LEFT JOIN Playlists Parent ON Parent.playlist_id = Playlists.parent_id
+                ORDER BY Playlists.orig_playlist_id
+                "#,
+            )
+            .unwrap();
+        let mut rows: Vec<(String, String, Option<String>)> = Vec::new();
+        while let Ok(State::Row) = stmt.next() {
+            rows.push((
+                stmt.read::<String, _>("name").unwrap(),
+                stmt.read::<String, _>("tracks").unwrap(),
+                stmt.read::<Option<String>, _>("parent").unwrap(),
+            ));
+        }
+        assert_eq!(
+            rows,
+            [
+                (
+                    "Rock & Roll".to_string(),
+                    format!("[{},{}]", track_ids[0], track_ids[1]),
+                    None
+                ),
+                (
+                    "<Live> & \"Loud\"".to_string(),
+                    format!("[{}]", track_ids[1]),
+                    Some("Rock & Roll".to_string())
+                ),
+            ]
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
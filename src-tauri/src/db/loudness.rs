@@ -0,0 +1,362 @@
+// Loudness measurement according to EBU R128 (ITU-R BS.1770-4)
+use log::error;
+use std::{f64::consts::PI, fs::File, path::Path};
+use symphonia::core::audio::{Channels, SampleBuffer, SignalSpec};
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+// ReplayGain 2.0 reference level, gains are calculated relative to it
+static REFERENCE_LUFS: f64 = -18.0;
+static ABSOLUTE_GATE_LUFS: f64 = -70.0;
+static RELATIVE_GATE_LU: f64 = -10.0;
+// Taps of the interpolation filter per phase for true peak measurement
+static TRUE_PEAK_TAPS: usize = 12;
+
+pub struct TrackLoudness {
+    pub integrated: f64, // LUFS
+    pub true_peak: f64,  // linear, 1.0 is full scale
+    // Mean square of each 400 ms gating block, required for the loudness of an album
+    pub blocks: Vec<f64>,
+}
+
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[1] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[2] * y;
+        return y;
+    }
+}
+
+/*
+K-weighting filter, a high shelf followed by a high pass.
+The coefficients of BS.1770 are only given for 48 kHz, so they are derived for any sample rate.
+ */
+fn get_k_weighting(rate: f64) -> [Biquad; 2] {
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    return [shelf, high_pass];
+}
+
+fn get_channel_weight(channel: Channels) -> f64 {
+    // LFE is not part of the measurement, surround channels are weighted higher
+    if channel == Channels::LFE1 || channel == Channels::LFE2 {
+        return 0.0;
+    } else if channel == Channels::SIDE_LEFT
+        || channel == Channels::SIDE_RIGHT
+        || channel == Channels::REAR_LEFT
+        || channel == Channels::REAR_RIGHT
+    {
+        return 1.41;
+    }
+    return 1.0;
+}
+
+// Windowed sinc filter for oversampling, tap k of phase p is at index k * factor + p
+fn get_interpolation_filter(factor: usize) -> Vec<f64> {
+    let length = factor * TRUE_PEAK_TAPS;
+    let center = (length - 1) as f64 / 2.0;
+
+    let mut filter = vec![];
+    for index in 0..length {
+        let x = (index as f64 - center) / factor as f64;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        };
+        let window = 0.5 - 0.5 * (2.0 * PI * (index as f64 + 0.5) / length as f64).cos();
+        filter.push(sinc * window);
+    }
+
+    return filter;
+}
+
+struct LoudnessMeter {
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    sub_block_len: usize,
+    sub_block_pos: usize,
+    sub_block_sum: f64,
+    // Mean square of each 100 ms sub block, 4 consecutive ones form a gating block
+    sub_blocks: Vec<f64>,
+    oversampling: usize,
+    interpolation_filter: Vec<f64>,
+    history: Vec<Vec<f64>>,
+    history_pos: usize,
+    true_peak: f64,
+}
+
+impl LoudnessMeter {
+    fn new(spec: &SignalSpec) -> LoudnessMeter {
+        let rate = spec.rate as f64;
+        let channels: Vec<Channels> = spec.channels.iter().collect();
+
+        // True peak requires a sample rate of at least 192 kHz
+        let oversampling = if spec.rate < 96000 {
+            4
+        } else if spec.rate < 192000 {
+            2
+        } else {
+            1
+        };
+
+        return LoudnessMeter {
+            weights: channels
+                .iter()
+                .map(|channel| get_channel_weight(*channel))
+                .collect(),
+            filters: vec![get_k_weighting(rate); channels.len()],
+            sub_block_len: (spec.rate as usize / 10).max(1),
+            sub_block_pos: 0,
+            sub_block_sum: 0.0,
+            sub_blocks: vec![],
+            oversampling: oversampling,
+            interpolation_filter: get_interpolation_filter(oversampling),
+            history: vec![vec![0.0; TRUE_PEAK_TAPS]; channels.len()],
+            history_pos: 0,
+            true_peak: 0.0,
+        };
+    }
+
+    fn process(&mut self, samples: &[f32]) {
+        let channel_count = self.weights.len();
+
+        for frame in samples.chunks_exact(channel_count) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let x = *sample as f64;
+
+                if self.weights[channel] > 0.0 {
+                    let filters = &mut self.filters[channel];
+                    let shelved = filters[0].process(x);
+                    let y = filters[1].process(shelved);
+                    self.sub_block_sum += self.weights[channel] * y * y;
+                }
+
+                self.history[channel][self.history_pos] = x;
+                self.true_peak = self.true_peak.max(self.get_interpolated_peak(channel));
+            }
+
+            self.history_pos = (self.history_pos + 1) % TRUE_PEAK_TAPS;
+
+            self.sub_block_pos += 1;
+            if self.sub_block_pos == self.sub_block_len {
+                self.sub_blocks
+                    .push(self.sub_block_sum / self.sub_block_len as f64);
+                self.sub_block_pos = 0;
+                self.sub_block_sum = 0.0;
+            }
+        }
+    }
+
+    fn get_interpolated_peak(&self, channel: usize) -> f64 {
+        let history = &self.history[channel];
+        let mut peak = history[self.history_pos].abs();
+
+        if self.oversampling > 1 {
+            for phase in 0..self.oversampling {
+                let mut value = 0.0;
+                for tap in 0..TRUE_PEAK_TAPS {
+                    // Newest sample first
+                    let index = (self.history_pos + TRUE_PEAK_TAPS - tap) % TRUE_PEAK_TAPS;
+                    value +=
+                        self.interpolation_filter[tap * self.oversampling + phase] * history[index];
+                }
+                peak = peak.max(value.abs());
+            }
+        }
+
+        return peak;
+    }
+
+    fn finish(self) -> Option<TrackLoudness> {
+        // Gating blocks of 400 ms with an overlap of 75%
+        let blocks: Vec<f64> = self
+            .sub_blocks
+            .windows(4)
+            .map(|window| window.iter().sum::<f64>() / 4.0)
+            .collect();
+
+        let integrated = get_gated_loudness(&blocks)?;
+
+        return Some(TrackLoudness {
+            integrated: integrated,
+            true_peak: self.true_peak,
+            blocks: blocks,
+        });
+    }
+}
+
+fn get_block_loudness(mean_square: f64) -> f64 {
+    return -0.691 + 10.0 * mean_square.log10();
+}
+
+/*
+Integrated loudness of the gating blocks of a track or all tracks of an album.
+None if everything is below the absolute gate, e.g. for silence.
+ */
+pub fn get_gated_loudness(blocks: &[f64]) -> Option<f64> {
+    let absolute_gate = 10f64.powf((ABSOLUTE_GATE_LUFS + 0.691) / 10.0);
+    let above_absolute: Vec<f64> = blocks
+        .iter()
+        .copied()
+        .filter(|block| *block > absolute_gate)
+        .collect();
+
+    if above_absolute.is_empty() {
+        return None;
+    }
+
+    let relative_gate = above_absolute.iter().sum::<f64>() / above_absolute.len() as f64
+        * 10f64.powf(RELATIVE_GATE_LU / 10.0);
+    let above_relative: Vec<f64> = above_absolute
+        .into_iter()
+        .filter(|block| *block > relative_gate)
+        .collect();
+
+    if above_relative.is_empty() {
+        return None;
+    }
+
+    return Some(get_block_loudness(
+        above_relative.iter().sum::<f64>() / above_relative.len() as f64,
+    ));
+}
+
+pub fn get_replaygain(integrated: f64) -> f64 {
+    return REFERENCE_LUFS - integrated;
+}
+
+pub fn measure_loudness(path: &Path) -> Option<TrackLoudness> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(error) => {
+            error!("{}: {}", path.display(), error);
+            return None;
+        }
+    };
+
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    // Encoder delay and padding are silence that should not be measured
+    let format_opts = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+
+    let metadata_opts: MetadataOptions = Default::default();
+
+    let mut hint = Hint::new();
+
+    if let Some(extension) = path.extension() {
+        if let Some(extension_str) = extension.to_str() {
+            hint.with_extension(extension_str);
+        }
+    }
+
+    let mut probed =
+        match symphonia::default::get_probe().format(&hint, mss, &format_opts, &metadata_opts) {
+            Ok(probed) => probed,
+            Err(error) => {
+                error!("{}: {}", path.display(), error);
+                return None;
+            }
+        };
+
+    let track = probed.format.default_track()?;
+    let track_id = track.id;
+
+    let mut decoder = match symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+    {
+        Ok(decoder) => decoder,
+        Err(error) => {
+            error!("{}: {}", path.display(), error);
+            return None;
+        }
+    };
+
+    let mut meter: Option<LoudnessMeter> = None;
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        let packet = match probed.format.next_packet() {
+            Ok(packet) => packet,
+            // End of stream
+            Err(_err) => break,
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(audio_buf) => {
+                let spec = *audio_buf.spec();
+
+                if meter.is_none() {
+                    meter = Some(LoudnessMeter::new(&spec));
+                }
+
+                let required = audio_buf.capacity() * spec.channels.count();
+                let too_small = match &sample_buf {
+                    Some(buf) => buf.capacity() < required,
+                    None => true,
+                };
+                if too_small {
+                    sample_buf = Some(SampleBuffer::<f32>::new(audio_buf.capacity() as u64, spec));
+                }
+
+                if let (Some(meter), Some(buf)) = (meter.as_mut(), sample_buf.as_mut()) {
+                    buf.copy_interleaved_ref(audio_buf);
+                    meter.process(buf.samples());
+                }
+            }
+            // Skip corrupted packets
+            Err(Error::DecodeError(_)) => continue,
+            Err(_err) => break,
+        }
+    }
+
+    return meter?.finish();
+}
@@ -1,12 +1,17 @@
 // Code based on: https://github.com/pdeljanov/Symphonia/blob/master/symphonia-play/src/main.rs
-use crate::defs::{Image, Meta};
-use std::{fs::File, path::Path};
+use crate::defs::{Gapless, Image, Meta, ReplayGain};
+use log::error;
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
 //use symphonia::core::formats::{Cue, FormatOptions, Track};
+use symphonia::core::codecs::CodecParameters;
 use symphonia::core::formats::FormatOptions;
 use symphonia::core::io::MediaSourceStream;
 //use symphonia::core::meta::{ColorMode, MetadataOptions, Tag, Value, Visual};
 use symphonia::core::meta::{ColorMode, StandardVisualKey, Visual};
-use symphonia::core::meta::{MetadataOptions, Tag};
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag};
 use symphonia::core::probe::{Hint, ProbeResult};
 //use symphonia::core::units::TimeBase;
 
@@ -199,148 +204,556 @@ fn print_tags(tags: &[Tag]) {
 }
 */
 
+/*
+The front cover, otherwise the first untagged visual (ID3 picture type "Other" has no usage),
+otherwise any other visual but file icons, e.g. the back cover or the media.
+ */
 fn get_meta_cover(visuals: &[Visual]) -> Option<Image> {
-    if !visuals.is_empty() {
-        /*println!("|");
-        println!("| // Visuals //");*/
-        /*
-        TODO:
-        if usage exists only export if it is of StandardVisualKey "FrontCover"
-        We do need the visual.media_type and visual.data to create the file and base64 string.
-        What about other images stored?
-        For quite a few file no cover is extracted, but in the file browser they clearly have a cover.
-         */
+    let visual = visuals
+        .iter()
+        .find(|visual| visual.usage == Some(StandardVisualKey::FrontCover))
+        .or_else(|| visuals.iter().find(|visual| visual.usage.is_none()))
+        .or_else(|| {
+            visuals.iter().find(|visual| {
+                !matches!(
+                    visual.usage,
+                    Some(StandardVisualKey::FileIcon | StandardVisualKey::OtherIcon)
+                )
+            })
+        })?;
 
-        let mut cover_index = None;
+    return Some(Image {
+        media_type: visual.media_type.clone(),
+        data: visual.data.clone().into_vec(),
+    });
+}
 
-        for (idx, visual) in visuals.iter().enumerate() {
-            if let Some(usage) = visual.usage {
-                if usage == StandardVisualKey::FrontCover {
-                    cover_index = Some(idx);
-                }
-            }
+fn image_media_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    return match extension.as_str() {
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "png" => Some("image/png"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "bmp" => Some("image/bmp"),
+        _ => None,
+    };
+}
+
+pub fn is_image_file(path: &Path) -> bool {
+    return image_media_type(path).is_some();
+}
+
+/*
+Lower is preferred: cover.*, folder.*, front.* and the AlbumArt*.jpg files of Windows Media Player,
+which come as AlbumArt_{GUID}_Large.jpg and AlbumArtSmall.jpg.
+ */
+fn cover_file_rank(path: &Path) -> Option<usize> {
+    let media_type = image_media_type(path)?;
+    let stem = path.file_stem()?.to_str()?.to_lowercase();
+
+    if let Some(rank) = ["cover", "folder", "front"]
+        .iter()
+        .position(|name| stem == *name)
+    {
+        return Some(rank);
+    }
+    if stem.starts_with("albumart") && media_type == "image/jpeg" {
+        if stem.ends_with("large") {
+            return Some(3);
+        } else if stem.ends_with("small") {
+            return Some(5);
         }
+        return Some(4);
+    }
+    return None;
+}
 
-        if let Some(index) = cover_index {
-            let visual = &visuals[index];
-            let cover: Image = Image {
-                media_type: visual.media_type.clone(),
-                data: visual.data.clone().into_vec(),
-            };
+// Chooses the album cover from the image files of a directory
+pub fn choose_cover_file(images: &[PathBuf]) -> Option<PathBuf> {
+    return images
+        .iter()
+        .filter_map(|image| cover_file_rank(image).map(|rank| (rank, image)))
+        .min()
+        .map(|(_, image)| image.clone());
+}
 
-            return Some(cover);
+pub fn find_cover_file(directory: &Path) -> Option<PathBuf> {
+    let images: Vec<PathBuf> = fs::read_dir(directory)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && is_image_file(path))
+        .collect();
+    return choose_cover_file(&images);
+}
 
-            /*
-            if let Some(dimensions) = visual.dimensions {
-                println!(
-                    "|          Dimensions: {} px x {} px",
-                    dimensions.width, dimensions.height
-                );
-            }
-            if let Some(bpp) = visual.bits_per_pixel {
-                println!("|          Bits/Pixel: {}", bpp);
-            }
-            if let Some(ColorMode::Indexed(colors)) = visual.color_mode {
-                println!("|          Palette:    {} colors", colors);
-            }
-            println!("|          Size:       {} bytes", visual.data.len());
+pub fn read_cover_file(path: &Path) -> Option<Image> {
+    let media_type = image_media_type(path)?;
+    return match fs::read(path) {
+        Ok(data) => Some(Image {
+            media_type: media_type.to_string(),
+            data: data,
+        }),
+        Err(error) => {
+            error!("{}: {}", path.display(), error);
+            None
+        }
+    };
+}
 
-            // Print out tags similar to how regular tags are printed.
-            if !visual.tags.is_empty() {
-                println!("|          Tags:");
+fn parse_replaygain_value(value: &str) -> Option<f64> {
+    // Gains are stored like "-6.48 dB", peaks without unit like "0.988525"
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .or_else(|| value.strip_suffix("DB"))
+        .unwrap_or(value);
+
+    match value.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() => return Some(value),
+        _ => return None,
+    }
+}
+
+fn parse_itunnorm(value: &str) -> Option<f64> {
+    /*
+    iTunNORM (SoundCheck) consists of 10 hexadecimal values with 8 digits each.
+    The first two are the adjustment for the left and right channel in 1/1000 W per mW,
+    the louder channel determines the gain.
+     */
+    let words: Vec<&str> = value.split_whitespace().collect();
+    if words.len() != 10 || words.iter().any(|word| word.len() != 8) {
+        return None;
+    }
+
+    let mut values: Vec<u32> = vec![];
+    for word in words.iter() {
+        match u32::from_str_radix(word, 16) {
+            Ok(value) => values.push(value),
+            Err(_error) => return None,
+        }
+    }
+
+    let adjustment = values[0].max(values[1]);
+    if adjustment == 0 {
+        return None;
+    }
+
+    return Some(-10.0 * (adjustment as f64 / 1000.0).log10());
+}
+
+/*
+POPM has no agreed scale, this follows the ranges Windows Media Player and foobar2000 use.
+0 is unrated, the result is 0-100 with 20 per star.
+ */
+fn popm_to_rating(popm: u8) -> i64 {
+    return match popm {
+        0 => 0,
+        1..=31 => 20,
+        32..=95 => 40,
+        96..=159 => 60,
+        160..=223 => 80,
+        _ => 100,
+    };
+}
+
+// The inverse of popm_to_rating, each star maps to the value the players write for it
+pub fn rating_to_popm(rating: i64) -> u8 {
+    return match rating {
+        i64::MIN..=0 => 0,
+        1..=20 => 1,
+        21..=40 => 64,
+        41..=60 => 128,
+        61..=80 => 196,
+        _ => 255,
+    };
+}
+
+// FMPS_RATING is a float between 0.0 and 1.0
+fn parse_fmps_rating(value: &str) -> Option<i64> {
+    match value.trim().parse::<f64>() {
+        Ok(rating) if (0.0..=1.0).contains(&rating) => {
+            return Some((rating * 100.0).round() as i64);
+        }
+        _ => {
+            return None;
+        }
+    }
+}
+
+// BPM is sometimes stored as a float, e.g. "120.50"
+fn parse_bpm(value: &str) -> Option<i64> {
+    let bpm = value.trim().parse::<f64>().ok()?;
+    if bpm > 0.0 {
+        return Some(bpm.round() as i64);
+    }
+    return None;
+}
+
+fn parse_compilation(value: &str) -> bool {
+    return matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes");
+}
+
+// iTunes stores its technical comments as hexadecimal words, e.g. iTunNORM and iTunSMPB
+fn is_hex_words(value: &str) -> bool {
+    let mut words = value.split_whitespace().peekable();
+    if words.peek().is_none() {
+        return false;
+    }
+    return words.all(|word| word.len() >= 8 && word.chars().all(|c| c.is_ascii_hexdigit()));
+}
+
+// Only the first value is kept, standard tag keys are read first and take precedence over vendor keys
+fn set_text(field: &mut Option<String>, value: String) {
+    if field.is_none() && !value.trim().is_empty() {
+        *field = Some(value.trim().to_string());
+    }
+}
+
+// Track and disc numbers are stored as "3", "3/12" or "/12" (ID3 TRCK and TPOS), 0 means unknown
+fn parse_number_pair(value: &str) -> (Option<i64>, Option<i64>) {
+    let parse = |part: &str| part.trim().parse::<i64>().ok().filter(|number| *number > 0);
+    if let Some((number, total)) = value.split_once('/') {
+        return (parse(number), parse(total));
+    }
+    return (parse(value), None);
+}
+
+fn parse_date_part(part: &str, len: usize, max: i64) -> Option<i64> {
+    if part.len() != len || !part.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    return part
+        .parse::<i64>()
+        .ok()
+        .filter(|number| (1..=max).contains(number));
+}
+
+fn format_date(year: i64, month: Option<i64>, day: Option<i64>) -> Option<String> {
+    return match (month, day) {
+        (Some(month), Some(day)) => Some(format!("{:04}-{:02}-{:02}", year, month, day)),
+        (Some(month), None) => Some(format!("{:04}-{:02}", year, month)),
+        _ => None,
+    };
+}
+
+/*
+Parses a date into year and release date (ISO 8601, "2025-08-22" or "2025-08").
+Accepts "2025", "2025-08", "2025-08-22", ID3v2.4 TDRC and MP4 timestamps ("2025-08-22T10:00:00Z"),
+"/" or "." as separator and "22.08.2025". Year-only dates have no release date.
+ */
+fn parse_date(value: &str) -> (Option<i64>, Option<String>) {
+    let date = value.trim().split(['T', ' ']).next().unwrap_or_default();
+    let parts: Vec<&str> = date.split(['-', '/', '.']).collect();
+
+    if let Some(year) = parse_date_part(parts[0], 4, 9999) {
+        let month = parts.get(1).and_then(|part| parse_date_part(part, 2, 12));
+        let day = month.and(parts.get(2).and_then(|part| parse_date_part(part, 2, 31)));
+        return (Some(year), format_date(year, month, day));
+    }
+
+    // Day first, e.g. "22.08.2025"
+    if parts.len() == 3 {
+        if let Some(year) = parse_date_part(parts[2], 4, 9999) {
+            let month = parse_date_part(parts[1], 2, 12);
+            let day = parse_date_part(parts[0], 2, 31);
+            if month.is_some() && day.is_some() {
+                return (Some(year), format_date(year, month, day));
             }
+        }
+    }
 
-            for (tidx, tag) in visual.tags.iter().enumerate() {
-                if let Some(std_key) = tag.std_key {
-                    //println!(
-                    //    "{}",
-                    //    print_tag_item(tidx + 1, &format!("{:?}", std_key), &tag.value, 21)
-                    //);
-                } else {
-                    //println!("{}", print_tag_item(tidx + 1, &tag.key, &tag.value, 21));
+    // Anything else, at least try to find a year
+    let year = value
+        .split(|c: char| !c.is_ascii_digit())
+        .find_map(|part| parse_date_part(part, 4, 9999));
+    return (year, None);
+}
+
+/*
+Combines all date tags into year and release date, the most precise date wins.
+ID3v2.3 splits the date into TYER ("2021") and TDAT ("0305", day and month), symphonia reports both as Date.
+ */
+fn parse_date_tags(dates: &[(String, String)]) -> (Option<i64>, Option<String>) {
+    let mut year: Option<i64> = None;
+    let mut release_date: Option<String> = None;
+    let mut day_month: Option<(i64, i64)> = None;
+    // "2025-08-22" is more precise than "2025-08"
+    let precision =
+        |date: &Option<String>| date.as_ref().map(|date| date.len()).unwrap_or_default();
+
+    for (key, value) in dates {
+        if key.eq_ignore_ascii_case("TDAT") {
+            let value = value.trim();
+            if value.len() == 4 && value.is_ascii() {
+                let day = parse_date_part(&value[..2], 2, 31);
+                let month = parse_date_part(&value[2..], 2, 12);
+                if let (Some(day), Some(month)) = (day, month) {
+                    day_month = Some((day, month));
                 }
-            }*/
+            }
+            continue;
+        }
+
+        let (date_year, date) = parse_date(value);
+        if precision(&date) > precision(&release_date) {
+            year = date_year;
+            release_date = date;
+        } else if year.is_none() {
+            year = date_year;
         }
     }
-    return None;
+
+    if let (Some(year), None, Some((day, month))) = (year, &release_date, day_month) {
+        release_date = format_date(year, Some(month), Some(day));
+    }
+
+    return (year, release_date);
 }
 
 fn fill_meta_tags(mut meta: Meta, tags: &[Tag]) -> Meta {
     if !tags.is_empty() {
-        // Print tags with a standard tag key first, these are the most common tags.
+        // SoundCheck is only used if there is no ReplayGain track gain
+        let mut soundcheck_gain: Option<f64> = None;
+        // Date tags are combined after reading all tags, see parse_date_tags
+        let mut dates: Vec<(String, String)> = Vec::new();
+
+        // Tags with a standard tag key first, these are the most common tags.
         for tag in tags.iter().filter(|tag| tag.is_known()) {
             if let Some(std_key) = tag.std_key {
-                match format!("{:?}", std_key).as_str() {
-                    "TrackTitle" => {
-                        meta.name = Some(tag.value.to_string());
+                let value = tag.value.to_string();
+                match std_key {
+                    StandardTagKey::TrackTitle => {
+                        meta.name = Some(value);
                     }
-                    "Artist" => {
-                        meta.artist = Some(tag.value.to_string());
+                    StandardTagKey::Artist => {
+                        meta.artist = Some(value);
                     }
-                    "AlbumArtist" => {
-                        meta.album_artist = Some(tag.value.to_string());
+                    StandardTagKey::AlbumArtist => {
+                        meta.album_artist = Some(value);
                     }
-                    "Composer" => {
-                        meta.composer = Some(tag.value.to_string());
+                    StandardTagKey::Composer => {
+                        meta.composer = Some(value);
                     }
-                    "Album" => {
-                        meta.album = Some(tag.value.to_string());
+                    StandardTagKey::Album => {
+                        meta.album = Some(value);
                     }
-                    "Genre" => {
-                        meta.genre = Some(tag.value.to_string());
+                    StandardTagKey::Genre => {
+                        meta.genre = Some(value);
                     }
-                    "DiscNumber" => {
-                        match tag.value.to_string().parse::<i64>() {
-                            Ok(value) => {
-                                meta.disc_number = Some(value);
-                            }
-                            Err(_error) => {}
-                        };
+                    StandardTagKey::DiscNumber => {
+                        // A separate DiscTotal tag takes precedence over the total of "1/2"
+                        let (number, total) = parse_number_pair(&value);
+                        if number.is_some() {
+                            meta.disc_number = number;
+                        }
+                        if meta.disc_count.is_none() {
+                            meta.disc_count = total;
+                        }
                     }
-                    "DiscTotal" => {
-                        match tag.value.to_string().parse::<i64>() {
-                            Ok(value) => {
-                                meta.disc_count = Some(value);
-                            }
-                            Err(_error) => {}
-                        };
+                    StandardTagKey::DiscTotal => {
+                        if let (Some(number), _) = parse_number_pair(&value) {
+                            meta.disc_count = Some(number);
+                        }
                     }
-                    "TrackNumber" => {
-                        match tag.value.to_string().parse::<i64>() {
-                            Ok(value) => {
-                                meta.track_number = Some(value);
-                            }
-                            Err(_error) => {}
-                        };
+                    StandardTagKey::TrackNumber => {
+                        let (number, total) = parse_number_pair(&value);
+                        if number.is_some() {
+                            meta.track_number = number;
+                        }
+                        if meta.track_count.is_none() {
+                            meta.track_count = total;
+                        }
+                    }
+                    StandardTagKey::TrackTotal => {
+                        if let (Some(number), _) = parse_number_pair(&value) {
+                            meta.track_count = Some(number);
+                        }
+                    }
+                    StandardTagKey::Date | StandardTagKey::ReleaseDate => {
+                        dates.push((tag.key.clone(), value));
+                    }
+                    StandardTagKey::OriginalDate => {
+                        set_text(&mut meta.original_date, value);
                     }
-                    "TrackTotal" => {
-                        match tag.value.to_string().parse::<i64>() {
-                            Ok(value) => {
-                                meta.track_count = Some(value);
+                    StandardTagKey::ReplayGainTrackGain => {
+                        meta.replaygain.track_gain = parse_replaygain_value(&value);
+                    }
+                    StandardTagKey::ReplayGainTrackPeak => {
+                        meta.replaygain.track_peak = parse_replaygain_value(&value);
+                    }
+                    StandardTagKey::ReplayGainAlbumGain => {
+                        meta.replaygain.album_gain = parse_replaygain_value(&value);
+                    }
+                    StandardTagKey::ReplayGainAlbumPeak => {
+                        meta.replaygain.album_peak = parse_replaygain_value(&value);
+                    }
+                    StandardTagKey::Rating if tag.key.starts_with("POPM") => {
+                        if let Ok(popm) = value.parse::<u8>() {
+                            meta.rating = Some(popm_to_rating(popm));
+                        }
+                    }
+                    StandardTagKey::Comment => {
+                        // ID3 drops the comment description, so iTunNORM and iTunSMPB are only recognizable by their values
+                        if is_hex_words(&value) {
+                            if soundcheck_gain.is_none() {
+                                soundcheck_gain = parse_itunnorm(&value);
                             }
-                            Err(_error) => {}
-                        };
-                    }
-                    "Date" => {
-                        // TODO: get release date and year for meta
-                        /*
-                        year: None,  // tags[11]
-                        release_date: None, // tags[11]
-                        [11] Date                         : 2025-08-22
-                        */
-                        meta.release_date = Some(tag.value.to_string());
+                        } else {
+                            set_text(&mut meta.comment, value);
+                        }
+                    }
+                    StandardTagKey::SortTrackTitle => {
+                        set_text(&mut meta.sort_name, value);
+                    }
+                    StandardTagKey::SortArtist => {
+                        set_text(&mut meta.sort_artist, value);
+                    }
+                    StandardTagKey::SortAlbumArtist => {
+                        set_text(&mut meta.sort_album_artist, value);
+                    }
+                    StandardTagKey::SortAlbum => {
+                        set_text(&mut meta.sort_album, value);
+                    }
+                    StandardTagKey::Compilation => {
+                        meta.compilation = Some(parse_compilation(&value));
+                    }
+                    StandardTagKey::Bpm => {
+                        meta.bpm = parse_bpm(&value);
+                    }
+                    StandardTagKey::Lyrics => {
+                        set_text(&mut meta.lyrics, value);
+                    }
+                    StandardTagKey::ContentGroup => {
+                        set_text(&mut meta.grouping, value);
+                    }
+                    StandardTagKey::MusicBrainzRecordingId | StandardTagKey::MusicBrainzTrackId => {
+                        set_text(&mut meta.musicbrainz_track_id, value);
+                    }
+                    StandardTagKey::MusicBrainzAlbumId => {
+                        set_text(&mut meta.musicbrainz_album_id, value);
+                    }
+                    StandardTagKey::MusicBrainzArtistId => {
+                        set_text(&mut meta.musicbrainz_artist_id, value);
+                    }
+                    StandardTagKey::MusicBrainzAlbumArtistId => {
+                        set_text(&mut meta.musicbrainz_album_artist_id, value);
+                    }
+                    StandardTagKey::MusicBrainzReleaseGroupId => {
+                        set_text(&mut meta.musicbrainz_release_group_id, value);
+                    }
+                    StandardTagKey::IdentIsrc => {
+                        set_text(&mut meta.isrc, value);
+                    }
+                    StandardTagKey::Label => {
+                        set_text(&mut meta.label, value);
                     }
                     _ => {}
                 }
             }
         }
 
-        // TODO: could there be tags of interest in here?
-        // Print the remaining tags with keys truncated to 26 characters.
-        /*for tag in tags.iter().filter(|tag| !tag.is_known()) {
-            println!("{}", print_tag_item(idx, &tag.key, &tag.value, 4));
-            idx += 1;
-        }*/
+        /*
+        Vendor keys without a standard tag key: ID3 TXXX frames ("TXXX:MusicBrainz Album Id"),
+        MP4 freeform atoms ("----:com.apple.iTunes:replaygain_track_gain") and free-form Vorbis comments.
+        The key is reduced to its last segment, lowercase without spaces and underscores.
+         */
+        for tag in tags.iter().filter(|tag| !tag.is_known()) {
+            let key = tag
+                .key
+                .rsplit(':')
+                .next()
+                .unwrap_or_default()
+                .to_lowercase()
+                .replace([' ', '_'], "");
+            let value = tag.value.to_string();
+            match key.as_str() {
+                "replaygaintrackgain" if meta.replaygain.track_gain.is_none() => {
+                    meta.replaygain.track_gain = parse_replaygain_value(&value);
+                }
+                "replaygaintrackpeak" if meta.replaygain.track_peak.is_none() => {
+                    meta.replaygain.track_peak = parse_replaygain_value(&value);
+                }
+                "replaygainalbumgain" if meta.replaygain.album_gain.is_none() => {
+                    meta.replaygain.album_gain = parse_replaygain_value(&value);
+                }
+                "replaygainalbumpeak" if meta.replaygain.album_peak.is_none() => {
+                    meta.replaygain.album_peak = parse_replaygain_value(&value);
+                }
+                "itunnorm" => {
+                    soundcheck_gain = parse_itunnorm(&value);
+                }
+                // Preferred over POPM, since its scale is unambiguous
+                "fmpsrating" => {
+                    if let Some(rating) = parse_fmps_rating(&value) {
+                        meta.rating = Some(rating);
+                    }
+                }
+                "titlesort" | "sonm" | "tsot" => {
+                    set_text(&mut meta.sort_name, value);
+                }
+                "artistsort" | "soar" | "tsop" => {
+                    set_text(&mut meta.sort_artist, value);
+                }
+                "albumartistsort" | "soaa" | "tso2" => {
+                    set_text(&mut meta.sort_album_artist, value);
+                }
+                "albumsort" | "soal" | "tsoa" => {
+                    set_text(&mut meta.sort_album, value);
+                }
+                "compilation" | "cpil" | "tcmp" if meta.compilation.is_none() => {
+                    meta.compilation = Some(parse_compilation(&value));
+                }
+                "bpm" | "tmpo" | "tbpm" if meta.bpm.is_none() => {
+                    meta.bpm = parse_bpm(&value);
+                }
+                "lyrics" | "unsyncedlyrics" | "©lyr" => {
+                    set_text(&mut meta.lyrics, value);
+                }
+                "grouping" | "©grp" => {
+                    set_text(&mut meta.grouping, value);
+                }
+                "musicbrainztrackid" | "musicbrainzrecordingid" => {
+                    set_text(&mut meta.musicbrainz_track_id, value);
+                }
+                "musicbrainzalbumid" => {
+                    set_text(&mut meta.musicbrainz_album_id, value);
+                }
+                "musicbrainzartistid" => {
+                    set_text(&mut meta.musicbrainz_artist_id, value);
+                }
+                "musicbrainzalbumartistid" => {
+                    set_text(&mut meta.musicbrainz_album_artist_id, value);
+                }
+                "musicbrainzreleasegroupid" => {
+                    set_text(&mut meta.musicbrainz_release_group_id, value);
+                }
+                "isrc" => {
+                    set_text(&mut meta.isrc, value);
+                }
+                "label" | "publisher" | "organization" => {
+                    set_text(&mut meta.label, value);
+                }
+                "originaldate" | "originalyear" | "tdor" | "tory" => {
+                    set_text(&mut meta.original_date, value);
+                }
+                _ => {}
+            }
+        }
+
+        if meta.replaygain.track_gain.is_none() {
+            meta.replaygain.track_gain = soundcheck_gain;
+        }
+
+        let (year, release_date) = parse_date_tags(&dates);
+        if year.is_some() {
+            meta.year = year;
+        }
+        if release_date.is_some() {
+            meta.release_date = release_date;
+        }
     }
 
     return meta;
@@ -348,26 +761,44 @@ fn fill_meta_tags(mut meta: Meta, tags: &[Tag]) -> Meta {
 
 fn get_meta(probed: &mut ProbeResult) -> Meta {
     let mut meta: Meta = Meta {
-        name: None,         // tags[02]
-        artist: None,       // check Artists table, create new if not existing from tags[05]
+        name: None,                        // tags[02]
+        artist: None, // check Artists table, create new if not existing from tags[05]
         album_artist: None, // check Artists table, create new if not existing from tags[04]
-        composer: None,     // check Composers table, create new if not existing from tags[12]
-        album: None,        // check Albums table, create new if not existing from tags[03]
-        genre: None, // check Genres table, create new if not existing from inexistent? tags[]
-        kind: None,  // Tracks [01] Codec
-        size: None,  // not available? calculate from copy file or os call.
+        composer: None, // check Composers table, create new if not existing from tags[12]
+        album: None,  // check Albums table, create new if not existing from tags[03]
+        genre: None,  // check Genres table, create new if not existing from inexistent? tags[]
+        kind: None,   // Tracks [01] Codec
+        size: None,   // not available? calculate from copy file or os call.
         total_time: None, // Tracks [01] Duration
         disc_number: None, // tags[09]
         disc_count: None, // tags[10]
         track_number: None, // tags[07]
         track_count: None, // tags[08]
-        year: None,  // tags[11]
+        year: None,   // tags[11]
         bit_rate: None, // Tracks [01] sample_rate * bits_per_sample / 1024 / 8      (96000*24/1024/8 = 281,25)
         sample_rate: None, // Tracks [01]
         release_date: None, // tags[11]
-        // normalization: not really available... tags[15-18] have values, but not sure if useful, also so far no use for normalization value.,
+        replaygain: ReplayGain::default(), // tags[15-18]
+        gapless: None,  // Tracks [01] Encoder Delay, Encoder Padding
+        rating: None,   // POPM or FMPS_RATING
+        sort_name: None,
+        sort_artist: None,
+        sort_album_artist: None,
+        sort_album: None,
+        compilation: None,
+        bpm: None,
+        lyrics: None,
+        comment: None,
+        grouping: None,
+        musicbrainz_track_id: None,
+        musicbrainz_album_id: None,
+        musicbrainz_artist_id: None,
+        musicbrainz_album_artist_id: None,
+        musicbrainz_release_group_id: None,
+        isrc: None,
+        label: None,
+        original_date: None,
         // artwork_count: -1, although theoretically I could use Visuals [01] FrontCover,
-        // sort_name: not available, also not really used,
         // persistent_id: not available, can calculate hash with salt of current time to prevent collision or pass some uuid,
         // track_type: "File", aka useless,
         // purchased: NULL,
@@ -450,6 +881,8 @@ fn get_meta(probed: &mut ProbeResult) -> Meta {
                 }
             }
 
+            meta.gapless = Some(get_gapless(params));
+
             // We do not allow multiple tracks in one file
             break;
         }
@@ -545,6 +978,53 @@ pub fn extract_metadata(path: &Path) -> Option<Meta> {
     }
 }
 
+// Gapless stays disabled while probing, so n_frames includes delay and padding
+fn get_gapless(params: &CodecParameters) -> Gapless {
+    return Gapless {
+        encoder_delay: params.delay.unwrap_or_default() as i64,
+        encoder_padding: params.padding.unwrap_or_default() as i64,
+        frame_count: params.n_frames.unwrap_or_default() as i64,
+        sample_rate: params.sample_rate.unwrap_or_default() as i64,
+    };
+}
+
+/*
+Probes only the encoder delay and padding of a file, for tracks that were not added from their file.
+Playback uses the stored values, so the file does not have to be probed on every track change.
+ */
+pub fn extract_gapless_info(path: &Path) -> Option<Gapless> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(_e) => return None,
+    };
+
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let format_opts = FormatOptions {
+        ..Default::default()
+    };
+
+    let metadata_opts: MetadataOptions = Default::default();
+
+    let mut hint = Hint::new();
+
+    if let Some(extension) = path.extension() {
+        if let Some(extension_str) = extension.to_str() {
+            hint.with_extension(extension_str);
+        }
+    }
+
+    match symphonia::default::get_probe().format(&hint, mss, &format_opts, &metadata_opts) {
+        Ok(probed) => {
+            let track = probed.format.default_track()?;
+            return Some(get_gapless(&track.codec_params));
+        }
+        Err(_err) => {
+            return None;
+        }
+    }
+}
+
 /*
 fn fmt_time(ts: u64, tb: TimeBase) -> String {
     let time = tb.calc_time(ts);
@@ -628,3 +1108,147 @@ fn fmt_time(ts: u64, tb: TimeBase) -> String {
 |          Size:       11306 bytes
 :
  */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dates(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+        return tags
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+    }
+
+    #[test]
+    fn parses_number_pairs() {
+        let cases = [
+            ("3", (Some(3), None)),
+            ("03", (Some(3), None)),
+            ("3/12", (Some(3), Some(12))),
+            (" 3 / 12 ", (Some(3), Some(12))),
+            ("/12", (None, Some(12))),
+            ("3/", (Some(3), None)),
+            ("0/0", (None, None)),
+            ("", (None, None)),
+            ("A1", (None, None)),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_number_pair(value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn parses_dates() {
+        let cases = [
+            ("2025", (Some(2025), None)),
+            ("2025-08", (Some(2025), Some("2025-08"))),
+            ("2025-08-22", (Some(2025), Some("2025-08-22"))),
+            (" 2025-08-22 ", (Some(2025), Some("2025-08-22"))),
+            ("2025-08-22T10:00", (Some(2025), Some("2025-08-22"))),
+            ("2025-08-22T07:00:00Z", (Some(2025), Some("2025-08-22"))),
+            ("2025-08-22 10:00:00", (Some(2025), Some("2025-08-22"))),
+            ("2025/08/22", (Some(2025), Some("2025-08-22"))),
+            ("2025.08.22", (Some(2025), Some("2025-08-22"))),
+            ("22.08.2025", (Some(2025), Some("2025-08-22"))),
+            ("2025-13-01", (Some(2025), None)),
+            ("2025-08-32", (Some(2025), Some("2025-08"))),
+            ("(p) 1999 Label", (Some(1999), None)),
+            ("unknown", (None, None)),
+            ("", (None, None)),
+        ];
+        for (value, (year, release_date)) in cases {
+            assert_eq!(
+                parse_date(value),
+                (year, release_date.map(|date| date.to_string())),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn combines_date_tags() {
+        let cases = [
+            // ID3v2.3 TYER and TDAT (DDMM), in any order
+            (
+                vec![("TDAT", "0305"), ("TYER", "2021")],
+                (Some(2021), Some("2021-05-03")),
+            ),
+            (
+                vec![("TYER", "2021"), ("TDAT", "0305")],
+                (Some(2021), Some("2021-05-03")),
+            ),
+            (
+                vec![("TYER", "2021"), ("TDAT", "3105")],
+                (Some(2021), Some("2021-05-31")),
+            ),
+            (vec![("TYER", "2021"), ("TDAT", "1399")], (Some(2021), None)),
+            (vec![("TDAT", "0305")], (None, None)),
+            // ID3v2.4 TDRC
+            (
+                vec![("TDRC", "2025-08-22T10:00")],
+                (Some(2025), Some("2025-08-22")),
+            ),
+            (vec![("TDRC", "2025")], (Some(2025), None)),
+            // Vorbis comments and MP4, the most precise date wins
+            (
+                vec![("DATE", "2025"), ("DATE", "2025-08-22")],
+                (Some(2025), Some("2025-08-22")),
+            ),
+            (
+                vec![("DATE", "2025-08-22"), ("DATE", "2025")],
+                (Some(2025), Some("2025-08-22")),
+            ),
+            (
+                vec![("©day", "2021-03-26T07:00:00Z")],
+                (Some(2021), Some("2021-03-26")),
+            ),
+            // A full date is not combined with TDAT
+            (
+                vec![("TDRC", "2021-03-26"), ("TDAT", "0305")],
+                (Some(2021), Some("2021-03-26")),
+            ),
+            (vec![], (None, None)),
+        ];
+        for (tags, (year, release_date)) in cases {
+            assert_eq!(
+                parse_date_tags(&dates(&tags)),
+                (year, release_date.map(|date| date.to_string())),
+                "{:?}",
+                tags
+            );
+        }
+    }
+
+    #[test]
+    fn chooses_cover_files() {
+        let cases = [
+            (
+                vec!["booklet.jpg", "Cover.JPG", "folder.jpg"],
+                Some("Cover.JPG"),
+            ),
+            (vec!["front.png", "folder.jpg"], Some("folder.jpg")),
+            (vec!["scan.jpg", "front.png"], Some("front.png")),
+            (
+                vec!["AlbumArtSmall.jpg", "AlbumArt_{1234}_Large.jpg"],
+                Some("AlbumArt_{1234}_Large.jpg"),
+            ),
+            (vec!["AlbumArtSmall.jpg"], Some("AlbumArtSmall.jpg")),
+            (vec!["AlbumArt.png", "cover.txt", "back.jpg"], None),
+            (vec![], None),
+        ];
+        for (names, expected) in cases {
+            let images: Vec<PathBuf> = names
+                .iter()
+                .map(|name| Path::new("/music/album").join(name))
+                .collect();
+            assert_eq!(
+                choose_cover_file(&images),
+                expected.map(|name| Path::new("/music/album").join(name)),
+                "{:?}",
+                names
+            );
+        }
+    }
+}
@@ -0,0 +1,560 @@
+// Reading and writing playlist files (M3U8, PLS, XSPF) for use with other players
+use crate::defs::{PlaylistFormat, PlaylistPaths};
+use quick_xml::{
+    escape::{escape, unescape},
+    events::Event,
+    reader::Reader,
+};
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+static PLAYLIST_EXTENSIONS: [&str; 4] = ["m3u", "m3u8", "pls", "xspf"];
+
+pub struct PlaylistEntry {
+    pub location: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub total_time: i64, // ms
+}
+
+/*
+Returns the path of target relative to base_dir, None if they do not share a root (e.g. different drives).
+Both paths need to be absolute.
+ */
+fn relative_path(base_dir: &Path, target: &Path) -> Option<PathBuf> {
+    let base: Vec<Component> = base_dir.components().collect();
+    let target_components: Vec<Component> = target.components().collect();
+
+    if base.first() != target_components.first() {
+        return None;
+    }
+
+    let common = base
+        .iter()
+        .zip(target_components.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    for component in &target_components[common..] {
+        relative.push(component.as_os_str());
+    }
+
+    return Some(relative);
+}
+
+fn entry_path(
+    location: &str,
+    export_dir: &Path,
+    paths: &PlaylistPaths,
+    media_path: &str,
+) -> String {
+    let location_path = Path::new(location);
+
+    let relative = match paths {
+        PlaylistPaths::Absolute => None,
+        PlaylistPaths::RelativeToExport => relative_path(export_dir, location_path),
+        // Tracks outside of the media path keep their absolute path
+        PlaylistPaths::RelativeToMediaPath => location_path
+            .strip_prefix(media_path)
+            .ok()
+            .map(|path| path.to_path_buf()),
+    };
+
+    match relative {
+        Some(path) => return path.to_string_lossy().to_string(),
+        None => return location.to_string(),
+    }
+}
+
+// XSPF locations are URIs, every path segment is percent encoded
+pub fn path_to_uri(path: &str, absolute: bool) -> String {
+    let encoded = path
+        .replace('\\', "/")
+        .split('/')
+        .map(|segment| urlencoding::encode(segment).into_owned())
+        .collect::<Vec<String>>()
+        .join("/");
+
+    if absolute {
+        if encoded.starts_with('/') {
+            return format!("file://{}", encoded);
+        }
+        // Windows drive letters, e.g. C:/Music
+        return format!("file:///{}", encoded.replacen("%3A", ":", 1));
+    }
+
+    return encoded;
+}
+
+fn display_title(entry: &PlaylistEntry) -> String {
+    if entry.artist.is_empty() {
+        return entry.title.clone();
+    }
+    return format!("{} - {}", entry.artist, entry.title);
+}
+
+fn to_m3u8(entries: &[PlaylistEntry], paths: &[String]) -> String {
+    let mut content = "#EXTM3U\n".to_string();
+
+    for (entry, path) in entries.iter().zip(paths) {
+        content.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            entry.total_time / 1000,
+            display_title(entry),
+            path
+        ));
+    }
+
+    return content;
+}
+
+fn to_pls(entries: &[PlaylistEntry], paths: &[String]) -> String {
+    let mut content = "[playlist]\n".to_string();
+
+    for (index, (entry, path)) in entries.iter().zip(paths).enumerate() {
+        let number = index + 1;
+        content.push_str(&format!("File{}={}\n", number, path));
+        content.push_str(&format!("Title{}={}\n", number, display_title(entry)));
+        content.push_str(&format!("Length{}={}\n", number, entry.total_time / 1000));
+    }
+
+    content.push_str(&format!("NumberOfEntries={}\nVersion=2\n", entries.len()));
+
+    return content;
+}
+
+fn to_xspf(name: &str, entries: &[PlaylistEntry], paths: &[String]) -> String {
+    let mut content = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+"#
+    .to_string();
+    content.push_str(&format!("  <title>{}</title>\n", escape(name)));
+    content.push_str("  <trackList>\n");
+
+    for (entry, path) in entries.iter().zip(paths) {
+        let absolute = Path::new(path).is_absolute();
+        content.push_str("    <track>\n");
+        content.push_str(&format!(
+            "      <location>{}</location>\n",
+            escape(path_to_uri(path, absolute).as_str())
+        ));
+        content.push_str(&format!("      <title>{}</title>\n", escape(&entry.title)));
+        if !entry.artist.is_empty() {
+            content.push_str(&format!(
+                "      <creator>{}</creator>\n",
+                escape(&entry.artist)
+            ));
+        }
+        if !entry.album.is_empty() {
+            content.push_str(&format!("      <album>{}</album>\n", escape(&entry.album)));
+        }
+        content.push_str(&format!(
+            "      <duration>{}</duration>\n",
+            entry.total_time
+        ));
+        content.push_str("    </track>\n");
+    }
+
+    content.push_str("  </trackList>\n</playlist>\n");
+
+    return content;
+}
+
+pub fn write_playlist_file(
+    export_path: &Path,
+    name: &str,
+    entries: &[PlaylistEntry],
+    format: &PlaylistFormat,
+    paths: &PlaylistPaths,
+    media_path: &str,
+) -> Result<(), String> {
+    let export_dir = match export_path.parent() {
+        Some(dir) => dir,
+        None => Path::new("/"),
+    };
+
+    let entry_paths: Vec<String> = entries
+        .iter()
+        .map(|entry| entry_path(&entry.location, export_dir, paths, media_path))
+        .collect();
+
+    let content = match format {
+        PlaylistFormat::M3u8 => to_m3u8(entries, &entry_paths),
+        PlaylistFormat::Pls => to_pls(entries, &entry_paths),
+        PlaylistFormat::Xspf => to_xspf(name, entries, &entry_paths),
+    };
+
+    return fs::write(export_path, content).map_err(|error| error.to_string());
+}
+
+pub fn is_playlist_file(path: &Path) -> bool {
+    if let Some(extension) = path.extension() {
+        let extension = extension.to_string_lossy().to_lowercase();
+        return PLAYLIST_EXTENSIONS.contains(&extension.as_str());
+    }
+    return false;
+}
+
+// Plain M3U files are usually Latin-1, everything else is expected to be UTF-8
+fn read_text_file(path: &Path) -> Result<String, String> {
+    let bytes = fs::read(path).map_err(|error| error.to_string())?;
+
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(error) => error
+            .into_bytes()
+            .iter()
+            .map(|byte| *byte as char)
+            .collect(),
+    };
+
+    return Ok(text.trim_start_matches('\u{feff}').to_string());
+}
+
+/*
+Turns a playlist entry into an absolute path.
+file:// URLs are decoded, relative paths are relative to the playlist file.
+Returns None for other URLs (e.g. http streams).
+ */
+fn resolve_entry(entry: &str, playlist_dir: &Path) -> Option<String> {
+    let entry = entry.trim();
+    if entry.is_empty() {
+        return None;
+    }
+
+    let path_str = if let Some(url_path) = entry.strip_prefix("file://") {
+        let decoded = urlencoding::decode(url_path).ok()?.to_string();
+        // file:///C:/Music on Windows
+        match decoded.strip_prefix('/') {
+            Some(drive_path) if drive_path.chars().nth(1) == Some(':') => drive_path.to_string(),
+            _ => {
+                // file://localhost/path
+                decoded
+                    .strip_prefix("localhost")
+                    .unwrap_or(&decoded)
+                    .to_string()
+            }
+        }
+    } else if entry.contains("://") {
+        return None;
+    } else {
+        entry.to_string()
+    };
+
+    let path = Path::new(&path_str);
+    if path.is_absolute() {
+        return Some(path_str);
+    }
+
+    // Entries written on Windows use backslashes
+    let relative = path_str.replace('\\', "/");
+    return Some(
+        normalize_path(&playlist_dir.join(relative))
+            .to_string_lossy()
+            .to_string(),
+    );
+}
+
+// Removes . and .. without touching the file system, so the same track is found as in the library
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                // .. at the root stays at the root
+                normalized.pop();
+            }
+            _ => normalized.push(component.as_os_str()),
+        }
+    }
+    return normalized;
+}
+
+fn parse_m3u(content: &str) -> Vec<String> {
+    return content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect();
+}
+
+fn parse_pls(content: &str) -> Vec<String> {
+    let mut files: Vec<(i64, String)> = Vec::new();
+
+    for line in content.lines() {
+        if let Some((key, value)) = line.trim().split_once('=') {
+            if let Some(number) = key.trim().to_lowercase().strip_prefix("file") {
+                if let Ok(number) = number.parse::<i64>() {
+                    files.push((number, value.trim().to_string()));
+                }
+            }
+        }
+    }
+
+    files.sort_by_key(|(number, _)| *number);
+
+    return files.into_iter().map(|(_, file)| file).collect();
+}
+
+// Returns the playlist title, if any, and the track locations
+fn parse_xspf(content: &str) -> Result<(Option<String>, Vec<String>), String> {
+    let mut reader = Reader::from_str(content);
+
+    let mut title: Option<String> = None;
+    let mut in_track_list = false;
+    let mut locations: Vec<String> = Vec::new();
+
+    loop {
+        match reader.read_event() {
+            Err(error) => {
+                return Err(format!(
+                    "error at position {}: {:?}",
+                    reader.error_position(),
+                    error
+                ));
+            }
+            Ok(Event::Eof) => break,
+            Ok(Event::Start(e)) => match e.name().as_ref() {
+                b"trackList" => {
+                    in_track_list = true;
+                }
+                b"title" if !in_track_list && title.is_none() => {
+                    let text = reader
+                        .read_text(e.name())
+                        .map_err(|error| error.to_string())?;
+                    title = unescape(&text).ok().map(|text| text.trim().to_string());
+                }
+                b"location" if in_track_list => {
+                    let text = reader
+                        .read_text(e.name())
+                        .map_err(|error| error.to_string())?;
+                    if let Ok(location) = unescape(&text) {
+                        let location = location.trim();
+                        // Relative locations are URI references too, file:// URLs are decoded when resolved
+                        if location.contains("://") {
+                            locations.push(location.to_string());
+                        } else {
+                            let decoded = urlencoding::decode(location)
+                                .map(|decoded| decoded.into_owned())
+                                .unwrap_or(location.to_string());
+                            locations.push(decoded);
+                        }
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    return Ok((title, locations));
+}
+
+/*
+Reads a M3U, M3U8, PLS or XSPF file.
+Returns the playlist name and each entry with its absolute path, None for entries that are not local files.
+ */
+pub fn read_playlist_file(path: &Path) -> Result<(String, Vec<(String, Option<String>)>), String> {
+    let content = read_text_file(path)?;
+
+    let extension = match path.extension() {
+        Some(extension) => extension.to_string_lossy().to_lowercase(),
+        None => "".to_string(),
+    };
+
+    let mut name = match path.file_stem() {
+        Some(stem) => stem.to_string_lossy().to_string(),
+        None => "".to_string(),
+    };
+
+    let entries = match extension.as_str() {
+        "m3u" | "m3u8" => parse_m3u(&content),
+        "pls" => parse_pls(&content),
+        "xspf" => {
+            let (title, locations) = parse_xspf(&content)?;
+            if let Some(title) = title {
+                if !title.is_empty() {
+                    name = title;
+                }
+            }
+            locations
+        }
+        _ => {
+            return Err(format!("{} is not a playlist file", path.display()));
+        }
+    };
+
+    let playlist_dir = match path.parent() {
+        Some(dir) => dir,
+        None => Path::new("/"),
+    };
+
+    return Ok((
+        name,
+        entries
+            .into_iter()
+            .map(|entry| {
+                let resolved = resolve_entry(&entry, playlist_dir);
+                (entry, resolved)
+            })
+            .collect(),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(location: &str) -> PlaylistEntry {
+        return PlaylistEntry {
+            location: location.to_string(),
+            title: "Title".to_string(),
+            artist: "Artist".to_string(),
+            album: "Album".to_string(),
+            total_time: 180_000,
+        };
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "anmutunes-playlists-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    #[test]
+    fn playlists_round_trip() {
+        let dir = temp_dir("round-trip");
+        let music_dir = dir.join("Music");
+        fs::create_dir_all(&music_dir).unwrap();
+        let locations: Vec<String> = [
+            "Artist/Album/01 Track.flac",
+            "Ärtist & Co/50% #1/02 Track?.mp3",
+            "Artist/Album/03 [Live].m4a",
+        ]
+        .iter()
+        .map(|location| music_dir.join(location).to_string_lossy().to_string())
+        .collect();
+        let entries: Vec<PlaylistEntry> =
+            locations.iter().map(|location| entry(location)).collect();
+
+        let formats = [
+            (PlaylistFormat::M3u8, "m3u8"),
+            (PlaylistFormat::Pls, "pls"),
+            (PlaylistFormat::Xspf, "xspf"),
+        ];
+        let paths = [
+            PlaylistPaths::Absolute,
+            PlaylistPaths::RelativeToExport,
+            PlaylistPaths::RelativeToMediaPath,
+        ];
+        for (format, extension) in formats {
+            for playlist_paths in paths.iter() {
+                // Relative to the media path only resolves if the playlist is in the media path
+                let export_path = music_dir.join(format!("Mix.{}", extension));
+                write_playlist_file(
+                    &export_path,
+                    "Mix",
+                    &entries,
+                    &format,
+                    playlist_paths,
+                    &music_dir.to_string_lossy(),
+                )
+                .unwrap();
+
+                let (name, read_entries) = read_playlist_file(&export_path).unwrap();
+                assert_eq!(name, "Mix");
+                let resolved: Vec<Option<String>> = read_entries
+                    .into_iter()
+                    .map(|(_, resolved)| resolved)
+                    .collect();
+                let expected: Vec<Option<String>> = locations
+                    .iter()
+                    .map(|location| Some(location.clone()))
+                    .collect();
+                assert_eq!(resolved, expected, "{} {:?}", extension, playlist_paths);
+            }
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resolves_entries() {
+        let playlist_dir = Path::new("/music/Playlists");
+        let cases = [
+            ("01.flac", Some("/music/Playlists/01.flac")),
+            ("../Album/01.flac", Some("/music/Album/01.flac")),
+            ("./../Album/./01.flac", Some("/music/Album/01.flac")),
+            ("..\\Album\\01.flac", Some("/music/Album/01.flac")),
+            ("../../../../01.flac", Some("/01.flac")),
+            ("/music/Album/01.flac", Some("/music/Album/01.flac")),
+            (
+                "file:///music/Album/01%20Track.flac",
+                Some("/music/Album/01 Track.flac"),
+            ),
+            ("file://localhost/music/01.flac", Some("/music/01.flac")),
+            // Multi-byte characters where the drive letter would be
+            ("file:///%C3%A4%C3%B6/01.flac", Some("/äö/01.flac")),
+            ("http://example.com/stream", None),
+            ("  ", None),
+        ];
+        for (entry, expected) in cases {
+            assert_eq!(
+                resolve_entry(entry, playlist_dir).as_deref(),
+                expected,
+                "{}",
+                entry
+            );
+        }
+    }
+
+    #[test]
+    fn xspf_decodes_locations() {
+        let dir = temp_dir("xspf");
+        let content = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <title>Encoded</title>
+  <trackList>
+    <track><location>Some%20Artist/01%20Track.flac</location></track>
+    <track><location>file:///music/02%20Track.flac</location></track>
+    <track><location>http://example.com/stream</location></track>
+  </trackList>
+</playlist>
+"#;
+        let path = dir.join("Encoded.xspf");
+        fs::write(&path, content).unwrap();
+
+        let (name, entries) = read_playlist_file(&path).unwrap();
+        assert_eq!(name, "Encoded");
+        let resolved: Vec<Option<String>> =
+            entries.into_iter().map(|(_, resolved)| resolved).collect();
+        assert_eq!(
+            resolved,
+            vec![
+                Some(
+                    dir.join("Some Artist/01 Track.flac")
+                        .to_string_lossy()
+                        .to_string()
+                ),
+                Some("/music/02 Track.flac".to_string()),
+                None,
+            ]
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,716 @@
+use crate::defs::{
+    Order, SmartCriteria, SmartField, SmartLimit, SmartLimitUnit, SmartOperator, SmartRule,
+    SmartValue,
+};
+use base64::{engine::general_purpose, Engine as _};
+use log::{debug, error};
+use sqlite::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+Smart playlists are stored in Playlists.smart_info and Playlists.smart_criteria.
+Imported iTunes playlists keep the base64 encoded binary blobs from library.xml,
+playlists created in anmutunes store a JSON encoded SmartCriteria in smart_criteria.
+
+iTunes criteria layout (all integers big endian):
+header: 136 bytes, starting with "SLst", byte 15 is 1 for "match any"
+rule: field id (u32), logic (u32), 44 bytes unknown, data length (u32), data
+logic: high byte 0x01 = string rule, 0x02 = negated, low bytes are the operator
+string data: UTF-16BE
+int data: 68 bytes, i64 words: from, from date, from unit, to, to date, to unit, padding
+
+iTunes info layout:
+byte 0 live updating, byte 2 limit enabled, byte 3 limit unit, byte 7 limit selection,
+bytes 8-11 limit value, byte 13 reverses the limit selection
+ */
+
+// Seconds between the HFS epoch (1904-01-01) used by iTunes and the unix epoch
+static HFS_EPOCH_OFFSET: i64 = 2082844800;
+// iTunes writes this instead of a date for relative rules like "is in the last"
+static RELATIVE_DATE_MAGIC: i64 = 0x2dae2dae2dae2dae;
+
+static CRITERIA_HEADER_LENGTH: usize = 136;
+static RULE_HEADER_LENGTH: usize = 56;
+
+pub fn decode_smart_criteria(smart_info: &str, smart_criteria: &str) -> Option<SmartCriteria> {
+    if smart_criteria.trim().is_empty() {
+        return None;
+    }
+
+    if smart_criteria.trim_start().starts_with('{') {
+        match serde_json::from_str::<SmartCriteria>(smart_criteria) {
+            Ok(criteria) => {
+                return Some(criteria);
+            }
+            Err(error) => {
+                error!("{}", error);
+                return None;
+            }
+        }
+    }
+
+    return decode_itunes_smart_criteria(smart_info, smart_criteria);
+}
+
+fn decode_base64_data(data: &str) -> Option<Vec<u8>> {
+    // library.xml wraps the base64 data in whitespace and newlines
+    let stripped: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+
+    match general_purpose::STANDARD.decode(stripped) {
+        Ok(bytes) => {
+            return Some(bytes);
+        }
+        Err(error) => {
+            error!("{}", error);
+            return None;
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let slice = bytes.get(offset..offset + 4)?;
+    return Some(u32::from_be_bytes(slice.try_into().unwrap()));
+}
+
+fn read_i64(bytes: &[u8], offset: usize) -> Option<i64> {
+    let slice = bytes.get(offset..offset + 8)?;
+    return Some(i64::from_be_bytes(slice.try_into().unwrap()));
+}
+
+fn decode_itunes_smart_criteria(smart_info: &str, smart_criteria: &str) -> Option<SmartCriteria> {
+    let info = decode_base64_data(smart_info).unwrap_or_default();
+    let criteria = decode_base64_data(smart_criteria)?;
+
+    if criteria.len() < CRITERIA_HEADER_LENGTH || &criteria[0..4] != b"SLst" {
+        debug!("unknown smart criteria format");
+        return None;
+    }
+
+    let mut smart = SmartCriteria {
+        match_all: criteria[15] != 1,
+        rules: Vec::new(),
+        limit: decode_itunes_smart_limit(&info),
+        live_updating: info.get(0) == Some(&1),
+    };
+
+    let mut offset = CRITERIA_HEADER_LENGTH;
+    while let (Some(field_id), Some(logic), Some(data_length)) = (
+        read_u32(&criteria, offset),
+        read_u32(&criteria, offset + 4),
+        read_u32(&criteria, offset + 52),
+    ) {
+        let data_start = offset + RULE_HEADER_LENGTH;
+        let data_end = data_start + data_length as usize;
+        let Some(data) = criteria.get(data_start..data_end) else {
+            debug!("smart criteria rule exceeds data length");
+            break;
+        };
+        offset = data_end;
+
+        let is_string = (logic >> 24) & 0x01 == 0x01;
+        let negate = (logic >> 24) & 0x02 == 0x02;
+
+        let operator = match logic & 0xffff {
+            0x0001 => SmartOperator::Is,
+            0x0002 => SmartOperator::Contains,
+            0x0004 => SmartOperator::StartsWith,
+            0x0008 => SmartOperator::EndsWith,
+            0x0010 => SmartOperator::GreaterThan,
+            0x0040 => SmartOperator::LessThan,
+            0x0100 => SmartOperator::InRange,
+            0x0200 => SmartOperator::InTheLast,
+            other => SmartOperator::Unsupported(other as i64),
+        };
+
+        let field = match field_id {
+            0x02 => SmartField::Name,
+            0x03 => SmartField::Album,
+            0x04 => SmartField::Artist,
+            0x05 => SmartField::BitRate,
+            0x06 => SmartField::SampleRate,
+            0x07 => SmartField::Year,
+            0x08 => SmartField::Genre,
+            0x09 => SmartField::Kind,
+            0x0a => SmartField::DateModified,
+            0x0b => SmartField::TrackNumber,
+            0x0c => SmartField::Size,
+            0x0d => SmartField::Time,
+            0x10 => SmartField::DateAdded,
+            0x12 => SmartField::Composer,
+            0x16 => SmartField::Plays,
+            0x18 => SmartField::DiscNumber,
+            0x19 => SmartField::Rating,
+            0x28 => SmartField::Playlist,
+            0x47 => SmartField::AlbumArtist,
+            0x5a => SmartField::AlbumRating,
+            other => SmartField::Unsupported(other as i64),
+        };
+
+        let value = if is_string {
+            let utf16: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            SmartValue::Text(String::from_utf16_lossy(&utf16))
+        } else {
+            let from = read_i64(data, 0).unwrap_or_default();
+            let to = read_i64(data, 24).unwrap_or_default();
+
+            if field == SmartField::Playlist {
+                // Playlist rules reference the persistent id as stored in library.xml
+                SmartValue::Text(format!("{:016X}", from as u64))
+            } else if operator == SmartOperator::InTheLast || from == RELATIVE_DATE_MAGIC {
+                let amount = read_i64(data, 8).unwrap_or_default();
+                let unit = read_i64(data, 16).unwrap_or_default();
+                SmartValue::Last(amount.abs(), unit)
+            } else {
+                let convert = |value: i64| -> i64 {
+                    match field {
+                        SmartField::DateAdded | SmartField::DateModified => {
+                            return value - HFS_EPOCH_OFFSET;
+                        }
+                        SmartField::Time => {
+                            return value / 1000;
+                        }
+                        _ => {
+                            return value;
+                        }
+                    }
+                };
+                if operator == SmartOperator::InRange {
+                    SmartValue::Range(convert(from), convert(to))
+                } else {
+                    SmartValue::Number(convert(from))
+                }
+            }
+        };
+
+        smart.rules.push(SmartRule {
+            field: field,
+            operator: operator,
+            negate: negate,
+            value: value,
+        });
+    }
+
+    return Some(smart);
+}
+
+fn decode_itunes_smart_limit(info: &[u8]) -> Option<SmartLimit> {
+    if info.len() < 14 || info[2] != 1 {
+        return None;
+    }
+
+    let unit = match info[3] {
+        0x01 => SmartLimitUnit::Minutes,
+        0x02 => SmartLimitUnit::MB,
+        0x03 => SmartLimitUnit::Items,
+        0x04 => SmartLimitUnit::Hours,
+        0x05 => SmartLimitUnit::GB,
+        _ => {
+            return None;
+        }
+    };
+
+    let reverse = info[13] == 1;
+
+    // TODO: iTunes also selects by rating, play count and last played date, these fall back to random
+    let order = match (info[7], reverse) {
+        (0x05, false) => Some(Order::ByName),
+        (0x05, true) => Some(Order::ByNameInverse),
+        (0x06, false) => Some(Order::ByAlbum),
+        (0x06, true) => Some(Order::ByAlbumInverse),
+        (0x07, false) => Some(Order::ByArtist),
+        (0x07, true) => Some(Order::ByArtistInverse),
+        (0x09, false) => Some(Order::ByGenre),
+        (0x09, true) => Some(Order::ByGenreInverse),
+        // "most recently added" is the default, reversed is "least recently added"
+        (0x15, false) => Some(Order::ByAddedDateInverse),
+        (0x15, true) => Some(Order::ByAddedDate),
+        _ => None,
+    };
+
+    return Some(SmartLimit {
+        unit: unit,
+        value: read_u32(info, 8).unwrap_or_default() as i64,
+        order: order,
+    });
+}
+
+fn escape_like(text: &str) -> String {
+    return text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+}
+
+fn smart_rule_to_sql(rule: &SmartRule, values: &mut Vec<Value>) -> Option<String> {
+    let column = match rule.field {
+        SmartField::Name => "Tracks.name",
+        SmartField::Album => "Albums.name",
+        SmartField::Artist => "Artist.name",
+        SmartField::AlbumArtist => "AlbumArtist.name",
+        SmartField::Composer => "Composers.name",
+        SmartField::Genre => "Genres.name",
+        SmartField::Kind => "Tracks.kind",
+        SmartField::BitRate => "Tracks.bit_rate",
+        SmartField::SampleRate => "Tracks.sample_rate",
+        SmartField::Year => "Tracks.year",
+        SmartField::TrackNumber => "Tracks.track_number",
+        SmartField::DiscNumber => "Tracks.disc_number",
+        SmartField::Size => "Tracks.size",
+        SmartField::Time => "Tracks.total_time / 1000",
+        SmartField::DateAdded => "CAST(strftime('%s', Tracks.date_added) AS INTEGER)",
+        SmartField::DateModified => "CAST(strftime('%s', Tracks.date_modified) AS INTEGER)",
+        SmartField::Plays => "Tracks.plays",
+        SmartField::Rating => "Tracks.rating",
+        SmartField::AlbumRating => "Albums.rating",
+        SmartField::Playlist => "Tracks.track_id",
+        SmartField::Unsupported(_) => {
+            return None;
+        }
+    };
+
+    let condition = match (&rule.field, &rule.operator, &rule.value) {
+        (SmartField::Playlist, SmartOperator::Is, SmartValue::Text(persistent_id)) => {
+            values.push(Value::String(persistent_id.clone()));
+            "Tracks.track_id IN (SELECT value FROM json_each((SELECT tracks FROM Playlists WHERE playlist_persistent_id = ? LIMIT 1)))".to_string()
+        }
+        (SmartField::Playlist, SmartOperator::Is, SmartValue::Number(playlist_id)) => {
+            values.push(Value::Integer(*playlist_id));
+            "Tracks.track_id IN (SELECT value FROM json_each((SELECT tracks FROM Playlists WHERE playlist_id = ?)))".to_string()
+        }
+        (SmartField::Playlist, _, _) => {
+            return None;
+        }
+        (_, SmartOperator::Is, SmartValue::Text(text)) => {
+            values.push(Value::String(text.clone()));
+            format!("LOWER(IFNULL({}, '')) = LOWER(?)", column)
+        }
+        (_, SmartOperator::Contains, SmartValue::Text(text)) => {
+            values.push(Value::String(escape_like(text)));
+            format!("IFNULL({}, '') LIKE '%' || ? || '%' ESCAPE '\\'", column)
+        }
+        (_, SmartOperator::StartsWith, SmartValue::Text(text)) => {
+            values.push(Value::String(escape_like(text)));
+            format!("IFNULL({}, '') LIKE ? || '%' ESCAPE '\\'", column)
+        }
+        (_, SmartOperator::EndsWith, SmartValue::Text(text)) => {
+            values.push(Value::String(escape_like(text)));
+            format!("IFNULL({}, '') LIKE '%' || ? ESCAPE '\\'", column)
+        }
+        (_, SmartOperator::Is, SmartValue::Number(number)) => {
+            values.push(Value::Integer(*number));
+            format!("IFNULL({}, 0) = ?", column)
+        }
+        (_, SmartOperator::GreaterThan, SmartValue::Number(number)) => {
+            values.push(Value::Integer(*number));
+            format!("IFNULL({}, 0) > ?", column)
+        }
+        (_, SmartOperator::LessThan, SmartValue::Number(number)) => {
+            values.push(Value::Integer(*number));
+            format!("IFNULL({}, 0) < ?", column)
+        }
+        (_, SmartOperator::InRange, SmartValue::Range(from, to)) => {
+            values.push(Value::Integer(*from));
+            values.push(Value::Integer(*to));
+            format!("IFNULL({}, 0) BETWEEN ? AND ?", column)
+        }
+        (_, SmartOperator::InTheLast, SmartValue::Last(amount, unit)) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64;
+            values.push(Value::Integer(now - amount * unit));
+            format!("IFNULL({}, 0) >= ?", column)
+        }
+        _ => {
+            return None;
+        }
+    };
+
+    if rule.negate {
+        return Some(format!("NOT ({})", condition));
+    }
+    return Some(condition);
+}
+
+fn smart_order_to_sql(order: &Option<Order>) -> String {
+    match order {
+        Some(Order::ByName) => "LOWER(Tracks.name) ASC".to_string(),
+        Some(Order::ByNameInverse) => "LOWER(Tracks.name) DESC".to_string(),
+        Some(Order::ByAlbum) => "LOWER(Albums.name) ASC".to_string(),
+        Some(Order::ByAlbumInverse) => "LOWER(Albums.name) DESC".to_string(),
+        Some(Order::ByArtist) => "LOWER(Artist.name) ASC".to_string(),
+        Some(Order::ByArtistInverse) => "LOWER(Artist.name) DESC".to_string(),
+        Some(Order::ByGenre) => "LOWER(Genres.name) ASC".to_string(),
+        Some(Order::ByGenreInverse) => "LOWER(Genres.name) DESC".to_string(),
+        Some(Order::ByAddedDate) => "Tracks.date_added ASC".to_string(),
+        Some(Order::ByAddedDateInverse) => "Tracks.date_added DESC".to_string(),
+        Some(Order::ByLastPlayed) => "IFNULL(Tracks.last_played, 0) ASC".to_string(),
+        Some(Order::ByLastPlayedInverse) => "IFNULL(Tracks.last_played, 0) DESC".to_string(),
+        Some(Order::ByPlays) => "Tracks.plays ASC".to_string(),
+        Some(Order::ByPlaysInverse) => "Tracks.plays DESC".to_string(),
+        Some(Order::ByRating) => "Tracks.rating ASC".to_string(),
+        Some(Order::ByRatingInverse) => "Tracks.rating DESC".to_string(),
+        _ => "RANDOM()".to_string(),
+    }
+}
+
+/*
+Builds the query for a smart playlist, returns None if no rule can be evaluated.
+Unsupported rules are skipped for "match any", which only narrows the playlist.
+For "match all" skipping would widen it, up to the whole library, so None is returned instead.
+The query selects track_id, total_time and size, so the limit can be applied afterwards.
+ */
+pub fn smart_criteria_to_sql(criteria: &SmartCriteria) -> Option<(String, Vec<Value>)> {
+    let mut conditions: Vec<String> = Vec::new();
+    let mut values: Vec<Value> = Vec::new();
+
+    for rule in &criteria.rules {
+        if let Some(condition) = smart_rule_to_sql(rule, &mut values) {
+            conditions.push(condition);
+        } else if criteria.match_all {
+            debug!("unsupported smart rule in match all: {:?}", rule);
+            return None;
+        } else {
+            debug!("skipping unsupported smart rule: {:?}", rule);
+        }
+    }
+
+    if conditions.len() == 0 {
+        return None;
+    }
+
+    let mut query = r#"
+    SELECT Tracks.track_id, Tracks.total_time, Tracks.size
+    FROM Tracks
+    LEFT JOIN Artists Artist ON Tracks.artist_id = Artist.artist_id
+    LEFT JOIN Artists AlbumArtist ON Tracks.album_artist_id = AlbumArtist.artist_id
+    LEFT JOIN Composers ON Tracks.composer_id = Composers.composer_id
+    LEFT JOIN Albums ON Tracks.album_id = Albums.album_id
+    LEFT JOIN Genres ON Tracks.genre_id = Genres.genre_id
+    WHERE
+    "#
+    .to_string();
+
+    if criteria.match_all {
+        query.push_str(&conditions.join(" AND "));
+    } else {
+        query.push_str(&conditions.join(" OR "));
+    }
+
+    if let Some(limit) = &criteria.limit {
+        query.push_str(" ORDER BY ");
+        query.push_str(&smart_order_to_sql(&limit.order));
+    }
+
+    return Some((query, values));
+}
+
+// rows are (track_id, total_time in ms, size in bytes), already in limit order
+pub fn apply_smart_limit(limit: &Option<SmartLimit>, rows: Vec<(i64, i64, i64)>) -> Vec<i64> {
+    let Some(limit) = limit else {
+        return rows.into_iter().map(|(track_id, _, _)| track_id).collect();
+    };
+
+    let mut track_ids: Vec<i64> = Vec::new();
+    let mut used: i64 = 0;
+
+    for (track_id, total_time, size) in rows {
+        let (amount, budget) = match limit.unit {
+            SmartLimitUnit::Items => (1, limit.value),
+            SmartLimitUnit::Minutes => (total_time, limit.value * 60 * 1000),
+            SmartLimitUnit::Hours => (total_time, limit.value * 60 * 60 * 1000),
+            SmartLimitUnit::MB => (size, limit.value * 1024 * 1024),
+            SmartLimitUnit::GB => (size, limit.value * 1024 * 1024 * 1024),
+        };
+
+        if used + amount > budget {
+            break;
+        }
+
+        used += amount;
+        track_ids.push(track_id);
+    }
+
+    return track_ids;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn itunes_rule(field_id: u32, logic: u32, data: &[u8]) -> Vec<u8> {
+        let mut rule: Vec<u8> = Vec::new();
+        rule.extend_from_slice(&field_id.to_be_bytes());
+        rule.extend_from_slice(&logic.to_be_bytes());
+        rule.extend_from_slice(&[0; 44]);
+        rule.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        rule.extend_from_slice(data);
+        return rule;
+    }
+
+    fn text_data(text: &str) -> Vec<u8> {
+        return text
+            .encode_utf16()
+            .flat_map(|unit| unit.to_be_bytes())
+            .collect();
+    }
+
+    fn int_data(from: i64, amount: i64, unit: i64, to: i64) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for word in [from, amount, unit, to, 0, 0, 0, 0] {
+            data.extend_from_slice(&word.to_be_bytes());
+        }
+        data.truncate(68);
+        return data;
+    }
+
+    fn itunes_criteria(match_any: bool, rules: &[Vec<u8>]) -> String {
+        let mut criteria: Vec<u8> = b"SLst".to_vec();
+        criteria.resize(CRITERIA_HEADER_LENGTH, 0);
+        criteria[15] = if match_any { 1 } else { 0 };
+        for rule in rules {
+            criteria.extend_from_slice(rule);
+        }
+        return general_purpose::STANDARD.encode(criteria);
+    }
+
+    fn rule(field: SmartField, operator: SmartOperator, value: SmartValue) -> SmartRule {
+        return SmartRule {
+            field: field,
+            operator: operator,
+            negate: false,
+            value: value,
+        };
+    }
+
+    fn criteria(match_all: bool, rules: Vec<SmartRule>) -> SmartCriteria {
+        return SmartCriteria {
+            match_all: match_all,
+            rules: rules,
+            limit: None,
+            live_updating: true,
+        };
+    }
+
+    #[test]
+    fn decodes_itunes_criteria() {
+        // Live updating, limited to 25 items selected by name
+        let info = general_purpose::STANDARD.encode([1, 0, 1, 3, 0, 0, 0, 5, 0, 0, 0, 25, 0, 0]);
+        let smart_criteria = itunes_criteria(
+            false,
+            &[
+                itunes_rule(0x04, 0x0100_0002, &text_data("Queen")),
+                itunes_rule(0x08, 0x0300_0001, &text_data("Rock")),
+                itunes_rule(0x07, 0x0000_0100, &int_data(1970, 0, 0, 1979)),
+                itunes_rule(
+                    0x10,
+                    0x0000_0200,
+                    &int_data(RELATIVE_DATE_MAGIC, -2, 604800, 0),
+                ),
+                itunes_rule(0x0d, 0x0000_0010, &int_data(180_000, 0, 0, 0)),
+                itunes_rule(0x99, 0x0000_0001, &int_data(1, 0, 0, 0)),
+            ],
+        );
+
+        let smart = decode_smart_criteria(&info, &smart_criteria).unwrap();
+        assert!(smart.match_all);
+        assert!(smart.live_updating);
+        let limit = smart.limit.unwrap();
+        assert_eq!(limit.unit, SmartLimitUnit::Items);
+        assert_eq!(limit.value, 25);
+        assert!(matches!(limit.order, Some(Order::ByName)));
+
+        assert_eq!(smart.rules.len(), 6);
+        let cases: [(SmartField, SmartOperator, bool); 6] = [
+            (SmartField::Artist, SmartOperator::Contains, false),
+            (SmartField::Genre, SmartOperator::Is, true),
+            (SmartField::Year, SmartOperator::InRange, false),
+            (SmartField::DateAdded, SmartOperator::InTheLast, false),
+            (SmartField::Time, SmartOperator::GreaterThan, false),
+            (SmartField::Unsupported(0x99), SmartOperator::Is, false),
+        ];
+        for (rule, (field, operator, negate)) in smart.rules.iter().zip(cases) {
+            assert_eq!(rule.field, field);
+            assert_eq!(rule.operator, operator);
+            assert_eq!(rule.negate, negate, "{:?}", rule);
+        }
+        assert!(matches!(&smart.rules[0].value, SmartValue::Text(text) if text == "Queen"));
+        assert!(matches!(
+            smart.rules[2].value,
+            SmartValue::Range(1970, 1979)
+        ));
+        assert!(matches!(smart.rules[3].value, SmartValue::Last(2, 604800)));
+        // Milliseconds to seconds
+        assert!(matches!(smart.rules[4].value, SmartValue::Number(180)));
+    }
+
+    #[test]
+    fn decodes_match_any_and_json() {
+        let smart_criteria = itunes_criteria(true, &[]);
+        let smart = decode_smart_criteria("", &smart_criteria).unwrap();
+        assert!(!smart.match_all);
+        assert!(!smart.live_updating);
+        assert!(smart.limit.is_none());
+
+        let json = serde_json::to_string(&criteria(
+            true,
+            vec![rule(
+                SmartField::Plays,
+                SmartOperator::GreaterThan,
+                SmartValue::Number(10),
+            )],
+        ))
+        .unwrap();
+        let smart = decode_smart_criteria("", &json).unwrap();
+        assert_eq!(smart.rules.len(), 1);
+
+        assert!(decode_smart_criteria("", "").is_none());
+        assert!(decode_smart_criteria("", "bm90IHNtYXJ0").is_none());
+    }
+
+    #[test]
+    fn builds_sql_conditions() {
+        let cases = [
+            (
+                rule(
+                    SmartField::Artist,
+                    SmartOperator::Contains,
+                    SmartValue::Text("50%".to_string()),
+                ),
+                "IFNULL(Artist.name, '') LIKE '%' || ? || '%' ESCAPE '\\'",
+                vec![Value::String("50\\%".to_string())],
+            ),
+            (
+                rule(
+                    SmartField::Genre,
+                    SmartOperator::Is,
+                    SmartValue::Text("Rock".to_string()),
+                ),
+                "LOWER(IFNULL(Genres.name, '')) = LOWER(?)",
+                vec![Value::String("Rock".to_string())],
+            ),
+            (
+                rule(
+                    SmartField::Year,
+                    SmartOperator::InRange,
+                    SmartValue::Range(1970, 1979),
+                ),
+                "IFNULL(Tracks.year, 0) BETWEEN ? AND ?",
+                vec![Value::Integer(1970), Value::Integer(1979)],
+            ),
+            (
+                rule(
+                    SmartField::Rating,
+                    SmartOperator::LessThan,
+                    SmartValue::Number(60),
+                ),
+                "IFNULL(Tracks.rating, 0) < ?",
+                vec![Value::Integer(60)],
+            ),
+            (
+                SmartRule {
+                    negate: true,
+                    ..rule(SmartField::Plays, SmartOperator::Is, SmartValue::Number(0))
+                },
+                "NOT (IFNULL(Tracks.plays, 0) = ?)",
+                vec![Value::Integer(0)],
+            ),
+        ];
+        for (rule, expected, expected_values) in cases {
+            let mut values: Vec<Value> = Vec::new();
+            assert_eq!(
+                smart_rule_to_sql(&rule, &mut values).as_deref(),
+                Some(expected),
+                "{:?}",
+                rule
+            );
+            assert_eq!(values, expected_values, "{:?}", rule);
+        }
+
+        let unsupported = [
+            rule(
+                SmartField::Unsupported(0x99),
+                SmartOperator::Is,
+                SmartValue::Number(1),
+            ),
+            rule(
+                SmartField::Year,
+                SmartOperator::Unsupported(0x400),
+                SmartValue::Number(1),
+            ),
+            rule(
+                SmartField::Year,
+                SmartOperator::Contains,
+                SmartValue::Number(1),
+            ),
+        ];
+        for rule in unsupported {
+            assert!(
+                smart_rule_to_sql(&rule, &mut Vec::new()).is_none(),
+                "{:?}",
+                rule
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_rules_do_not_widen_match_all() {
+        let supported = rule(
+            SmartField::Year,
+            SmartOperator::Is,
+            SmartValue::Number(1999),
+        );
+        let unsupported = rule(
+            SmartField::Unsupported(0x99),
+            SmartOperator::Is,
+            SmartValue::Number(1),
+        );
+
+        let match_all = criteria(true, vec![supported.clone(), unsupported.clone()]);
+        assert!(smart_criteria_to_sql(&match_all).is_none());
+
+        let match_any = criteria(false, vec![supported.clone(), unsupported.clone()]);
+        let (query, values) = smart_criteria_to_sql(&match_any).unwrap();
+        assert!(query.trim_end().ends_with("IFNULL(Tracks.year, 0) = ?"));
+        assert_eq!(values, vec![Value::Integer(1999)]);
+
+        let both = criteria(true, vec![supported.clone(), supported]);
+        let (query, _) = smart_criteria_to_sql(&both).unwrap();
+        assert!(query.contains("IFNULL(Tracks.year, 0) = ? AND IFNULL(Tracks.year, 0) = ?"));
+
+        assert!(smart_criteria_to_sql(&criteria(false, vec![unsupported])).is_none());
+    }
+
+    #[test]
+    fn applies_limits() {
+        let rows = vec![
+            (1, 120_000, 4_000_000),
+            (2, 240_000, 8_000_000),
+            (3, 60_000, 1_000_000),
+        ];
+        let cases = [
+            (None, vec![1, 2, 3]),
+            (Some((SmartLimitUnit::Items, 2)), vec![1, 2]),
+            (Some((SmartLimitUnit::Minutes, 5)), vec![1]),
+            (Some((SmartLimitUnit::Minutes, 6)), vec![1, 2]),
+            (Some((SmartLimitUnit::MB, 12)), vec![1, 2]),
+            (Some((SmartLimitUnit::GB, 1)), vec![1, 2, 3]),
+        ];
+        for (limit, expected) in cases {
+            let limit = limit.map(|(unit, value)| SmartLimit {
+                unit: unit,
+                value: value,
+                order: None,
+            });
+            assert_eq!(
+                apply_smart_limit(&limit, rows.clone()),
+                expected,
+                "{:?}",
+                limit
+            );
+        }
+    }
+}
@@ -0,0 +1,492 @@
+// Writing edited metadata back into the audio files (ID3v2, Vorbis comments, MP4 atoms, APE)
+use super::metadata::rating_to_popm;
+use crate::defs::Image;
+use lofty::aac::AacFile;
+use lofty::ape::{ApeFile, ApeItem, ApeTag};
+use lofty::config::{ParseOptions, WriteOptions};
+use lofty::file::{AudioFile, FileType};
+use lofty::flac::FlacFile;
+use lofty::id3::v1::Id3v1Tag;
+use lofty::id3::v2::{
+    Frame, FrameId, Id3v2Tag, Id3v2Version, PopularimeterFrame, TextInformationFrame,
+};
+use lofty::iff::aiff::AiffFile;
+use lofty::iff::wav::WavFile;
+use lofty::mp4::{Atom, AtomData, AtomIdent, Ilst, Mp4File};
+use lofty::mpeg::MpegFile;
+use lofty::musepack::MpcFile;
+use lofty::ogg::{OggPictureStorage, OpusFile, SpeexFile, VorbisComments, VorbisFile};
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::prelude::{Accessor, TagExt};
+use lofty::probe::Probe;
+use lofty::tag::ItemValue;
+use lofty::wavpack::WavPackFile;
+use lofty::TextEncoding;
+use std::borrow::Cow;
+use std::path::Path;
+
+pub struct TagUpdate {
+    pub title: String,
+    pub artist: String,
+    pub album_artist: String,
+    pub album: String,
+    pub genre: String,
+    pub composer: String,
+    pub track_number: i64,
+    pub track_count: i64,
+    pub disc_number: i64,
+    pub disc_count: i64,
+    pub year: i64,
+    pub cover: Option<Image>, // None keeps the covers in the file
+}
+
+/*
+The tags are edited in their own format, converting to lofty's generic Tag drops every frame without an ItemKey,
+e.g. PRIV, RVA2, GEOB or the POPM frames of other players.
+ */
+enum FileTag {
+    Id3v2(Id3v2Tag),
+    Id3v1(Id3v1Tag),
+    VorbisComments(VorbisComments),
+    Ilst(Ilst),
+    Ape(ApeTag),
+}
+
+/*
+Reads the writable tags of the file, the first one is the primary tag of the format and is created if missing.
+ID3v2 in FLAC and APE files is read only in lofty and stays untouched.
+ */
+fn read_tags(path: &Path) -> Result<Vec<FileTag>, String> {
+    let probe = Probe::open(path)
+        .map_err(|error| error.to_string())?
+        .guess_file_type()
+        .map_err(|error| error.to_string())?;
+    let file_type = probe.file_type();
+    let mut reader = probe.into_inner();
+    let options = ParseOptions::new();
+
+    let tags = match file_type {
+        Some(FileType::Mpeg) => {
+            let mut file =
+                MpegFile::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            vec![
+                Some(FileTag::Id3v2(file.remove_id3v2().unwrap_or_default())),
+                file.remove_ape().map(FileTag::Ape),
+                file.remove_id3v1().map(FileTag::Id3v1),
+            ]
+        }
+        Some(FileType::Aac) => {
+            let mut file =
+                AacFile::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            vec![
+                Some(FileTag::Id3v2(file.remove_id3v2().unwrap_or_default())),
+                file.remove_id3v1().map(FileTag::Id3v1),
+            ]
+        }
+        Some(FileType::Wav) => {
+            let mut file =
+                WavFile::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            vec![Some(FileTag::Id3v2(
+                file.remove_id3v2().unwrap_or_default(),
+            ))]
+        }
+        Some(FileType::Aiff) => {
+            let mut file =
+                AiffFile::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            vec![Some(FileTag::Id3v2(
+                file.remove_id3v2().unwrap_or_default(),
+            ))]
+        }
+        Some(FileType::Flac) => {
+            let mut file =
+                FlacFile::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            let mut comments = file.remove_vorbis_comments().unwrap_or_default();
+            // Saving the comments replaces all picture blocks with the pictures of the tag
+            for (picture, information) in file.remove_pictures() {
+                comments
+                    .insert_picture(picture, Some(information))
+                    .map_err(|error| error.to_string())?;
+            }
+            vec![Some(FileTag::VorbisComments(comments))]
+        }
+        Some(FileType::Vorbis) => {
+            let mut file =
+                VorbisFile::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            vec![Some(FileTag::VorbisComments(file.remove_vorbis_comments()))]
+        }
+        Some(FileType::Opus) => {
+            let mut file =
+                OpusFile::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            vec![Some(FileTag::VorbisComments(file.remove_vorbis_comments()))]
+        }
+        Some(FileType::Speex) => {
+            let mut file =
+                SpeexFile::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            vec![Some(FileTag::VorbisComments(file.remove_vorbis_comments()))]
+        }
+        Some(FileType::Mp4) => {
+            let mut file =
+                Mp4File::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            vec![Some(FileTag::Ilst(file.remove_ilst().unwrap_or_default()))]
+        }
+        Some(FileType::Ape) => {
+            let mut file =
+                ApeFile::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            vec![
+                Some(FileTag::Ape(file.remove_ape().unwrap_or_default())),
+                file.remove_id3v1().map(FileTag::Id3v1),
+            ]
+        }
+        Some(FileType::WavPack) => {
+            let mut file =
+                WavPackFile::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            vec![
+                Some(FileTag::Ape(file.remove_ape().unwrap_or_default())),
+                file.remove_id3v1().map(FileTag::Id3v1),
+            ]
+        }
+        Some(FileType::Mpc) => {
+            let mut file =
+                MpcFile::read_from(&mut reader, options).map_err(|error| error.to_string())?;
+            vec![
+                Some(FileTag::Ape(file.remove_ape().unwrap_or_default())),
+                file.remove_id3v1().map(FileTag::Id3v1),
+            ]
+        }
+        _ => {
+            return Err(format!("no writable tag in {}", path.display()));
+        }
+    };
+
+    return Ok(tags.into_iter().flatten().collect());
+}
+
+fn save_tag(path: &Path, tag: &FileTag) -> Result<(), String> {
+    let result = match tag {
+        FileTag::Id3v2(tag) => {
+            // lofty upgrades to ID3v2.4 otherwise, which older players can't read
+            let options =
+                WriteOptions::new().use_id3v23(tag.original_version() == Id3v2Version::V3);
+            tag.save_to_path(path, options)
+        }
+        FileTag::Id3v1(tag) => tag.save_to_path(path, WriteOptions::default()),
+        FileTag::VorbisComments(tag) => tag.save_to_path(path, WriteOptions::default()),
+        FileTag::Ilst(tag) => tag.save_to_path(path, WriteOptions::default()),
+        FileTag::Ape(tag) => tag.save_to_path(path, WriteOptions::default()),
+    };
+
+    return result.map_err(|error| error.to_string());
+}
+
+fn set_text<T>(tag: &mut T, text: &str, set: fn(&mut T, String), remove: fn(&mut T)) {
+    if text.is_empty() {
+        remove(tag);
+    } else {
+        set(tag, text.to_string());
+    }
+}
+
+// Title, artist, album, genre, numbers and year exist in every format
+fn update_accessors<T: Accessor>(tag: &mut T, update: &TagUpdate) {
+    set_text(tag, &update.title, T::set_title, T::remove_title);
+    set_text(tag, &update.artist, T::set_artist, T::remove_artist);
+    set_text(tag, &update.album, T::set_album, T::remove_album);
+    set_text(tag, &update.genre, T::set_genre, T::remove_genre);
+
+    // Number and total share one frame in ID3v2 and APE, removing the total first keeps the number from coming back
+    // 0 means unknown, the tag is removed then
+    tag.remove_track_total();
+    tag.remove_track();
+    if update.track_number > 0 {
+        tag.set_track(update.track_number as u32);
+    }
+    if update.track_count > 0 {
+        tag.set_track_total(update.track_count as u32);
+    }
+    tag.remove_disk_total();
+    tag.remove_disk();
+    if update.disc_number > 0 {
+        tag.set_disk(update.disc_number as u32);
+    }
+    if update.disc_count > 0 {
+        tag.set_disk_total(update.disc_count as u32);
+    }
+
+    // An unchanged year keeps a full date
+    if tag.year() != Some(update.year as u32) {
+        tag.remove_year();
+        if update.year > 0 {
+            tag.set_year(update.year as u32);
+        }
+    }
+}
+
+fn cover_picture(cover: &Image) -> Picture {
+    return Picture::new_unchecked(
+        PictureType::CoverFront,
+        Some(MimeType::from_str(&cover.media_type)),
+        None,
+        cover.data.clone(),
+    );
+}
+
+fn set_id3v2_text(tag: &mut Id3v2Tag, id: &'static str, text: &str) {
+    let frame_id = FrameId::Valid(Cow::Borrowed(id));
+    let _ = tag.remove(&frame_id);
+    if !text.is_empty() {
+        tag.insert(Frame::Text(TextInformationFrame::new(
+            frame_id,
+            TextEncoding::UTF8,
+            text.to_string(),
+        )));
+    }
+}
+
+fn set_vorbis_text(tag: &mut VorbisComments, key: &str, text: &str) {
+    let _ = tag.remove(key);
+    if !text.is_empty() {
+        tag.insert(key.to_string(), text.to_string());
+    }
+}
+
+fn set_ilst_text(tag: &mut Ilst, ident: AtomIdent<'static>, text: &str) {
+    let _ = tag.remove(&ident);
+    if !text.is_empty() {
+        tag.replace_atom(Atom::new(ident, AtomData::UTF8(text.to_string())));
+    }
+}
+
+fn set_ape_item(tag: &mut ApeTag, key: &str, value: Option<ItemValue>) -> Result<(), String> {
+    tag.remove(key);
+    if let Some(value) = value {
+        tag.insert(ApeItem::new(key.to_string(), value).map_err(|error| error.to_string())?);
+    }
+    return Ok(());
+}
+
+fn ape_text(text: &str) -> Option<ItemValue> {
+    if text.is_empty() {
+        return None;
+    }
+    return Some(ItemValue::Text(text.to_string()));
+}
+
+// Only the fields of the update are touched, everything else in the tag is written back as read
+fn update_tag(tag: &mut FileTag, update: &TagUpdate) -> Result<(), String> {
+    match tag {
+        FileTag::Id3v2(tag) => {
+            update_accessors(tag, update);
+            set_id3v2_text(tag, "TPE2", &update.album_artist);
+            set_id3v2_text(tag, "TCOM", &update.composer);
+            if let Some(cover) = &update.cover {
+                tag.remove_picture_type(PictureType::CoverFront);
+                tag.insert_picture(cover_picture(cover));
+            }
+        }
+        // ID3v1 has no album artist, composer or cover
+        FileTag::Id3v1(tag) => {
+            update_accessors(tag, update);
+        }
+        FileTag::VorbisComments(tag) => {
+            update_accessors(tag, update);
+            set_vorbis_text(tag, "ALBUMARTIST", &update.album_artist);
+            set_vorbis_text(tag, "COMPOSER", &update.composer);
+            if let Some(cover) = &update.cover {
+                tag.remove_picture_type(PictureType::CoverFront);
+                tag.insert_picture(cover_picture(cover), None)
+                    .map_err(|error| error.to_string())?;
+            }
+        }
+        FileTag::Ilst(tag) => {
+            update_accessors(tag, update);
+            set_ilst_text(tag, AtomIdent::Fourcc(*b"aART"), &update.album_artist);
+            set_ilst_text(tag, AtomIdent::Fourcc(*b"\xa9wrt"), &update.composer);
+            // MP4 covers have no picture type
+            if let Some(cover) = &update.cover {
+                tag.remove_pictures();
+                tag.insert_picture(cover_picture(cover));
+            }
+        }
+        FileTag::Ape(tag) => {
+            update_accessors(tag, update);
+            set_ape_item(tag, "Album Artist", ape_text(&update.album_artist))?;
+            set_ape_item(tag, "Composer", ape_text(&update.composer))?;
+            if let Some(cover) = &update.cover {
+                set_ape_item(
+                    tag,
+                    "Cover Art (Front)",
+                    Some(ItemValue::Binary(cover_picture(cover).as_ape_bytes())),
+                )?;
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+/*
+Writes the metadata to all tags of the file, e.g. ID3v2 and APE in MP3 files.
+Files without tags get their format's primary tag.
+ */
+pub fn write_tags(path: &Path, update: &TagUpdate) -> Result<(), String> {
+    for mut tag in read_tags(path)? {
+        update_tag(&mut tag, update)?;
+        save_tag(path, &tag)?;
+    }
+
+    return Ok(());
+}
+
+/*
+Writes the rating (0-100) to the primary tag, POPM and FMPS_Rating for ID3v2, FMPS_RATING otherwise.
+A rating of 0 removes the rating tags. Only the POPM frame without an email is ours, other players keep theirs.
+ */
+pub fn write_rating(path: &Path, rating: i64) -> Result<(), String> {
+    let Some(mut tag) = read_tags(path)?.into_iter().next() else {
+        return Err(format!("no writable tag in {}", path.display()));
+    };
+    let fmps_rating = format!("{}", rating.min(100) as f64 / 100.0);
+
+    match &mut tag {
+        FileTag::Id3v2(tag) => {
+            let _ = tag.remove_user_text("FMPS_Rating");
+            tag.retain(
+                |frame| !matches!(frame, Frame::Popularimeter(popm) if popm.email.is_empty()),
+            );
+            if rating > 0 {
+                tag.insert_user_text("FMPS_Rating".to_string(), fmps_rating);
+                tag.insert(Frame::Popularimeter(PopularimeterFrame::new(
+                    String::new(),
+                    rating_to_popm(rating),
+                    0,
+                )));
+            }
+        }
+        FileTag::Id3v1(_) => {
+            return Err(format!("no writable tag in {}", path.display()));
+        }
+        FileTag::VorbisComments(tag) => {
+            let _ = tag.remove("FMPS_RATING");
+            if rating > 0 {
+                tag.insert("FMPS_RATING".to_string(), fmps_rating);
+            }
+        }
+        FileTag::Ilst(tag) => {
+            let ident = AtomIdent::Freeform {
+                mean: Cow::Borrowed("com.apple.iTunes"),
+                name: Cow::Borrowed("FMPS_Rating"),
+            };
+            let _ = tag.remove(&ident);
+            if rating > 0 {
+                tag.replace_atom(Atom::new(ident, AtomData::UTF8(fmps_rating)));
+            }
+        }
+        FileTag::Ape(tag) => {
+            let value = if rating > 0 {
+                Some(ItemValue::Text(fmps_rating))
+            } else {
+                None
+            };
+            set_ape_item(tag, "FMPS_RATING", value)?;
+        }
+    }
+
+    return save_tag(path, &tag);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lofty::id3::v2::PrivateFrame;
+    use std::fs::{self, File};
+
+    // A few silent MPEG-1 Layer III frames, 128 kbit/s at 44.1 kHz
+    fn mp3_with_tag(path: &Path, tag: &Id3v2Tag) {
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+        fs::write(path, frame.repeat(4)).unwrap();
+        tag.save_to_path(path, WriteOptions::default()).unwrap();
+    }
+
+    fn read_id3v2(path: &Path) -> Id3v2Tag {
+        let mut file = File::open(path).unwrap();
+        return MpegFile::read_from(&mut file, ParseOptions::new())
+            .unwrap()
+            .remove_id3v2()
+            .unwrap();
+    }
+
+    fn popm_ratings(tag: &Id3v2Tag) -> Vec<(String, u8)> {
+        return tag
+            .into_iter()
+            .filter_map(|frame| match frame {
+                Frame::Popularimeter(popm) => Some((popm.email.clone(), popm.rating)),
+                _ => None,
+            })
+            .collect();
+    }
+
+    #[test]
+    fn keeps_frames_we_do_not_own() {
+        let path = std::env::temp_dir().join(format!("anmutunes-tags-{}.mp3", std::process::id()));
+        let mut tag = Id3v2Tag::new();
+        tag.set_title("Old title".to_string());
+        tag.insert(Frame::Private(PrivateFrame::new(
+            "WM/MediaClassPrimaryID".to_string(),
+            vec![1, 2, 3, 4],
+        )));
+        tag.insert_user_text("MusicMagic Fingerprint".to_string(), "abc".to_string());
+        tag.insert(Frame::Popularimeter(PopularimeterFrame::new(
+            "Windows Media Player 9 Series".to_string(),
+            64,
+            0,
+        )));
+        mp3_with_tag(&path, &tag);
+
+        let update = TagUpdate {
+            title: "New title".to_string(),
+            artist: "Artist".to_string(),
+            album_artist: String::new(),
+            album: String::new(),
+            genre: String::new(),
+            composer: String::new(),
+            track_number: 3,
+            track_count: 12,
+            disc_number: 0,
+            disc_count: 0,
+            year: 0,
+            cover: None,
+        };
+        write_tags(&path, &update).unwrap();
+        write_rating(&path, 80).unwrap();
+
+        let tag = read_id3v2(&path);
+        assert_eq!(tag.title().as_deref(), Some("New title"));
+        assert_eq!(tag.artist().as_deref(), Some("Artist"));
+        assert_eq!((tag.track(), tag.track_total()), (Some(3), Some(12)));
+        assert!((&tag).into_iter().any(|frame| matches!(
+            frame,
+            Frame::Private(private) if private.owner == "WM/MediaClassPrimaryID" && private.private_data == [1, 2, 3, 4]
+        )));
+        assert_eq!(tag.get_user_text("MusicMagic Fingerprint"), Some("abc"));
+        assert_eq!(tag.get_user_text("FMPS_Rating"), Some("0.8"));
+        assert_eq!(
+            popm_ratings(&tag),
+            vec![
+                ("Windows Media Player 9 Series".to_string(), 64),
+                (String::new(), 196)
+            ]
+        );
+
+        // Removing our rating leaves the other player's
+        write_rating(&path, 0).unwrap();
+        let tag = read_id3v2(&path);
+        assert_eq!(tag.get_user_text("FMPS_Rating"), None);
+        assert_eq!(
+            popm_ratings(&tag),
+            vec![("Windows Media Player 9 Series".to_string(), 64)]
+        );
+
+        let _ = fs::remove_file(&path);
+    }
+}
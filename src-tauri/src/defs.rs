@@ -1,4 +1,5 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::sync::mpsc::Sender;
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Track {
@@ -12,6 +13,11 @@ pub struct Track {
     pub disc_number: i32,
     pub track_number: i32,
     //cover: String, // base64 encoded jpg/png?
+    pub plays: i64,
+    pub skips: i64,
+    pub last_played: i64, // unix time in seconds, 0 if never played until the end
+    pub rating: i64,      // 0-100, 20 per star, 0 if unrated
+    pub loved: i64,       // 1 loved, -1 disliked, 0 neither
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -50,12 +56,88 @@ pub struct DBTrack {
     pub location: String,
     pub file_folder_count: i64,
     pub library_folder_count: i64,
+    pub replaygain: ReplayGain,
+    pub gapless: Option<Gapless>, // None until the file was probed, e.g. after an iTunes import
+    pub rating: i64,              // 0-100, 20 per star
+    pub loved: i64,               // 1 loved, -1 disliked
+    pub plays: i64,
+    pub skips: i64,
+    pub play_date: String, // ISO 8601 in UTC as in the iTunes library, stored as unix time
+    pub skip_date: String,
+    pub comments: String,
+    pub grouping: String,
+    pub bpm: i64,
+    pub volume_adjustment: i64, // -255 to 255
+    pub start_time: i64,        // ms, 0 if unset
+    pub stop_time: i64,         // ms, 0 if unset
+    pub compilation: i64,
+    pub lyrics: String,
+    pub isrc: String,
+    pub label: String,
+    pub original_date: String,
+    pub musicbrainz_track_id: String,
+    pub musicbrainz_album_id: String,
+    pub musicbrainz_artist_id: String,
+    pub musicbrainz_album_artist_id: String,
+    pub musicbrainz_release_group_id: String,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct AudioTrack {
     pub id: i64,
+    pub album_id: i64,
     pub location: String,
+    pub plays: i64,
+    pub rating: i64, // 0-100, 0 if unrated
+    pub replaygain: ReplayGain,
+    pub gapless: Gapless,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ReplayGain {
+    pub track_gain: Option<f64>, // in dB
+    pub track_peak: Option<f64>, // linear, 1.0 is full scale
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+// Read from the file when the track is added, all 0 if the file has no gapless info
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Gapless {
+    pub encoder_delay: i64,   // frames of silence before the audio
+    pub encoder_padding: i64, // frames of silence after the audio
+    pub frame_count: i64,     // including delay and padding
+    pub sample_rate: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum ReplayGainMode {
+    #[default]
+    Off,
+    Track,
+    Album, // falls back to track gain if a track has no album gain
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum PlaylistFormat {
+    M3u8, // extended M3U in UTF-8
+    Pls,
+    Xspf,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum PlaylistPaths {
+    #[default]
+    Absolute,
+    RelativeToExport,    // relative to the exported playlist file
+    RelativeToMediaPath, // tracks outside of the media path stay absolute
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum PlaybackBackend {
+    #[default]
+    Vlc,
+    Symphonia, // requires the symphonia-backend feature
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -71,13 +153,9 @@ pub struct Album {
     pub date_added: String,
     pub tracks: Vec<i64>,
     pub cover_id: i64,
-}
-
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct Cover {
-    pub id: i64,
-    pub album_id: i64,
-    pub data: String, // base64 encoded jpg/png/bmp/etc.
+    pub cover_hash: String, // Served by the cover protocol, empty without cover
+    pub rating: i64,        // 0-100, 20 per star, 0 if unrated
+    pub loved: i64,         // 1 loved, -1 disliked, 0 neither
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -129,6 +207,89 @@ pub struct Playlist {
     pub name: String,
     pub description: String,
     pub tracks: Vec<i64>,
+    pub smart_criteria: Option<SmartCriteria>, // None for regular playlists
+    #[serde(default)]
+    pub folder: bool,     // folders hold playlists, not tracks
+    #[serde(default)]
+    pub parent_id: i64,   // playlist_id of the parent folder, 0 at the top level
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SmartCriteria {
+    pub match_all: bool, // true matches all rules, false matches any rule
+    pub rules: Vec<SmartRule>,
+    pub limit: Option<SmartLimit>,
+    pub live_updating: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SmartRule {
+    pub field: SmartField,
+    pub operator: SmartOperator,
+    pub negate: bool,
+    pub value: SmartValue,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum SmartField {
+    Name,
+    Album,
+    Artist,
+    AlbumArtist,
+    Composer,
+    Genre,
+    Kind,
+    BitRate,
+    SampleRate,
+    Year,
+    TrackNumber,
+    DiscNumber,
+    Size,         // bytes
+    Time,         // seconds
+    DateAdded,    // unix timestamp
+    DateModified, // unix timestamp
+    Plays,
+    Rating,           // 0-100, 20 per star
+    AlbumRating,      // 0-100, 20 per star
+    Playlist,         // playlist id (native) or persistent id (iTunes)
+    Unsupported(i64), // iTunes field id without a matching column, ignored when evaluating
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum SmartOperator {
+    Is,
+    Contains,
+    StartsWith,
+    EndsWith,
+    GreaterThan,
+    LessThan,
+    InRange,
+    InTheLast,
+    Unsupported(i64),
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub enum SmartValue {
+    Text(String),
+    Number(i64),
+    Range(i64, i64),
+    Last(i64, i64), // amount, unit in seconds (e.g. 86400 for days)
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SmartLimit {
+    pub unit: SmartLimitUnit,
+    pub value: i64,
+    pub order: Option<Order>, // None selects randomly
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum SmartLimitUnit {
+    Items,
+    Minutes,
+    Hours,
+    MB,
+    GB,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -148,6 +309,7 @@ pub struct DBPlaylist {
     pub date_modified: String,
     pub date_added: String,
     pub tracks: Vec<i64>,
+    pub parent_id: i64, // resolved from parent_persistent_id for imported playlists
 }
 
 impl Default for DBPlaylist {
@@ -168,6 +330,7 @@ impl Default for DBPlaylist {
             date_modified: "".to_string(),
             date_added: "".to_string(),
             tracks: vec![],
+            parent_id: 0,
         };
     }
 }
@@ -179,10 +342,41 @@ pub enum RepeatMode {
     RepeatNone,
 }
 
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum ShuffleMode {
+    #[default]
+    Off,
+    Tracks,
+    Albums,   // albums in random order, tracks of an album in order
+    Weighted, // often played tracks are more likely to come first
+}
+
+// Older versions stored shuffle as a bool
+fn deserialize_shuffle_mode<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<ShuffleMode, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredShuffleMode {
+        Bool(bool),
+        Mode(ShuffleMode),
+    }
+
+    match StoredShuffleMode::deserialize(deserializer)? {
+        StoredShuffleMode::Bool(true) => return Ok(ShuffleMode::Tracks),
+        StoredShuffleMode::Bool(false) => return Ok(ShuffleMode::Off),
+        StoredShuffleMode::Mode(mode) => return Ok(mode),
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Output {
-    pub id: i64,
+    pub id: i64, // index in the list of outputs of the audio thread, changes with the devices
     pub name: String,
+    #[serde(default)]
+    pub module: String, // libvlc audio output module, e.g. "pulse"
+    #[serde(default)]
+    pub device: String, // device of the module, empty for the default device
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -198,6 +392,10 @@ pub enum Order {
     ByGenre,
     BySize,
     ByTime,
+    ByLastPlayed,
+    ByPlays,
+    BySkips,
+    ByRating,
     ByNameInverse,
     ByReleaseDateInverse,
     ByAddedDateInverse,
@@ -209,6 +407,10 @@ pub enum Order {
     ByGenreInverse,
     BySizeInverse,
     ByTimeInverse,
+    ByLastPlayedInverse,
+    ByPlaysInverse,
+    BySkipsInverse,
+    ByRatingInverse,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
@@ -223,6 +425,33 @@ pub enum DataType {
     Playlist,
 }
 
+// Where a track was played from, e.g. an album or playlist
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct PlaySource {
+    pub datatype: DataType,
+    pub id: i64,
+}
+
+// A listen of a track, written to the PlayHistory table by the audio backend
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PlayedTrack {
+    pub track_id: i64,
+    pub started_at: i64,        // unix time in seconds
+    pub duration_listened: i64, // in ms
+    pub completed: bool,        // played until the end
+    pub skipped: bool,          // Next before the skip threshold was listened to
+    pub source: Option<PlaySource>,
+}
+
+// Listens of a track, album or artist within a date range
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PlayStats {
+    pub id: i64,
+    pub plays: i64, // completed listens
+    pub skips: i64,
+    pub duration_listened: i64, // in ms
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum AudioRequest {
     // Playback Events
@@ -238,8 +467,12 @@ pub enum AudioRequest {
     Volume(i32),
     Output(i32), // uses the output id
     Seek(i64),
-    Shuffle(bool),
+    Shuffle(ShuffleMode),
     Repeat(RepeatMode), // no repeat, repeat one, repeat queue
+    Crossfade(i64),     // crossfade duration in ms, 0 disables crossfade
+    ReplayGain(ReplayGainMode),
+    #[serde(skip)]
+    Checkpoint(Sender<()>), // Write the audio backend state, e.g. before exit. Not available to the frontend
     Init,
 }
 
@@ -252,15 +485,18 @@ pub enum DBRequest {
     // Playlist
     NewPlaylist(Playlist), // Save current playlist with name and array of track IDs
     UpdatePlaylist(Playlist),
+    NewPlaylistFolder(String, i64), // name, parent folder id (0 for top level)
+    MovePlaylists(Vec<i64>, i64),   // playlists or folders, new parent folder id (0 for top level)
+    RenamePlaylistFolder(i64, String), // folder id, name
+    ExportPlaylist(i64, PlaylistFormat, String), // playlist or folder id, format, file path
+    ExportLibrary(String),          // library.xml file path
     // Files
     AddToLibrary(Vec<String>), // Add file(s)/directory to library
     ImportLibrary(String),
     UpdateTrackLocations(String, String),
     DeleteById(DataType, Vec<i64>, bool), // Delete from db, delete files if allowed
-    // EditMetadata(String, HashMap<String, String>), // e.g., { "artist": "Queen" }
     // Data
     GetDataOrder(DataType, Option<Vec<Order>>), // type, order (default alphabetical)
-    GetCoversById(Vec<i64>),
     GetTrackPaths(Vec<i64>),
     Search(String, Option<Vec<DataType>>, Option<i64>),
     UpdateTracks(Vec<Track>, Vec<String>, Vec<String>, Vec<String>),
@@ -274,13 +510,22 @@ pub enum DBRequest {
     UpdateConfig(ConfigState), // Only to be called from a configrequest after updating config.json file
     OpenContainingDir(DataType, i64),
     CopyNotCopied,
-    ListenedToTrack(i64),
+    ListenedToTrack(PlayedTrack),
+    SetRating(DataType, Vec<i64>, i64), // tracks or albums, 0-100 with 20 per star, 0 removes the rating
+    SetLoved(DataType, Vec<i64>, i64),  // tracks or albums, 1 loved, -1 disliked, 0 neither
+    // Stats, from and to are unix time in seconds
+    GetTopTracks(i64, i64, i64), // from, to, limit
+    GetTopAlbums(i64, i64, i64),
+    GetTopArtists(i64, i64, i64),
+    GetRecentlyPlayed(i64), // limit
+    RefreshSmartPlaylists,  // Re-evaluate live updating smart playlists, e.g. for date rules
+    ScanLoudness,           // Measure loudness of tracks without ReplayGain in the background
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum DBData {
-    Play(Vec<AudioTrack>), // array of filepaths, overwrites current queue
-    QueueInsert(Vec<AudioTrack>, Option<usize>), // insert filepaths. defaults to append if no index is given
+    Play(Vec<AudioTrack>, Option<PlaySource>), // array of filepaths, overwrites current queue
+    QueueInsert(Vec<AudioTrack>, Option<usize>, Option<PlaySource>), // insert filepaths. defaults to append if no index is given
     AudioBackendRecover(Option<Vec<AudioTrack>>, Option<Vec<AudioTrack>>),
 }
 
@@ -305,8 +550,9 @@ pub struct AudioState {
     pub is_muted: Option<bool>,
     pub volume: Option<i32>,
     pub output: Option<Output>,
+    pub outputs: Option<Vec<Output>>,
     pub position: Option<i64>, // Current playback position (ms or s?)
-    pub shuffle_mode: Option<bool>,
+    pub shuffle_mode: Option<ShuffleMode>,
     pub repeat_mode: Option<RepeatMode>,
     pub current_track: Option<i64>,
     pub queue: Option<Vec<i64>>,
@@ -317,12 +563,17 @@ pub struct AudioState {
 pub struct AudioBackendState {
     pub volume: i32,
     pub position: i64,
-    pub shuffle_mode: bool,
+    #[serde(deserialize_with = "deserialize_shuffle_mode")]
+    pub shuffle_mode: ShuffleMode,
     pub repeat_mode: RepeatMode,
     pub current_id: i64,
     pub current_location: String,
     pub queue: Vec<i64>,
     pub history: Vec<i64>,
+    #[serde(default)]
+    pub output: Option<Output>,
+    #[serde(default)]
+    pub unshuffled_queue: Vec<i64>, // order of the queue before shuffling
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -338,6 +589,18 @@ pub struct ConfigState {
     pub allow_delete_from_db: bool,
     pub allow_delete_files: bool,
     pub is_new: bool,
+    #[serde(default)]
+    pub crossfade_ms: i64, // 0 disables crossfade
+    #[serde(default)]
+    pub replaygain_mode: ReplayGainMode,
+    #[serde(default)]
+    pub playback_backend: PlaybackBackend, // only applied on startup
+    #[serde(default)]
+    pub playlist_export_paths: PlaylistPaths,
+    #[serde(default)]
+    pub write_tags: bool, // write edited metadata into the files
+    #[serde(default)]
+    pub write_ratings: bool, // write ratings into the files as POPM or FMPS_RATING
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -430,12 +693,24 @@ pub struct BackendMessage {
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum Notification {
-    LibraryImport,
+    LibraryImport(ImportReport),
     None,
 }
 
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ImportReport {
+    pub tracks_imported: i64,
+    pub playlists_imported: i64,
+    pub skipped: Vec<(String, String)>, // track id or playlist name, reason
+    pub missing_files: Vec<String>,
+    pub duplicate_persistent_ids: Vec<String>,
+    pub error: Option<String>, // import stopped early, e.g. because of broken XML
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum Warning {
+    UnresolvedPlaylistEntries(String, Vec<String>), // playlist name, entries without a track
+    TagWriteFailed(Vec<(String, String)>),          // file path, reason
     None,
 }
 
@@ -454,6 +729,7 @@ pub enum ProgressInfo {
     Delete,
     UpdateTracks,
     UpdateAlbum,
+    LoudnessScan,
     None,
 }
 
@@ -478,7 +754,6 @@ pub struct Data {
     pub artist_tracks: Option<Vec<ArtistTracks>>,
     pub composers: Option<Vec<Composer>>,
     pub composer_tracks: Option<Vec<ComposerTracks>>,
-    pub covers: Option<Vec<Cover>>,
     pub genres: Option<Vec<Genre>>,
     pub genre_tracks: Option<Vec<GenreTracks>>,
     pub playlists: Option<Vec<Playlist>>,
@@ -493,6 +768,10 @@ pub struct Data {
     pub error: Option<String>,
     pub loading: Option<Loading>,
     pub is_init: Option<bool>,
+    pub top_tracks: Option<Vec<PlayStats>>,
+    pub top_albums: Option<Vec<PlayStats>>,
+    pub top_artists: Option<Vec<PlayStats>>,
+    pub recently_played: Option<Vec<PlayedTrack>>,
     //pub selectedTracks: Vec<usize>,
 }
 
@@ -534,9 +813,27 @@ pub struct Meta {
     pub bit_rate: Option<i64>,
     pub sample_rate: Option<i64>,
     pub release_date: Option<String>,
-    // normalization: not really available... tags[15-18] have values, but not sure if useful, also so far no use for normalization value.,
+    pub replaygain: ReplayGain, // from REPLAYGAIN_* tags, iTunNORM as fallback for the track gain
+    pub gapless: Option<Gapless>,
+    pub rating: Option<i64>, // 0-100, from POPM or FMPS_RATING
+    pub sort_name: Option<String>,
+    pub sort_artist: Option<String>,
+    pub sort_album_artist: Option<String>,
+    pub sort_album: Option<String>,
+    pub compilation: Option<bool>,
+    pub bpm: Option<i64>,
+    pub lyrics: Option<String>,
+    pub comment: Option<String>,
+    pub grouping: Option<String>,
+    pub musicbrainz_track_id: Option<String>, // recording id
+    pub musicbrainz_album_id: Option<String>,
+    pub musicbrainz_artist_id: Option<String>,
+    pub musicbrainz_album_artist_id: Option<String>,
+    pub musicbrainz_release_group_id: Option<String>,
+    pub isrc: Option<String>,
+    pub label: Option<String>,
+    pub original_date: Option<String>,
     // artwork_count: -1, although theoretically I could use Visuals [01] FrontCover,
-    // sort_name: not available, also not really used,
     // persistent_id: not available, can calculate hash with salt of current time to prevent collision or pass some uuid,
     // track_type: "File", aka useless,
     // purchased: NULL,
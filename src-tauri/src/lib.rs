@@ -3,15 +3,21 @@ mod config;
 mod db;
 mod defs;
 
+#[cfg(feature = "vlc")]
 extern crate vlc;
 use crate::audio::create_player_instance;
 use crate::config::{create_config, get_config, load_or_setup_config_path, set_config};
+use crate::db::cover_store::{covers_dir, read_cover_request};
 use crate::db::create_sqlite_instance;
 use crate::defs::{AudioRequest, ConfigRequest, DBData, DBRequest};
 use log::{debug, error};
+use std::path::Path;
 use std::sync::Mutex;
 use std::sync::{mpsc, mpsc::Sender};
-use tauri::{AppHandle, Emitter};
+use std::thread;
+use std::time::Duration;
+use tauri::http::{header, Response, StatusCode};
+use tauri::{AppHandle, Emitter, Manager, RunEvent, UriSchemeResponder, WindowEvent};
 
 #[tauri::command]
 fn audiorequest(audio_sender: tauri::State<Mutex<Sender<AudioRequest>>>, request: AudioRequest) {
@@ -56,6 +62,7 @@ fn dbrequest(db_sender: tauri::State<Mutex<Sender<DBRequest>>>, request: DBReque
 #[tauri::command]
 fn configrequest(
     app: AppHandle,
+    audio_sender: tauri::State<Mutex<Sender<AudioRequest>>>,
     db_sender: tauri::State<Mutex<Sender<DBRequest>>>,
     request: ConfigRequest,
 ) {
@@ -99,6 +106,26 @@ fn configrequest(
                                 // TODO: send error to frontend?
                             }
                         }
+                        match audio_sender.lock() {
+                            Ok(audio_lock) => {
+                                for audio_request in [
+                                    AudioRequest::Crossfade(new_config.crossfade_ms),
+                                    AudioRequest::ReplayGain(new_config.replaygain_mode.clone()),
+                                ] {
+                                    match audio_lock.send(audio_request) {
+                                        Ok(()) => {}
+                                        Err(error) => {
+                                            error!("{}", error);
+                                            // TODO: send error to frontend?
+                                        }
+                                    }
+                                }
+                            }
+                            Err(error) => {
+                                error!("{}", error);
+                                // TODO: send error to frontend?
+                            }
+                        }
                         match app.emit("config_state", new_config) {
                             Ok(()) => {}
                             Err(error) => {
@@ -123,6 +150,57 @@ fn configrequest(
     }
 }
 
+// Let the audio thread store the playback position and wait until it is written
+fn checkpoint_audio_backend_state(app: &AppHandle) {
+    let (checkpoint_sender, checkpoint_receiver) = mpsc::channel::<()>();
+    let sent = match app.state::<Mutex<Sender<AudioRequest>>>().lock() {
+        Ok(audio_lock) => match audio_lock.send(AudioRequest::Checkpoint(checkpoint_sender)) {
+            Ok(()) => true,
+            Err(error) => {
+                error!("{}", error);
+                false
+            }
+        },
+        Err(error) => {
+            error!("{}", error);
+            false
+        }
+    };
+
+    if sent {
+        match checkpoint_receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(()) => {}
+            Err(error) => {
+                error!("{}", error);
+            }
+        }
+    }
+}
+
+// Answers the cover protocol, e.g. cover://localhost/<hash>/256 (http://cover.localhost/... on Windows)
+fn respond_cover(covers_dir: &Path, path: &str, responder: UriSchemeResponder) {
+    let response = match read_cover_request(covers_dir, path) {
+        Ok((media_type, data)) => Response::builder()
+            .header(header::CONTENT_TYPE, media_type)
+            // Content-addressed, the image behind a url never changes
+            .header(header::CACHE_CONTROL, "max-age=31536000, immutable")
+            .body(data),
+        Err(error) => {
+            error!("{}", error);
+            Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Vec::new())
+        }
+    };
+
+    match response {
+        Ok(response) => responder.respond(response),
+        Err(error) => {
+            error!("{}", error);
+        }
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let tauri_logger = if tauri::is_dev() {
@@ -160,6 +238,25 @@ pub fn run() {
         .plugin(tauri_plugin_os::init())
         .manage(Mutex::new(audio_sender.clone()))
         .manage(Mutex::new(db_sender))
+        .register_asynchronous_uri_scheme_protocol("cover", |ctx, request, responder| {
+            let path = request.uri().path().to_string();
+            match ctx.app_handle().path().app_local_data_dir() {
+                Ok(data_path) => {
+                    // Creating thumbnails takes too long for the webview thread
+                    let covers_dir = covers_dir(&data_path);
+                    thread::spawn(move || respond_cover(&covers_dir, &path, responder));
+                }
+                Err(error) => {
+                    error!("{}", error);
+                    if let Ok(response) = Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Vec::new())
+                    {
+                        responder.respond(response);
+                    }
+                }
+            }
+        })
         .setup(|app| {
             match load_or_setup_config_path(app.handle().clone()) {
                 Ok(config_path) => {
@@ -169,13 +266,14 @@ pub fn run() {
                                 app.handle().clone(),
                                 db_receiver,
                                 data_sender,
-                                config_state,
+                                config_state.clone(),
                             );
                             create_player_instance(
                                 app.handle().clone(),
                                 audio_receiver,
                                 data_receiver,
                                 db_sender_audio,
+                                config_state,
                             );
                         }
                         Err(error) => {
@@ -189,6 +287,7 @@ pub fn run() {
                                         audio_receiver,
                                         data_receiver,
                                         db_sender_audio,
+                                        config_state.clone(),
                                     );
                                     create_sqlite_instance(
                                         app.handle().clone(),
@@ -217,6 +316,17 @@ pub fn run() {
             audiorequest,
             dbrequest
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| match event {
+            // Otherwise the position since the last checkpoint is lost
+            RunEvent::WindowEvent {
+                event: WindowEvent::CloseRequested { .. },
+                ..
+            }
+            | RunEvent::ExitRequested { .. } => {
+                checkpoint_audio_backend_state(app);
+            }
+            _ => {}
+        });
 }
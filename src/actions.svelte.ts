@@ -1,8 +1,10 @@
-import { invoke } from "@tauri-apps/api/core";
+import { convertFileSrc, invoke } from "@tauri-apps/api/core";
 import {
   ActiveView,
   DataType,
   Order,
+  PlaylistFormat,
+  ShuffleMode,
   Theme,
   type Album,
   type Artist,
@@ -26,7 +28,6 @@ import {
   mediaPathModalState,
   playlistSelectModalState,
   queueModalState,
-  requested_covers,
   searchModalState,
   settingsModalState,
   viewState,
@@ -42,7 +43,7 @@ export function play_by_id(id: number, type: DataType) {
 }
 
 export function play_random_by_id(id: number, type: DataType) {
-  invoke("audiorequest", { request: { Shuffle: true } });
+  invoke("audiorequest", { request: { Shuffle: ShuffleMode.Tracks } });
   invoke("dbrequest", {
     request: {
       Play: [type, [id], null],
@@ -59,7 +60,7 @@ export function play_by_ids(ids: [number], type: DataType) {
 }
 
 export function play_random_by_ids(ids: [number], type: DataType) {
-  invoke("audiorequest", { request: { Shuffle: true } });
+  invoke("audiorequest", { request: { Shuffle: ShuffleMode.Tracks } });
   invoke("dbrequest", {
     request: {
       Play: [type, ids, null],
@@ -110,6 +111,22 @@ export function update_album(
   });
 }
 
+export function set_rating(type: DataType, ids: number[], rating: number) {
+  invoke("dbrequest", {
+    request: {
+      SetRating: [type, ids, rating],
+    },
+  });
+}
+
+export function set_loved(type: DataType, ids: number[], loved: number) {
+  invoke("dbrequest", {
+    request: {
+      SetLoved: [type, ids, loved],
+    },
+  });
+}
+
 export function update_artist(artist: Artist) {
   invoke("dbrequest", {
     request: {
@@ -150,6 +167,88 @@ export function create_playlist(playlist: Playlist) {
   });
 }
 
+export function create_playlist_folder(name: string, parent_id: number) {
+  invoke("dbrequest", {
+    request: {
+      NewPlaylistFolder: [name, parent_id],
+    },
+  });
+}
+
+export function move_playlists(ids: number[], parent_id: number) {
+  invoke("dbrequest", {
+    request: {
+      MovePlaylists: [ids, parent_id],
+    },
+  });
+}
+
+export function rename_playlist_folder(id: number, name: string) {
+  invoke("dbrequest", {
+    request: {
+      RenamePlaylistFolder: [id, name],
+    },
+  });
+}
+
+export function export_playlist(
+  id: number,
+  format: PlaylistFormat,
+  path: string
+) {
+  invoke("dbrequest", {
+    request: {
+      ExportPlaylist: [id, format, path],
+    },
+  });
+}
+
+export function export_library(path: string) {
+  invoke("dbrequest", {
+    request: {
+      ExportLibrary: path,
+    },
+  });
+}
+
+// Orders the playlist ids depth first, children follow their folder in the given order
+export function playlists_as_tree(ids: number[]): number[] {
+  let tree: number[] = [];
+
+  function add_children(parent_id: number) {
+    ids.forEach((id) => {
+      let playlist = data.playlists.get(id);
+      if (playlist && playlist.parent_id === parent_id && !tree.includes(id)) {
+        tree.push(id);
+        if (playlist.folder) {
+          add_children(id);
+        }
+      }
+    });
+  }
+
+  add_children(0);
+
+  // Playlists whose parent is missing are shown at the top level
+  ids.forEach((id) => {
+    if (!tree.includes(id)) {
+      tree.push(id);
+    }
+  });
+
+  return tree;
+}
+
+export function playlist_depth(id: number): number {
+  let depth = 0;
+  let playlist = data.playlists.get(id);
+  while (playlist && playlist.parent_id !== 0 && depth < 32) {
+    depth++;
+    playlist = data.playlists.get(playlist.parent_id);
+  }
+  return depth;
+}
+
 export function update_queue(queue: number[]) {
   invoke("audiorequest", {
     request: {
@@ -158,20 +257,12 @@ export function update_queue(queue: number[]) {
   });
 }
 
-export function get_cover(cover_id: number) {
-  // TODO: create timeout before calling backend to load multiple covers at once?
-  let cover = data.covers.get(cover_id);
-
-  if (cover) {
-    return cover;
-  } else if (cover_id !== 0 && !requested_covers.includes(cover_id)) {
-    requested_covers.push(cover_id);
-    invoke("dbrequest", {
-      request: {
-        GetCoversById: [cover_id],
-      },
-    });
+// Thumbnail of the cover from the backend cover protocol, sizes are 128, 256 and 512 px
+export function cover_src(album: Album | undefined, size: number) {
+  if (album && album.cover_hash) {
+    return convertFileSrc(`${album.cover_hash}/${size}`, "cover");
   }
+  return undefined;
 }
 
 export function reset_custom_colors_light() {
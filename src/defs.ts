@@ -8,6 +8,11 @@ export interface Track {
   total_time: number; // (ms to s?)
   disc_number: number;
   track_number: number;
+  plays: number;
+  skips: number;
+  last_played: number; // unix time in seconds, 0 if never played until the end
+  rating: number; // 0-100, 20 per star, 0 if unrated
+  loved: number; // 1 loved, -1 disliked, 0 neither
   //cover: string, // base64 encoded jpg/png?
 }
 
@@ -23,12 +28,9 @@ export interface Album {
   date_added: string;
   tracks: number[];
   cover_id: number;
-}
-
-export interface Cover {
-  id: number;
-  album_id: number;
-  data: string; // base64 encoded jpg/png
+  cover_hash: string; // served by the cover protocol, empty without cover
+  rating: number; // 0-100, 20 per star, 0 if unrated
+  loved: number; // 1 loved, -1 disliked, 0 neither
 }
 
 export interface Artist {
@@ -72,6 +74,20 @@ export interface Playlist {
   name: string;
   description: string;
   tracks: number[]; // list of track IDs
+  folder: boolean; // folders hold playlists, not tracks
+  parent_id: number; // id of the parent folder, 0 at the top level
+}
+
+export enum PlaylistFormat {
+  M3u8 = "M3u8",
+  Pls = "Pls",
+  Xspf = "Xspf",
+}
+
+export enum PlaylistPaths {
+  Absolute = "Absolute",
+  RelativeToExport = "RelativeToExport", // relative to the exported playlist file
+  RelativeToMediaPath = "RelativeToMediaPath", // tracks outside of the media path stay absolute
 }
 
 export enum RepeatMode {
@@ -80,6 +96,24 @@ export enum RepeatMode {
   RepeatNone = "RepeatNone",
 }
 
+export enum ShuffleMode {
+  Off = "Off",
+  Tracks = "Tracks",
+  Albums = "Albums", // albums in random order, tracks of an album in order
+  Weighted = "Weighted", // often played tracks are more likely to come first
+}
+
+export enum ReplayGainMode {
+  Off = "Off",
+  Track = "Track",
+  Album = "Album", // falls back to track gain if a track has no album gain
+}
+
+export enum PlaybackBackend {
+  Vlc = "Vlc",
+  Symphonia = "Symphonia", // requires the symphonia-backend feature
+}
+
 export interface Output {
   id: number;
   name: string;
@@ -97,6 +131,10 @@ export enum Order {
   ByGenre = "ByGenre",
   BySize = "BySize",
   ByTime = "ByTime",
+  ByLastPlayed = "ByLastPlayed",
+  ByPlays = "ByPlays",
+  BySkips = "BySkips",
+  ByRating = "ByRating",
   ByNameInverse = "ByNameInverse",
   ByReleaseDateInverse = "ByReleaseDateInverse",
   ByAddedDateInverse = "ByAddedDateInverse",
@@ -108,6 +146,10 @@ export enum Order {
   ByGenreInverse = "ByGenreInverse",
   BySizeInverse = "BySizeInverse",
   ByTimeInverse = "ByTimeInverse",
+  ByLastPlayedInverse = "ByLastPlayedInverse",
+  ByPlaysInverse = "ByPlaysInverse",
+  BySkipsInverse = "BySkipsInverse",
+  ByRatingInverse = "ByRatingInverse",
 }
 
 export enum DataType {
@@ -149,7 +191,7 @@ export interface AudioState {
   volume: number | null;
   output: Output | null;
   position: number | null; // Current playback position (ms)
-  shuffle_mode: boolean | null;
+  shuffle_mode: ShuffleMode | null;
   repeat_mode: RepeatMode | null;
   current_track: number | null;
   queue: number[] | null;
@@ -168,6 +210,12 @@ export interface ConfigState {
   allow_delete_from_db: boolean;
   allow_delete_files: boolean;
   is_new: boolean;
+  crossfade_ms: number; // 0 disables crossfade
+  replaygain_mode: ReplayGainMode;
+  playback_backend: PlaybackBackend; // only applied on startup
+  playlist_export_paths: PlaylistPaths;
+  write_tags: boolean;
+  write_ratings: boolean;
 }
 
 export interface Version {
@@ -362,6 +410,10 @@ export interface Translation {
     managefolders: string;
     managefolders_explain_active: string;
     managefolders_explain_inactive: string;
+    writetags: string;
+    writetags_explain: string;
+    writeratings: string;
+    writeratings_explain: string;
     copynotcopiedmedia: string;
     allowdelete: string;
     allowdelete_active: string;
@@ -379,6 +431,25 @@ export interface Translation {
     extractmetadata_explain: string;
     support_please: string;
     updatesarehere: string;
+    crossfade: string;
+    crossfade_off: string;
+    crossfade_explain: string;
+    replaygain: {
+      replaygain: string;
+      off: string;
+      track: string;
+      album: string;
+      explain: string;
+    };
+    playbackbackend: string;
+    playbackbackend_explain: string;
+    playlistpaths: {
+      playlistpaths: string;
+      absolute: string;
+      relativetoexport: string;
+      relativetomediapath: string;
+      explain: string;
+    };
   };
   setup: {
     setup: string;
@@ -452,6 +523,16 @@ export interface Translation {
       first: string;
       second: string;
       third: string;
+      tracks: string;
+      playlists: string;
+      skipped: string;
+      missing: string;
+      duplicates: string;
+      failed: string;
+    };
+    warning: {
+      unresolvedplaylistentries: string;
+      tagwritefailed: string;
     };
   };
   emptyview: {
@@ -484,15 +565,22 @@ export interface BackendMessage {
   progress: Progress | null;
 }
 
-export enum Notification {
-  LibraryImport = "LibraryImport",
-  None = "None",
-}
+export type Notification = { LibraryImport: ImportReport } | "None";
 
-export enum Warning {
-  None = "None",
+export interface ImportReport {
+  tracks_imported: number;
+  playlists_imported: number;
+  skipped: [string, string][]; // track id or playlist name, reason
+  missing_files: string[];
+  duplicate_persistent_ids: string[];
+  error: string | null;
 }
 
+export type Warning =
+  | { UnresolvedPlaylistEntries: [string, string[]] } // playlist name, entries without a track
+  | { TagWriteFailed: [string, string][] } // file path, reason
+  | "None";
+
 export interface Progress {
   info: ProgressInfo;
   value: number | null;
@@ -523,6 +611,27 @@ export interface SpaceTime {
   time: number | null;
 }
 
+export interface PlaySource {
+  datatype: DataType;
+  id: number;
+}
+
+export interface PlayedTrack {
+  track_id: number;
+  started_at: number; // unix time in seconds
+  duration_listened: number; // in ms
+  completed: boolean;
+  skipped: boolean;
+  source: PlaySource | null;
+}
+
+export interface PlayStats {
+  id: number;
+  plays: number;
+  skips: number;
+  duration_listened: number; // in ms
+}
+
 export interface Data {
   queue: number[] | null;
   search: Search | null;
@@ -533,7 +642,6 @@ export interface Data {
   artist_tracks: ArtistTracks[] | null;
   composers: Composer[] | null;
   composer_tracks: ComposerTracks[] | null;
-  covers: Cover[] | null;
   genres: Genre[] | null;
   genre_tracks: GenreTracks[] | null;
   playlists: Playlist[] | null;
@@ -545,6 +653,10 @@ export interface Data {
   genres_order: [Order[], number[]] | null;
   playlists_order: [Order[], number[]] | null;
   tracks_order: [Order[], number[]] | null;
+  top_tracks: PlayStats[] | null;
+  top_albums: PlayStats[] | null;
+  top_artists: PlayStats[] | null;
+  recently_played: PlayedTrack[] | null;
 }
 
 export interface AppState {
@@ -155,6 +155,10 @@ export const translations: Translation = $state({
     managefolders: "",
     managefolders_explain_active: "",
     managefolders_explain_inactive: "",
+    writetags: "",
+    writetags_explain: "",
+    writeratings: "",
+    writeratings_explain: "",
     copynotcopiedmedia: "",
     allowdelete: "",
     allowdelete_active: "",
@@ -172,6 +176,25 @@ export const translations: Translation = $state({
     extractmetadata_explain: "",
     support_please: "",
     updatesarehere: "",
+    crossfade: "",
+    crossfade_off: "",
+    crossfade_explain: "",
+    replaygain: {
+      replaygain: "",
+      off: "",
+      track: "",
+      album: "",
+      explain: "",
+    },
+    playbackbackend: "",
+    playbackbackend_explain: "",
+    playlistpaths: {
+      playlistpaths: "",
+      absolute: "",
+      relativetoexport: "",
+      relativetomediapath: "",
+      explain: "",
+    },
   },
   setup: {
     setup: "",
@@ -245,6 +268,16 @@ export const translations: Translation = $state({
       first: "",
       second: "",
       third: "",
+      tracks: "",
+      playlists: "",
+      skipped: "",
+      missing: "",
+      duplicates: "",
+      failed: "",
+    },
+    warning: {
+      unresolvedplaylistentries: "",
+      tagwritefailed: "",
     },
   },
   emptyview: {
@@ -408,6 +441,10 @@ function fillTranslations(err: any, t: TFunction<"translation", undefined>) {
     managefolders_explain_inactive: t(
       "settings.managefolders_explain_inactive"
     ),
+    writetags: t("settings.writetags"),
+    writetags_explain: t("settings.writetags_explain"),
+    writeratings: t("settings.writeratings"),
+    writeratings_explain: t("settings.writeratings_explain"),
     copynotcopiedmedia: t("settings.copynotcopiedmedia"),
     allowdelete: t("settings.allowdelete"),
     allowdelete_active: t("settings.allowdelete_active"),
@@ -425,6 +462,25 @@ function fillTranslations(err: any, t: TFunction<"translation", undefined>) {
     extractmetadata_explain: t("settings.extractmetadata_explain"),
     support_please: t("settings.support_please"),
     updatesarehere: t("settings.updatesarehere"),
+    crossfade: t("settings.crossfade"),
+    crossfade_off: t("settings.crossfade_off"),
+    crossfade_explain: t("settings.crossfade_explain"),
+    replaygain: {
+      replaygain: t("settings.replaygain.replaygain"),
+      off: t("settings.replaygain.off"),
+      track: t("settings.replaygain.track"),
+      album: t("settings.replaygain.album"),
+      explain: t("settings.replaygain.explain"),
+    },
+    playbackbackend: t("settings.playbackbackend"),
+    playbackbackend_explain: t("settings.playbackbackend_explain"),
+    playlistpaths: {
+      playlistpaths: t("settings.playlistpaths.playlistpaths"),
+      absolute: t("settings.playlistpaths.absolute"),
+      relativetoexport: t("settings.playlistpaths.relativetoexport"),
+      relativetomediapath: t("settings.playlistpaths.relativetomediapath"),
+      explain: t("settings.playlistpaths.explain"),
+    },
   };
   translations.setup = {
     setup: t("setup.setup"),
@@ -500,6 +556,18 @@ function fillTranslations(err: any, t: TFunction<"translation", undefined>) {
       first: t("notification.libraryimport.first"),
       second: t("notification.libraryimport.second"),
       third: t("notification.libraryimport.third"),
+      tracks: t("notification.libraryimport.tracks"),
+      playlists: t("notification.libraryimport.playlists"),
+      skipped: t("notification.libraryimport.skipped"),
+      missing: t("notification.libraryimport.missing"),
+      duplicates: t("notification.libraryimport.duplicates"),
+      failed: t("notification.libraryimport.failed"),
+    },
+    warning: {
+      unresolvedplaylistentries: t(
+        "notification.warning.unresolvedplaylistentries"
+      ),
+      tagwritefailed: t("notification.warning.tagwritefailed"),
     },
   };
   translations.emptyview = {
@@ -4,6 +4,7 @@ import {
   DataType,
   Order,
   RepeatMode,
+  ShuffleMode,
   type Output,
   type Album,
   type Artist,
@@ -14,7 +15,6 @@ import {
   type SpaceTime,
   type Track,
   type AudioState,
-  type Cover,
   type Search,
   type Composer,
   ActiveView,
@@ -25,9 +25,13 @@ import {
   type Version,
   type ThemeColors,
   type BackendMessage,
-  Notification,
+  type Notification,
+  type Warning,
   ProgressInfo,
   type Progress,
+  ReplayGainMode,
+  PlaybackBackend,
+  PlaylistPaths,
 } from "./defs";
 import { translations } from "./localisation/localisation.svelte";
 
@@ -42,7 +46,6 @@ export const data = $state({
   composers: new Map() as Map<number, Composer>,
   composers_order: { order: [] as Order[], ids: [] as number[] },
   composer_tracks: new Map() as Map<number, number[]>,
-  covers: new Map() as Map<number, Cover>,
   genres: new Map() as Map<number, Genre>,
   genres_order: { order: [] as Order[], ids: [] as number[] },
   genre_tracks: new Map() as Map<number, number[]>,
@@ -72,7 +75,7 @@ export const audio_state = $state({
   volume: 100,
   output: { id: 0, name: "" } as Output,
   position: 0, // Current playback position (ms)
-  shuffle_mode: false,
+  shuffle_mode: ShuffleMode.Off,
   repeat_mode: RepeatMode.RepeatNone,
   current_track: 0,
   queue: [] as number[],
@@ -108,6 +111,12 @@ export const config_state = $state({
   allow_delete_from_db: false,
   allow_delete_files: false,
   is_new: false,
+  crossfade_ms: 0,
+  replaygain_mode: ReplayGainMode.Off,
+  playback_backend: PlaybackBackend.Vlc,
+  playlist_export_paths: PlaylistPaths.Absolute,
+  write_tags: false,
+  write_ratings: false,
 });
 
 export const custom_colors_backup = $state({
@@ -135,7 +144,6 @@ export const app_state = $state({
   new_composers: 0,
   new_genres: 0,
   new_playlists: 0,
-  new_covers: 0,
   new_queue: 0,
   open_split: -1,
   resized: 0,
@@ -258,7 +266,8 @@ export const viewState = $state({
 
 export const notificationState = $state({
   visible: false,
-  notification: Notification.None,
+  notification: "None" as Notification,
+  warnings: [] as Warning[], // collected until the notification is closed
 });
 
 export const progress = $state({
@@ -593,7 +602,37 @@ export function translateStartupView(view: View) {
   }
 }
 
-export let requested_covers: number[] = [];
+export function translatePlaylistPaths(paths: PlaylistPaths) {
+  switch (paths) {
+    case PlaylistPaths.Absolute:
+      return translations.settings.playlistpaths.absolute;
+    case PlaylistPaths.RelativeToExport:
+      return translations.settings.playlistpaths.relativetoexport;
+    case PlaylistPaths.RelativeToMediaPath:
+      return translations.settings.playlistpaths.relativetomediapath;
+  }
+}
+
+export function translatePlaybackBackend(backend: PlaybackBackend) {
+  // Product names, not translated
+  switch (backend) {
+    case PlaybackBackend.Vlc:
+      return "VLC";
+    case PlaybackBackend.Symphonia:
+      return "Symphonia";
+  }
+}
+
+export function translateReplayGainMode(mode: ReplayGainMode) {
+  switch (mode) {
+    case ReplayGainMode.Off:
+      return translations.settings.replaygain.off;
+    case ReplayGainMode.Track:
+      return translations.settings.replaygain.track;
+    case ReplayGainMode.Album:
+      return translations.settings.replaygain.album;
+  }
+}
 
 export function initState() {
   // Initialize listeners
@@ -602,6 +641,10 @@ export function initState() {
     let config = event.payload as ConfigState;
 
     config_state.allow_delete_files = config.allow_delete_files;
+    config_state.crossfade_ms = config.crossfade_ms;
+    config_state.replaygain_mode = config.replaygain_mode;
+    config_state.playback_backend = config.playback_backend;
+    config_state.playlist_export_paths = config.playlist_export_paths;
     config_state.allow_delete_from_db = config.allow_delete_from_db;
     config_state.custom_colors = config.custom_colors;
     config_state.is_new = config.is_new;
@@ -612,6 +655,8 @@ export function initState() {
     config_state.startup_view = config.startup_view;
     config_state.theme = config.theme;
     config_state.version = config.version;
+    config_state.write_ratings = config.write_ratings;
+    config_state.write_tags = config.write_tags;
 
     custom_colors_backup.background = config.custom_colors.background;
     custom_colors_backup.background_active =
@@ -711,25 +756,6 @@ export function initState() {
       app_state.new_composers += 1;
     }
 
-    if (data_msg.covers != null) {
-      // TODO: implement access based discard of covers to keep frontend memory footprint lean
-      let received_covers: number[] = [];
-      data_msg.covers.forEach((cover) => {
-        received_covers.push(cover.id);
-        //requested_covers.push(cover.id);
-        data.covers.set(cover.id, cover);
-      });
-
-      let unreceived_covers = [];
-      for (let i = 0; i < requested_covers.length; i++) {
-        if (!received_covers.includes(requested_covers[i])) {
-          unreceived_covers.push(requested_covers[i]);
-        }
-      }
-      requested_covers = unreceived_covers;
-      app_state.new_covers += 1;
-    }
-
     if (data_msg.genres != null) {
       data_msg.genres.forEach((genre) => {
         data.genres.set(genre.id, genre);
@@ -961,6 +987,12 @@ export function initState() {
       notificationState.visible = true;
     }
 
+    if (message.warning !== null && message.warning !== "None") {
+      console.log(message.warning);
+      notificationState.warnings.push(message.warning);
+      notificationState.visible = true;
+    }
+
     if (message.error !== null) {
       console.log(message.error);
       // TODO: display error